@@ -1,7 +1,7 @@
 use anyhow::Result;
 use clap::Parser;
 use futures::prelude::*;
-use kneron_bbox_server::AsyncServer;
+use kneron_bbox_server::{AsyncServer, Endian, Frame};
 use std::net::SocketAddr;
 
 pub const DEFAULT_ADDR: &str = "0.0.0.0:8700";
@@ -10,6 +10,12 @@ pub const DEFAULT_ADDR: &str = "0.0.0.0:8700";
 struct Opts {
     #[clap(long, help = "Server bind address.")]
     pub addr: Option<SocketAddr>,
+    #[clap(
+        long,
+        default_value = "little",
+        help = "Byte order of received frames."
+    )]
+    pub endian: Endian,
 }
 
 #[async_std::main]
@@ -18,13 +24,22 @@ async fn main() -> Result<()> {
 
     let server = {
         let addr = opts.addr.unwrap_or_else(|| DEFAULT_ADDR.parse().unwrap());
-        AsyncServer::bind(addr).await?
+        AsyncServer::bind(addr).await?.with_endian(opts.endian)
     };
 
     server
         .into_stream()
-        .try_for_each(|result| async move {
+        .for_each(|frame| async move {
+            // Skip invalid frames and keep receiving from other cameras.
+            let Frame { source, result, .. } = match frame {
+                Ok(frame) => frame,
+                Err(err) => {
+                    eprintln!("Drop a frame: {:#}", err);
+                    return;
+                }
+            };
             let box_count = result.box_count();
+            eprintln!("Source : {}", source);
             eprintln!("Result Count : {}\n", box_count);
 
             result.boxes().iter().enumerate().for_each(|(idx, bbox)| {
@@ -33,10 +48,8 @@ async fn main() -> Result<()> {
                     idx, bbox.x1, bbox.y1, bbox.x2, bbox.y2, bbox.score, bbox.class_num,
                 );
             });
-
-            anyhow::Ok(())
         })
-        .await?;
+        .await;
 
     Ok(())
 }
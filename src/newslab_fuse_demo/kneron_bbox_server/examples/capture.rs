@@ -1,6 +1,6 @@
 use anyhow::Result;
 use clap::Parser;
-use kneron_bbox_server::Server;
+use kneron_bbox_server::{Endian, Error, Frame, Server};
 use std::net::SocketAddr;
 
 pub const DEFAULT_ADDR: &str = "0.0.0.0:8700";
@@ -9,19 +9,36 @@ pub const DEFAULT_ADDR: &str = "0.0.0.0:8700";
 struct Opts {
     #[clap(long, help = "Server bind address.")]
     pub addr: Option<SocketAddr>,
+    #[clap(
+        long,
+        default_value = "little",
+        help = "Byte order of received frames."
+    )]
+    pub endian: Endian,
 }
 
 fn main() -> Result<()> {
     let opts = Opts::parse();
 
-    let mut server = {
+    let server = {
         let addr = opts.addr.unwrap_or_else(|| DEFAULT_ADDR.parse().unwrap());
-        Server::bind(addr)?
+        Server::bind(addr)?.with_endian(opts.endian)
     };
 
-    server.try_for_each(|result| {
-        let result = result?;
+    for frame in &server {
+        // Skip invalid frames and keep receiving from other cameras.
+        let Frame { source, result, .. } = match frame {
+            Ok(frame) => frame,
+            Err(err) if matches!(err.downcast_ref::<Error>(), Some(Error::Stopped)) => {
+                return Err(err)
+            }
+            Err(err) => {
+                eprintln!("Drop a frame: {:#}", err);
+                continue;
+            }
+        };
         let box_count = result.box_count();
+        eprintln!("Source : {}", source);
         eprintln!("Result Count : {}\n", box_count);
 
         result.boxes().iter().enumerate().for_each(|(idx, bbox)| {
@@ -30,9 +47,7 @@ fn main() -> Result<()> {
                 idx, bbox.x1, bbox.y1, bbox.x2, bbox.y2, bbox.score, bbox.class_num,
             );
         });
-
-        anyhow::Ok(())
-    })?;
+    }
 
     Ok(())
 }
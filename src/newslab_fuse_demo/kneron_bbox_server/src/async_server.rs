@@ -1,17 +1,49 @@
-use crate::protocol::YoloResult;
-use anyhow::Result;
-use async_std::net::{TcpListener, ToSocketAddrs};
+use crate::{
+    connection::{read_full_async, Backoff, ConnectionGuard},
+    error::Error,
+    frame::{CameraMap, Frame, Source},
+    protocol::{Endian, YoloResult, FRAME_SIZE},
+};
+use anyhow::{ensure, Context, Result};
+use async_std::{
+    channel::{bounded, Receiver, Sender},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    task::{sleep, spawn},
+};
 use futures::prelude::*;
 use log::info;
-use std::mem;
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering::*},
+        Arc, Mutex,
+    },
+    time::SystemTime,
+};
 
 pub const DEFAULT_ADDR: &str = "0.0.0.0:8700";
 
+/// The capacity of the channel between connection tasks and the
+/// receiver.
+const CHANNEL_SIZE: usize = 16;
+
 /// The async/.await server that listens to detection messages from
 /// the Kneron camera.
+///
+/// The server accepts multiple cameras concurrently. Each connection
+/// is kept open and served by a task that reads a stream of frames
+/// until the camera disconnects. The tasks are started on the first
+/// call to [AsyncServer::recv], and the accepting task stops when the
+/// server is dropped.
 #[derive(Debug)]
 pub struct AsyncServer {
-    listener: TcpListener,
+    listener: Arc<TcpListener>,
+    endian: Endian,
+    cameras: CameraMap,
+    connections: Arc<AtomicUsize>,
+    rx: Mutex<Option<Receiver<Result<Frame>>>>,
+    /// Closes `stop_rx` when the server is dropped. Nothing is sent.
+    _stop_tx: Sender<()>,
+    stop_rx: Receiver<()>,
 }
 
 impl AsyncServer {
@@ -26,23 +58,177 @@ impl AsyncServer {
         A: ToSocketAddrs,
     {
         let listener = TcpListener::bind(addrs).await?;
-        Ok(Self { listener })
+        let (stop_tx, stop_rx) = bounded(1);
+        Ok(Self {
+            listener: Arc::new(listener),
+            endian: Endian::default(),
+            cameras: CameraMap::new(),
+            connections: Arc::new(AtomicUsize::new(0)),
+            rx: Mutex::new(None),
+            _stop_tx: stop_tx,
+            stop_rx,
+        })
+    }
+
+    /// Returns the local address that the server binds to.
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Returns the number of connected cameras.
+    pub fn connections(&self) -> usize {
+        self.connections.load(SeqCst)
     }
 
-    pub async fn recv(&self) -> Result<YoloResult> {
-        let (mut stream, addr) = self.listener.accept().await?;
-        info!("Connected from client {}", addr);
+    /// Sets the byte order of received frames. The default is
+    /// little-endian.
+    pub fn with_endian(self, endian: Endian) -> Self {
+        Self { endian, ..self }
+    }
+
+    /// Sets the camera names by camera IP addresses. Frames from the
+    /// listed addresses are tagged with the camera names.
+    pub fn with_cameras(self, cameras: CameraMap) -> Self {
+        Self { cameras, ..self }
+    }
 
-        let mut bytes = [0u8; mem::size_of::<YoloResult>()];
-        stream.read_exact(&mut bytes).await?;
-        let result: YoloResult = unsafe { mem::transmute(bytes) };
-        Ok(result)
+    /// Receives the next frame from any of the connected cameras.
+    pub async fn recv(&self) -> Result<Frame> {
+        let rx = {
+            let mut guard = self.rx.lock().unwrap();
+            guard.get_or_insert_with(|| self.start()).clone()
+        };
+        rx.recv().await.map_err(|_| Error::Stopped)?
     }
 
-    pub fn into_stream(self) -> impl Stream<Item = Result<YoloResult>> + Sync + Send {
-        stream::try_unfold(self, |server| async move {
-            let result = server.recv().await?;
-            anyhow::Ok(Some((result, server)))
+    /// Turns the server into a stream of frames. Errors from any
+    /// camera are yielded as items, and the stream ends only when the
+    /// server is stopped.
+    pub fn into_stream(self) -> impl Stream<Item = Result<Frame>> + Sync + Send {
+        stream::unfold(self, |server| async move {
+            let result = server.recv().await;
+            if let Err(err) = &result {
+                if matches!(err.downcast_ref::<Error>(), Some(Error::Stopped)) {
+                    return None;
+                }
+            }
+            Some((result, server))
         })
     }
+
+    /// Spawns the task that accepts clients.
+    fn start(&self) -> Receiver<Result<Frame>> {
+        let (tx, rx) = bounded(CHANNEL_SIZE);
+        spawn(accept_loop(
+            self.listener.clone(),
+            self.endian,
+            self.cameras.clone(),
+            self.connections.clone(),
+            self.stop_rx.clone(),
+            tx,
+        ));
+        rx
+    }
+}
+
+/// Accepts clients and spawns a task for each connection.
+async fn accept_loop(
+    listener: Arc<TcpListener>,
+    endian: Endian,
+    cameras: CameraMap,
+    connections: Arc<AtomicUsize>,
+    stop_rx: Receiver<()>,
+    tx: Sender<Result<Frame>>,
+) {
+    let mut backoff = Backoff::default();
+
+    loop {
+        // Stop when the server is dropped and the channel is closed.
+        let stopped = stop_rx.recv().map(|_| None);
+        let accepted = listener.accept().map(Some);
+        let (stream, addr) = match future::select(stopped.boxed(), accepted.boxed())
+            .await
+            .factor_first()
+            .0
+        {
+            None => break,
+            Some(Ok(pair)) => {
+                backoff.reset();
+                pair
+            }
+            Some(Err(err)) => {
+                if tx.send(Err(err.into())).await.is_err() {
+                    break;
+                }
+                let stopped = stop_rx.recv().map(|_| true);
+                let slept = sleep(backoff.next_delay()).map(|()| false);
+                if future::select(stopped.boxed(), slept.boxed())
+                    .await
+                    .factor_first()
+                    .0
+                {
+                    break;
+                }
+                continue;
+            }
+        };
+
+        let source = Source::new(addr, &cameras);
+        info!("Connected from client {}", source);
+
+        let guard = ConnectionGuard::new(connections.clone());
+        spawn(connection_loop(stream, source, endian, guard, tx.clone()));
+    }
+}
+
+/// Reads frames from a connection until the client disconnects.
+async fn connection_loop(
+    mut stream: TcpStream,
+    source: Source,
+    endian: Endian,
+    _guard: ConnectionGuard,
+    tx: Sender<Result<Frame>>,
+) {
+    loop {
+        let mut bytes = [0u8; FRAME_SIZE];
+
+        // EOF is only expected between frames.
+        let received = read_full_async(&mut stream, &mut bytes)
+            .await
+            .map_err(anyhow::Error::from)
+            .and_then(|received| {
+                ensure!(
+                    received == 0 || received == FRAME_SIZE,
+                    Error::TruncatedFrame {
+                        expected: FRAME_SIZE,
+                        received,
+                    }
+                );
+                Ok(received)
+            });
+
+        let time = SystemTime::now();
+        let item = match received {
+            Ok(0) => {
+                info!("Client {} disconnected", source);
+                break;
+            }
+            Ok(_) => YoloResult::from_bytes(&bytes, endian)
+                .map(|result| Frame {
+                    source: source.clone(),
+                    time,
+                    result,
+                })
+                .with_context(|| format!("invalid frame from client {}", source)),
+            Err(err) => {
+                let err = err.context(format!("connection to client {} is broken", source));
+                let _ = tx.send(Err(err)).await;
+                break;
+            }
+        };
+
+        if tx.send(item).await.is_err() {
+            break;
+        }
+    }
 }
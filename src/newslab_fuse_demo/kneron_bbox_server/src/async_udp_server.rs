@@ -0,0 +1,90 @@
+use crate::{
+    frame::{CameraMap, Frame, Source},
+    protocol::{Endian, YoloResult},
+    server::DEFAULT_ADDR,
+    udp_server::DatagramBuf,
+};
+use anyhow::{Context, Result};
+use async_std::{
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    sync::Mutex,
+};
+use futures::prelude::*;
+use std::time::SystemTime;
+
+/// The async/.await server that receives detection messages from the
+/// Kneron camera in UDP datagrams.
+///
+/// Each datagram carries exactly one frame. Datagrams from any
+/// number of cameras are received on the same socket.
+#[derive(Debug)]
+pub struct AsyncUdpServer {
+    socket: UdpSocket,
+    endian: Endian,
+    cameras: CameraMap,
+    buf: Mutex<DatagramBuf>,
+}
+
+impl AsyncUdpServer {
+    /// Starts the server that binds to the default address.
+    pub async fn new() -> Result<Self> {
+        Self::bind(DEFAULT_ADDR).await
+    }
+
+    /// Starts the server that binds to specified address.
+    pub async fn bind<A>(addrs: A) -> Result<Self>
+    where
+        A: ToSocketAddrs,
+    {
+        let socket = UdpSocket::bind(addrs).await?;
+        Ok(Self {
+            socket,
+            endian: Endian::default(),
+            cameras: CameraMap::new(),
+            buf: Mutex::new(DatagramBuf::new()),
+        })
+    }
+
+    /// Returns the local address that the server binds to.
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    /// Sets the byte order of received frames. The default is
+    /// little-endian.
+    pub fn with_endian(self, endian: Endian) -> Self {
+        Self { endian, ..self }
+    }
+
+    /// Sets the camera names by camera IP addresses. Frames from the
+    /// listed addresses are tagged with the camera names.
+    pub fn with_cameras(self, cameras: CameraMap) -> Self {
+        Self { cameras, ..self }
+    }
+
+    /// Receives the next datagram and decodes it to a frame.
+    /// Concurrent calls take turns on the receive buffer.
+    pub async fn recv(&self) -> Result<Frame> {
+        let mut buf = self.buf.lock().await;
+        let (len, addr) = self.socket.recv_from(&mut buf).await?;
+        let time = SystemTime::now();
+        let source = Source::new(addr, &self.cameras);
+
+        let result = YoloResult::from_bytes(&buf[..len], self.endian)
+            .with_context(|| format!("invalid datagram from client {}", source))?;
+        Ok(Frame {
+            source,
+            time,
+            result,
+        })
+    }
+
+    /// Turns the server into a stream of frames. Invalid datagrams are
+    /// yielded as errors and the stream keeps receiving.
+    pub fn into_stream(self) -> impl Stream<Item = Result<Frame>> + Sync + Send {
+        stream::unfold(self, |server| async move {
+            let result = server.recv().await;
+            Some((result, server))
+        })
+    }
+}
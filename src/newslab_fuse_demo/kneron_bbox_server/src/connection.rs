@@ -0,0 +1,98 @@
+use futures::io::{AsyncRead, AsyncReadExt};
+use std::{
+    io::{self, prelude::*, ErrorKind},
+    sync::{
+        atomic::{AtomicUsize, Ordering::*},
+        Arc,
+    },
+    time::Duration,
+};
+
+/// The first delay to retry after an accept error.
+const MIN_BACKOFF: Duration = Duration::from_millis(10);
+
+/// The maximum delay to retry after consecutive accept errors.
+const MAX_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Counts a connection as active during its lifetime.
+#[derive(Debug)]
+pub(crate) struct ConnectionGuard {
+    connections: Arc<AtomicUsize>,
+}
+
+impl ConnectionGuard {
+    pub(crate) fn new(connections: Arc<AtomicUsize>) -> Self {
+        connections.fetch_add(1, SeqCst);
+        Self { connections }
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.connections.fetch_sub(1, SeqCst);
+    }
+}
+
+/// Doubles the delay on consecutive accept errors, such as running out
+/// of file descriptors, so that the accept loop does not spin.
+#[derive(Debug, Default)]
+pub(crate) struct Backoff {
+    delay: Option<Duration>,
+}
+
+impl Backoff {
+    /// Returns the delay before retrying after an error.
+    pub(crate) fn next_delay(&mut self) -> Duration {
+        let delay = match self.delay {
+            Some(delay) => (delay * 2).min(MAX_BACKOFF),
+            None => MIN_BACKOFF,
+        };
+        self.delay = Some(delay);
+        delay
+    }
+
+    /// Resets the delay after a success.
+    pub(crate) fn reset(&mut self) {
+        self.delay = None;
+    }
+}
+
+/// Fills the buffer from the reader until it is full or the reader
+/// reaches EOF. It returns the number of bytes read, which is less
+/// than the buffer size only at EOF.
+pub(crate) fn read_full<R>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize>
+where
+    R: Read,
+{
+    let mut received = 0;
+
+    while received < buf.len() {
+        match reader.read(&mut buf[received..]) {
+            Ok(0) => break,
+            Ok(len) => received += len,
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(received)
+}
+
+/// The async version of [read_full].
+pub(crate) async fn read_full_async<R>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut received = 0;
+
+    while received < buf.len() {
+        match reader.read(&mut buf[received..]).await {
+            Ok(0) => break,
+            Ok(len) => received += len,
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(received)
+}
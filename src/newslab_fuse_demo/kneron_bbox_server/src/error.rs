@@ -0,0 +1,66 @@
+//! Error types returned by the servers, the frame decoder and
+//! recordings.
+
+use thiserror::Error;
+
+/// Errors that occur when reading frames from connections.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum Error {
+    /// The client closed the connection in the middle of a frame.
+    #[error("connection closed after {received} of {expected} bytes of a frame")]
+    TruncatedFrame { expected: usize, received: usize },
+
+    /// The server stopped accepting clients and no more frames will
+    /// arrive.
+    #[error("the server stopped accepting clients")]
+    Stopped,
+}
+
+/// Errors that occur when decoding a frame from bytes.
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum DecodeError {
+    /// The byte buffer does not have the size of a frame.
+    #[error("expect a frame of {expected} bytes, but received {received} bytes")]
+    InvalidLength { expected: usize, received: usize },
+
+    /// The box count field is larger than the box array.
+    #[error("box count {count} exceeds the maximum {max}")]
+    BoxCountOverflow { count: usize, max: usize },
+
+    /// A box has a NaN or infinite value.
+    #[error("box {index} has a non-finite {field}")]
+    NonFiniteValue { index: usize, field: &'static str },
+
+    /// A box has its bottom-right corner above or left to the
+    /// top-left corner.
+    #[error("box {index} has inverted corners")]
+    InvertedBox { index: usize },
+
+    /// A box has a negative score.
+    #[error("box {index} has a negative score {score}")]
+    NegativeScore { index: usize, score: f32 },
+}
+
+/// Errors that occur when reading a recording.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum RecordError {
+    /// The file does not start with a recording header.
+    #[error("not a Kneron recording")]
+    BadHeader,
+
+    /// The recording is written in an unsupported format version.
+    #[error("unsupported recording version {version}")]
+    UnsupportedVersion { version: u16 },
+
+    /// The recording ends in the middle of a record.
+    #[error("the recording ends in the middle of a record")]
+    TruncatedRecord,
+
+    /// The source address of a record is not valid.
+    #[error("invalid source address in a record")]
+    InvalidSource,
+
+    /// The frame of a record is larger than a frame on the wire.
+    #[error("the recorded frame of {len} bytes is too large")]
+    OversizedFrame { len: usize },
+}
@@ -0,0 +1,49 @@
+//! Define frames tagged with the camera that sends them.
+
+use crate::protocol::YoloResult;
+use std::{
+    fmt,
+    fmt::Display,
+    net::{IpAddr, SocketAddr},
+    time::SystemTime,
+};
+
+/// The map from camera IP addresses to camera names.
+pub type CameraMap = std::collections::HashMap<IpAddr, String>;
+
+/// A detection frame along with the camera that sends it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub source: Source,
+    /// The time when the frame is read from the socket. Frames read
+    /// from a recording carry the recorded receive time.
+    pub time: SystemTime,
+    pub result: YoloResult,
+}
+
+/// Identifies the camera that sends a frame.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Source {
+    /// The peer address of the connection.
+    pub addr: SocketAddr,
+    /// The camera name configured for the peer IP address, if any.
+    pub camera_id: Option<String>,
+}
+
+impl Source {
+    pub(crate) fn new(addr: SocketAddr, cameras: &CameraMap) -> Self {
+        Self {
+            addr,
+            camera_id: cameras.get(&addr.ip()).cloned(),
+        }
+    }
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.camera_id {
+            Some(camera_id) => write!(f, "{} ({})", camera_id, self.addr),
+            None => write!(f, "{}", self.addr),
+        }
+    }
+}
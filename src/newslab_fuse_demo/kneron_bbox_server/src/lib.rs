@@ -1,8 +1,25 @@
+mod connection;
+
+mod error;
+pub use error::*;
+
+mod frame;
+pub use frame::*;
+
 mod protocol;
 pub use protocol::*;
 
+mod record;
+pub use record::*;
+
 mod server;
 pub use server::*;
 
 mod async_server;
 pub use async_server::*;
+
+mod udp_server;
+pub use udp_server::*;
+
+mod async_udp_server;
+pub use async_udp_server::*;
@@ -1,33 +1,213 @@
-//! Define C structs that are transmitted over the socket.
+//! Define the detection messages transmitted over the socket and
+//! their wire format.
+//!
+//! A frame on the wire is laid out as the C struct below. Integers
+//! and floats are encoded in the byte order given by [Endian].
+//!
+//! ```c
+//! struct YoloResult {
+//!     uint32_t class_count;
+//!     uint32_t box_count;
+//!     struct BoundingBox boxes[BOXES_MAX_NUM];
+//! };
+//!
+//! struct BoundingBox {
+//!     float x1, y1, x2, y2;
+//!     float score;
+//!     uint32_t class_num;
+//! };
+//! ```
 
-use std::ffi::c_uint;
+use crate::error::DecodeError;
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+use std::{ffi::c_uint, str::FromStr};
 
 pub const BOXES_MAX_NUM: usize = 80;
 
-#[derive(Debug, Clone)]
-#[repr(C)]
+/// The size of an encoded [BoundingBox] in bytes.
+pub const BOX_SIZE: usize = 24;
+
+/// The size of an encoded [YoloResult] in bytes.
+pub const FRAME_SIZE: usize = 8 + BOX_SIZE * BOXES_MAX_NUM;
+
+/// The byte order of numbers on the wire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Endian {
+    #[default]
+    Little,
+    Big,
+}
+
+impl FromStr for Endian {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let endian = match text {
+            "little" => Self::Little,
+            "big" => Self::Big,
+            _ => bail!("invalid endian '{}', expect 'little' or 'big'", text),
+        };
+        Ok(endian)
+    }
+}
+
+impl Endian {
+    fn u32_from_bytes(self, bytes: &[u8]) -> u32 {
+        let bytes: [u8; 4] = bytes.try_into().unwrap();
+        match self {
+            Endian::Little => u32::from_le_bytes(bytes),
+            Endian::Big => u32::from_be_bytes(bytes),
+        }
+    }
+
+    fn f32_from_bytes(self, bytes: &[u8]) -> f32 {
+        f32::from_bits(self.u32_from_bytes(bytes))
+    }
+
+    fn u32_to_bytes(self, value: u32) -> [u8; 4] {
+        match self {
+            Endian::Little => value.to_le_bytes(),
+            Endian::Big => value.to_be_bytes(),
+        }
+    }
+
+    fn f32_to_bytes(self, value: f32) -> [u8; 4] {
+        self.u32_to_bytes(value.to_bits())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct YoloResult {
     class_count: c_uint,
-    box_count: c_uint,
-    boxes: [BoundingBox; BOXES_MAX_NUM],
+    boxes: Vec<BoundingBox>,
 }
 
 impl YoloResult {
+    /// Creates a builder to construct a frame.
+    pub fn builder() -> YoloResultBuilder {
+        YoloResultBuilder::default()
+    }
+
+    /// Decodes a frame from bytes received from the socket.
+    ///
+    /// The boxes are validated before returned. It returns an error if
+    /// the box count exceeds [BOXES_MAX_NUM], a box has non-finite
+    /// values, a box has inverted corners or has a negative score.
+    pub fn from_bytes(bytes: &[u8], endian: Endian) -> Result<Self, DecodeError> {
+        if bytes.len() != FRAME_SIZE {
+            return Err(DecodeError::InvalidLength {
+                expected: FRAME_SIZE,
+                received: bytes.len(),
+            });
+        }
+
+        let class_count = endian.u32_from_bytes(&bytes[0..4]);
+        let box_count = endian.u32_from_bytes(&bytes[4..8]) as usize;
+
+        if box_count > BOXES_MAX_NUM {
+            return Err(DecodeError::BoxCountOverflow {
+                count: box_count,
+                max: BOXES_MAX_NUM,
+            });
+        }
+
+        let boxes: Vec<_> = bytes[8..]
+            .chunks_exact(BOX_SIZE)
+            .take(box_count)
+            .enumerate()
+            .map(|(index, bytes)| BoundingBox::from_bytes(index, bytes, endian))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self { class_count, boxes })
+    }
+
     pub fn class_count(&self) -> usize {
         self.class_count as usize
     }
 
     pub fn box_count(&self) -> usize {
-        self.box_count as usize
+        self.boxes.len()
     }
 
     pub fn boxes(&self) -> &[BoundingBox] {
-        let count = self.box_count();
-        &self.boxes[0..count]
+        &self.boxes
+    }
+
+    /// Encodes the frame to bytes in the wire format.
+    ///
+    /// Unused box slots are filled with zeros.
+    pub fn to_bytes(&self, endian: Endian) -> Vec<u8> {
+        let mut bytes = vec![0u8; FRAME_SIZE];
+        bytes[0..4].copy_from_slice(&endian.u32_to_bytes(self.class_count));
+        bytes[4..8].copy_from_slice(&endian.u32_to_bytes(self.boxes.len() as u32));
+
+        bytes[8..]
+            .chunks_exact_mut(BOX_SIZE)
+            .zip(&self.boxes)
+            .for_each(|(chunk, bbox)| bbox.write_bytes(chunk, endian));
+
+        bytes
+    }
+}
+
+/// The builder that constructs a [YoloResult].
+#[derive(Debug, Clone, Default)]
+pub struct YoloResultBuilder {
+    class_count: c_uint,
+    boxes: Vec<BoundingBox>,
+}
+
+impl YoloResultBuilder {
+    /// Sets the number of classes of the detector.
+    pub fn class_count(self, class_count: c_uint) -> Self {
+        Self {
+            class_count,
+            ..self
+        }
+    }
+
+    /// Appends a box to the frame.
+    pub fn push_box(mut self, bbox: BoundingBox) -> Self {
+        self.boxes.push(bbox);
+        self
+    }
+
+    /// Appends a sequence of boxes to the frame.
+    pub fn boxes<I>(mut self, boxes: I) -> Self
+    where
+        I: IntoIterator<Item = BoundingBox>,
+    {
+        self.boxes.extend(boxes);
+        self
+    }
+
+    /// Builds the frame.
+    ///
+    /// The frame is validated in the same way as
+    /// [YoloResult::from_bytes] so that it can be decoded after
+    /// encoded.
+    pub fn build(self) -> Result<YoloResult, DecodeError> {
+        let Self { class_count, boxes } = self;
+
+        if boxes.len() > BOXES_MAX_NUM {
+            return Err(DecodeError::BoxCountOverflow {
+                count: boxes.len(),
+                max: BOXES_MAX_NUM,
+            });
+        }
+
+        boxes
+            .iter()
+            .enumerate()
+            .try_for_each(|(index, bbox)| bbox.validate(index))?;
+
+        Ok(YoloResult { class_count, boxes })
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 #[repr(C)]
 pub struct BoundingBox {
     /// top-left x corner
@@ -43,3 +223,62 @@ pub struct BoundingBox {
     /// class number (of many) with highest probability
     pub class_num: c_uint,
 }
+
+impl BoundingBox {
+    /// Decodes and validates the `index`-th box in a frame.
+    fn from_bytes(index: usize, bytes: &[u8], endian: Endian) -> Result<Self, DecodeError> {
+        let bbox = Self {
+            x1: endian.f32_from_bytes(&bytes[0..4]),
+            y1: endian.f32_from_bytes(&bytes[4..8]),
+            x2: endian.f32_from_bytes(&bytes[8..12]),
+            y2: endian.f32_from_bytes(&bytes[12..16]),
+            score: endian.f32_from_bytes(&bytes[16..20]),
+            class_num: endian.u32_from_bytes(&bytes[20..24]),
+        };
+        bbox.validate(index)?;
+        Ok(bbox)
+    }
+
+    /// Encodes the box into a slice of [BOX_SIZE] bytes.
+    fn write_bytes(&self, bytes: &mut [u8], endian: Endian) {
+        bytes[0..4].copy_from_slice(&endian.f32_to_bytes(self.x1));
+        bytes[4..8].copy_from_slice(&endian.f32_to_bytes(self.y1));
+        bytes[8..12].copy_from_slice(&endian.f32_to_bytes(self.x2));
+        bytes[12..16].copy_from_slice(&endian.f32_to_bytes(self.y2));
+        bytes[16..20].copy_from_slice(&endian.f32_to_bytes(self.score));
+        bytes[20..24].copy_from_slice(&endian.u32_to_bytes(self.class_num));
+    }
+
+    /// Checks the box values and reports the first violation.
+    fn validate(&self, index: usize) -> Result<(), DecodeError> {
+        let Self {
+            x1,
+            y1,
+            x2,
+            y2,
+            score,
+            ..
+        } = *self;
+
+        let fields = [
+            ("x1", x1),
+            ("y1", y1),
+            ("x2", x2),
+            ("y2", y2),
+            ("score", score),
+        ];
+        if let Some(&(field, _)) = fields.iter().find(|(_, value)| !value.is_finite()) {
+            return Err(DecodeError::NonFiniteValue { index, field });
+        }
+
+        if x2 < x1 || y2 < y1 {
+            return Err(DecodeError::InvertedBox { index });
+        }
+
+        if score < 0.0 {
+            return Err(DecodeError::NegativeScore { index, score });
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,324 @@
+//! Record detection frames to a file and replay them later.
+//!
+//! A recording starts with a header, followed by a sequence of
+//! records. All integers are little-endian.
+//!
+//! ```text
+//! header:
+//!     magic: [u8; 8] = "KNRNREC\0"
+//!     version: u16 = 1
+//!     reserved: u16 = 0
+//!
+//! record:
+//!     time: u64          // receive time in nanoseconds since UNIX epoch
+//!     source_len: u8
+//!     source: [u8; source_len]  // peer address, e.g. "172.23.230.84:40312"
+//!     frame_len: u32
+//!     frame: [u8; frame_len]    // little-endian YoloResult
+//! ```
+//!
+//! The frame is truncated after the last used box to save space, and
+//! is zero-padded back to [FRAME_SIZE] when it is read.
+
+use crate::{
+    connection::read_full,
+    error::RecordError,
+    frame::{CameraMap, Frame, Source},
+    protocol::{Endian, YoloResult, BOX_SIZE, FRAME_SIZE},
+};
+use anyhow::{bail, ensure, Context, Result};
+use std::{
+    fs::File,
+    io::{prelude::*, BufReader, BufWriter},
+    net::SocketAddr,
+    path::Path,
+    str::FromStr,
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+const MAGIC: &[u8; 8] = b"KNRNREC\0";
+const VERSION: u16 = 1;
+
+/// Writes frames to a recording.
+#[derive(Debug)]
+pub struct Recorder<W>
+where
+    W: Write,
+{
+    writer: W,
+}
+
+impl Recorder<BufWriter<File>> {
+    /// Creates a recording file at the path.
+    pub fn create<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let file = File::create(path)
+            .with_context(|| format!("unable to create recording {}", path.display()))?;
+        Self::new(BufWriter::new(file))
+    }
+}
+
+impl<W> Recorder<W>
+where
+    W: Write,
+{
+    /// Starts a recording on the writer and writes the header.
+    pub fn new(mut writer: W) -> Result<Self> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&0u16.to_le_bytes())?;
+        Ok(Self { writer })
+    }
+
+    /// Appends a frame along with its receive time.
+    pub fn write(&mut self, frame: &Frame) -> Result<()> {
+        let nanos: u64 = frame
+            .time
+            .duration_since(UNIX_EPOCH)
+            .context("the receive time is before UNIX epoch")?
+            .as_nanos()
+            .try_into()
+            .context("the receive time is too far in the future")?;
+        let source = frame.source.addr.to_string();
+        let bytes = frame.result.to_bytes(Endian::Little);
+        let len = 8 + BOX_SIZE * frame.result.box_count();
+        let bytes = &bytes[..len];
+
+        self.writer.write_all(&nanos.to_le_bytes())?;
+        self.writer.write_all(&[source.len() as u8])?;
+        self.writer.write_all(source.as_bytes())?;
+        self.writer.write_all(&(len as u32).to_le_bytes())?;
+        self.writer.write_all(bytes)?;
+        Ok(())
+    }
+
+    /// Flushes buffered records to the underlying writer.
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Returns the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Reads frames from a recording in the recorded order.
+#[derive(Debug)]
+pub struct Recording<R>
+where
+    R: Read,
+{
+    reader: R,
+    cameras: CameraMap,
+}
+
+impl Recording<BufReader<File>> {
+    /// Opens a recording file at the path.
+    pub fn open<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let file = File::open(path)
+            .with_context(|| format!("unable to open recording {}", path.display()))?;
+        Self::new(BufReader::new(file))
+    }
+}
+
+impl<R> Recording<R>
+where
+    R: Read,
+{
+    /// Reads a recording from the reader and checks the header.
+    pub fn new(mut reader: R) -> Result<Self> {
+        let mut header = [0u8; 12];
+        reader
+            .read_exact(&mut header)
+            .map_err(|_| RecordError::BadHeader)?;
+
+        ensure!(&header[0..8] == MAGIC, RecordError::BadHeader);
+        let version = u16::from_le_bytes(header[8..10].try_into().unwrap());
+        ensure!(
+            version == VERSION,
+            RecordError::UnsupportedVersion { version }
+        );
+
+        Ok(Self {
+            reader,
+            cameras: CameraMap::new(),
+        })
+    }
+
+    /// Sets the camera names by camera IP addresses. Replayed frames
+    /// from the listed addresses are tagged with the camera names.
+    pub fn with_cameras(self, cameras: CameraMap) -> Self {
+        Self { cameras, ..self }
+    }
+
+    /// Reads the next frame. It returns `Ok(None)` at the end of the
+    /// recording.
+    pub fn read(&mut self) -> Result<Option<Frame>> {
+        let mut time = [0u8; 8];
+        match read_full(&mut self.reader, &mut time)? {
+            0 => return Ok(None),
+            received if received == time.len() => {}
+            _ => bail!(RecordError::TruncatedRecord),
+        }
+        let time = UNIX_EPOCH + Duration::from_nanos(u64::from_le_bytes(time));
+
+        let mut source_len = [0u8; 1];
+        read_record_field(&mut self.reader, &mut source_len)?;
+        let mut source = vec![0u8; source_len[0] as usize];
+        read_record_field(&mut self.reader, &mut source)?;
+        let addr: SocketAddr = std::str::from_utf8(&source)
+            .ok()
+            .and_then(|text| text.parse().ok())
+            .ok_or(RecordError::InvalidSource)?;
+
+        let mut frame_len = [0u8; 4];
+        read_record_field(&mut self.reader, &mut frame_len)?;
+        let frame_len = u32::from_le_bytes(frame_len) as usize;
+        ensure!(
+            frame_len <= FRAME_SIZE,
+            RecordError::OversizedFrame { len: frame_len }
+        );
+        let mut bytes = [0u8; FRAME_SIZE];
+        read_record_field(&mut self.reader, &mut bytes[..frame_len])?;
+        let result = YoloResult::from_bytes(&bytes, Endian::Little)?;
+
+        Ok(Some(Frame {
+            source: Source::new(addr, &self.cameras),
+            time,
+            result,
+        }))
+    }
+
+    /// Replays the recording at the given pace.
+    pub fn replay(self, pace: Pace) -> Replay<R> {
+        Replay {
+            recording: self,
+            pace,
+            start: None,
+        }
+    }
+}
+
+impl<R> Iterator for Recording<R>
+where
+    R: Read,
+{
+    type Item = Result<Frame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read().transpose()
+    }
+}
+
+/// The pace to replay a recording.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pace {
+    /// Replays frames at the recorded timing.
+    RealTime,
+    /// Replays frames as fast as possible.
+    Fastest,
+    /// Replays frames at the recorded timing sped up by the factor.
+    Speed(f64),
+}
+
+impl FromStr for Pace {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let pace = match text {
+            "realtime" => Self::RealTime,
+            "fastest" => Self::Fastest,
+            _ => {
+                let speed: f64 = match text.parse() {
+                    Ok(speed) => speed,
+                    Err(_) => bail!(
+                        "invalid pace '{}', expect 'realtime', 'fastest' or a speed factor",
+                        text
+                    ),
+                };
+                ensure!(
+                    speed.is_finite() && speed > 0.0,
+                    "the speed factor must be positive, but get {}",
+                    speed
+                );
+                Self::Speed(speed)
+            }
+        };
+        Ok(pace)
+    }
+}
+
+/// Yields frames of a recording, sleeping between frames to follow
+/// the recorded timing.
+#[derive(Debug)]
+pub struct Replay<R>
+where
+    R: Read,
+{
+    recording: Recording<R>,
+    pace: Pace,
+    start: Option<(SystemTime, Instant)>,
+}
+
+impl<R> Replay<R>
+where
+    R: Read,
+{
+    /// Waits for the time of the next frame and returns it. It
+    /// returns `Ok(None)` at the end of the recording.
+    pub fn recv(&mut self) -> Result<Option<Frame>> {
+        let frame = match self.recording.read()? {
+            Some(frame) => frame,
+            None => return Ok(None),
+        };
+        let time = frame.time;
+
+        let speed = match self.pace {
+            Pace::RealTime => 1.0,
+            Pace::Fastest => return Ok(Some(frame)),
+            Pace::Speed(speed) => speed,
+        };
+
+        let (first_time, start) = *self.start.get_or_insert_with(|| (time, Instant::now()));
+        let offset = time.duration_since(first_time).unwrap_or_default();
+        let deadline = start + offset.div_f64(speed);
+        if let Some(wait) = deadline.checked_duration_since(Instant::now()) {
+            thread::sleep(wait);
+        }
+
+        Ok(Some(frame))
+    }
+}
+
+impl<R> Iterator for Replay<R>
+where
+    R: Read,
+{
+    type Item = Result<Frame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.recv().transpose()
+    }
+}
+
+/// Fills the buffer with a field in the middle of a record.
+fn read_record_field<R>(reader: &mut R, buf: &mut [u8]) -> Result<()>
+where
+    R: Read,
+{
+    if read_full(reader, buf)? == buf.len() {
+        Ok(())
+    } else {
+        Err(RecordError::TruncatedRecord.into())
+    }
+}
@@ -1,18 +1,48 @@
-use crate::protocol::YoloResult;
-use anyhow::Result;
+use crate::{
+    connection::{read_full, Backoff, ConnectionGuard},
+    error::Error,
+    frame::{CameraMap, Frame, Source},
+    protocol::{Endian, YoloResult, FRAME_SIZE},
+};
+use anyhow::{ensure, Context, Result};
 use log::info;
 use std::{
-    io::prelude::*,
-    mem,
-    net::{TcpListener, ToSocketAddrs},
+    io::ErrorKind,
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering::*},
+        mpsc::{sync_channel, Receiver, SyncSender},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, SystemTime},
 };
 
 pub const DEFAULT_ADDR: &str = "0.0.0.0:8700";
 
+/// The capacity of the channel between connection threads and the
+/// receiver.
+const CHANNEL_SIZE: usize = 16;
+
+/// The interval to check for new clients and for the server being
+/// dropped.
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(50);
+
 /// The server that listens to detection messages from the Kneron camera.
+///
+/// The server accepts multiple cameras concurrently. Each connection
+/// is kept open and served by a thread that reads a stream of frames
+/// until the camera disconnects. The threads are started on the first
+/// call to [Server::recv], and the accepting thread stops when the
+/// server is dropped.
 #[derive(Debug)]
 pub struct Server {
     listener: TcpListener,
+    endian: Endian,
+    cameras: CameraMap,
+    connections: Arc<AtomicUsize>,
+    rx: Mutex<Option<Receiver<Result<Frame>>>>,
+    stop: StopOnDrop,
 }
 
 impl Server {
@@ -27,22 +57,77 @@ impl Server {
         A: ToSocketAddrs,
     {
         let listener = TcpListener::bind(addrs)?;
-        Ok(Self { listener })
+        Ok(Self {
+            listener,
+            endian: Endian::default(),
+            cameras: CameraMap::new(),
+            connections: Arc::new(AtomicUsize::new(0)),
+            rx: Mutex::new(None),
+            stop: StopOnDrop(Arc::new(AtomicBool::new(false))),
+        })
+    }
+
+    /// Returns the local address that the server binds to.
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Returns the number of connected cameras.
+    pub fn connections(&self) -> usize {
+        self.connections.load(SeqCst)
+    }
+
+    /// Sets the byte order of received frames. The default is
+    /// little-endian.
+    pub fn with_endian(self, endian: Endian) -> Self {
+        Self { endian, ..self }
+    }
+
+    /// Sets the camera names by camera IP addresses. Frames from the
+    /// listed addresses are tagged with the camera names.
+    pub fn with_cameras(self, cameras: CameraMap) -> Self {
+        Self { cameras, ..self }
+    }
+
+    /// Receives the next frame from any of the connected cameras.
+    pub fn recv(&self) -> Result<Frame> {
+        let mut guard = self.rx.lock().unwrap();
+        let rx = match &*guard {
+            Some(rx) => rx,
+            None => guard.insert(self.start()?),
+        };
+        rx.recv().map_err(|_| Error::Stopped)?
+    }
+
+    /// Spawns the thread that accepts clients.
+    fn start(&self) -> Result<Receiver<Result<Frame>>> {
+        // Poll the listener so that the thread can notice the server
+        // being dropped.
+        let listener = self.listener.try_clone()?;
+        listener.set_nonblocking(true)?;
+        let endian = self.endian;
+        let cameras = self.cameras.clone();
+        let connections = self.connections.clone();
+        let stop = self.stop.0.clone();
+        let (tx, rx) = sync_channel(CHANNEL_SIZE);
+
+        thread::spawn(move || accept_loop(listener, endian, cameras, connections, stop, tx));
+        Ok(rx)
     }
+}
 
-    pub fn recv(&self) -> Result<YoloResult> {
-        let (mut stream, addr) = self.listener.accept()?;
-        info!("Connected from client {}", addr);
+/// Signals the accepting thread to stop when dropped.
+#[derive(Debug)]
+struct StopOnDrop(Arc<AtomicBool>);
 
-        let mut bytes = [0u8; mem::size_of::<YoloResult>()];
-        stream.read_exact(&mut bytes)?;
-        let result: YoloResult = unsafe { mem::transmute(bytes) };
-        Ok(result)
+impl Drop for StopOnDrop {
+    fn drop(&mut self) {
+        self.0.store(true, SeqCst);
     }
 }
 
 impl Iterator for Server {
-    type Item = Result<YoloResult>;
+    type Item = Result<Frame>;
 
     fn next(&mut self) -> Option<Self::Item> {
         Some(self.recv())
@@ -50,9 +135,108 @@ impl Iterator for Server {
 }
 
 impl<'a> Iterator for &'a Server {
-    type Item = Result<YoloResult>;
+    type Item = Result<Frame>;
 
     fn next(&mut self) -> Option<Self::Item> {
         Some(self.recv())
     }
 }
+
+/// Accepts clients and spawns a thread for each connection.
+fn accept_loop(
+    listener: TcpListener,
+    endian: Endian,
+    cameras: CameraMap,
+    connections: Arc<AtomicUsize>,
+    stop: Arc<AtomicBool>,
+    tx: SyncSender<Result<Frame>>,
+) {
+    let mut backoff = Backoff::default();
+
+    while !stop.load(SeqCst) {
+        let (stream, addr) = match listener.accept() {
+            Ok(pair) => {
+                backoff.reset();
+                pair
+            }
+            Err(err) if err.kind() == ErrorKind::WouldBlock => {
+                thread::sleep(ACCEPT_INTERVAL);
+                continue;
+            }
+            Err(err) => {
+                if tx.send(Err(err.into())).is_err() {
+                    break;
+                }
+                thread::sleep(backoff.next_delay());
+                continue;
+            }
+        };
+
+        // Accepted streams may inherit the non-blocking mode on some
+        // platforms.
+        if let Err(err) = stream.set_nonblocking(false) {
+            if tx.send(Err(err.into())).is_err() {
+                break;
+            }
+            continue;
+        }
+
+        let source = Source::new(addr, &cameras);
+        info!("Connected from client {}", source);
+
+        let guard = ConnectionGuard::new(connections.clone());
+        let tx = tx.clone();
+        thread::spawn(move || connection_loop(stream, source, endian, guard, tx));
+    }
+}
+
+/// Reads frames from a connection until the client disconnects.
+fn connection_loop(
+    mut stream: TcpStream,
+    source: Source,
+    endian: Endian,
+    _guard: ConnectionGuard,
+    tx: SyncSender<Result<Frame>>,
+) {
+    loop {
+        let mut bytes = [0u8; FRAME_SIZE];
+
+        // EOF is only expected between frames.
+        let received = read_full(&mut stream, &mut bytes)
+            .map_err(anyhow::Error::from)
+            .and_then(|received| {
+                ensure!(
+                    received == 0 || received == FRAME_SIZE,
+                    Error::TruncatedFrame {
+                        expected: FRAME_SIZE,
+                        received,
+                    }
+                );
+                Ok(received)
+            });
+
+        let time = SystemTime::now();
+        let item = match received {
+            Ok(0) => {
+                info!("Client {} disconnected", source);
+                break;
+            }
+            Ok(_) => YoloResult::from_bytes(&bytes, endian)
+                .map(|result| Frame {
+                    source: source.clone(),
+                    time,
+                    result,
+                })
+                .with_context(|| format!("invalid frame from client {}", source)),
+            Err(err) => {
+                let err = err.context(format!("connection to client {} is broken", source));
+                let _ = tx.send(Err(err));
+                break;
+            }
+        };
+
+        if tx.send(item).is_err() {
+            break;
+        }
+    }
+}
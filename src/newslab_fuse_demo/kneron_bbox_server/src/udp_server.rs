@@ -0,0 +1,132 @@
+use crate::{
+    frame::{CameraMap, Frame, Source},
+    protocol::{Endian, YoloResult},
+    server::DEFAULT_ADDR,
+};
+use anyhow::{Context, Result};
+use std::{
+    fmt,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    ops::{Deref, DerefMut},
+    sync::Mutex,
+    time::SystemTime,
+};
+
+/// The size of the receive buffer, which is large enough for any UDP
+/// datagram so that oversized frames are detected rather than
+/// truncated.
+const MAX_DATAGRAM_SIZE: usize = 65536;
+
+/// The receive buffer that is allocated once per server and reused
+/// for every datagram.
+pub(crate) struct DatagramBuf(Box<[u8]>);
+
+impl DatagramBuf {
+    pub(crate) fn new() -> Self {
+        Self(vec![0u8; MAX_DATAGRAM_SIZE].into_boxed_slice())
+    }
+}
+
+impl fmt::Debug for DatagramBuf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "DatagramBuf({} bytes)", self.0.len())
+    }
+}
+
+impl Deref for DatagramBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for DatagramBuf {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+/// The server that receives detection messages from the Kneron
+/// camera in UDP datagrams.
+///
+/// Each datagram carries exactly one frame. Datagrams from any
+/// number of cameras are received on the same socket.
+#[derive(Debug)]
+pub struct UdpServer {
+    socket: UdpSocket,
+    endian: Endian,
+    cameras: CameraMap,
+    buf: Mutex<DatagramBuf>,
+}
+
+impl UdpServer {
+    /// Starts the server that binds to the default address.
+    pub fn new() -> Result<Self> {
+        Self::bind(DEFAULT_ADDR)
+    }
+
+    /// Starts the server that binds to specified address.
+    pub fn bind<A>(addrs: A) -> Result<Self>
+    where
+        A: ToSocketAddrs,
+    {
+        let socket = UdpSocket::bind(addrs)?;
+        Ok(Self {
+            socket,
+            endian: Endian::default(),
+            cameras: CameraMap::new(),
+            buf: Mutex::new(DatagramBuf::new()),
+        })
+    }
+
+    /// Returns the local address that the server binds to.
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    /// Sets the byte order of received frames. The default is
+    /// little-endian.
+    pub fn with_endian(self, endian: Endian) -> Self {
+        Self { endian, ..self }
+    }
+
+    /// Sets the camera names by camera IP addresses. Frames from the
+    /// listed addresses are tagged with the camera names.
+    pub fn with_cameras(self, cameras: CameraMap) -> Self {
+        Self { cameras, ..self }
+    }
+
+    /// Receives the next datagram and decodes it to a frame.
+    /// Concurrent calls take turns on the receive buffer.
+    pub fn recv(&self) -> Result<Frame> {
+        let mut buf = self.buf.lock().unwrap();
+        let (len, addr) = self.socket.recv_from(&mut buf)?;
+        let time = SystemTime::now();
+        let source = Source::new(addr, &self.cameras);
+
+        let result = YoloResult::from_bytes(&buf[..len], self.endian)
+            .with_context(|| format!("invalid datagram from client {}", source))?;
+        Ok(Frame {
+            source,
+            time,
+            result,
+        })
+    }
+}
+
+impl Iterator for UdpServer {
+    type Item = Result<Frame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.recv())
+    }
+}
+
+impl Iterator for &UdpServer {
+    type Item = Result<Frame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.recv())
+    }
+}
@@ -0,0 +1,187 @@
+use kneron_bbox_server::{
+    BoundingBox, DecodeError, Endian, YoloResult, BOXES_MAX_NUM, BOX_SIZE, FRAME_SIZE,
+};
+use proptest::{collection::vec, prelude::*};
+
+fn endian() -> impl Strategy<Value = Endian> {
+    prop_oneof![Just(Endian::Little), Just(Endian::Big)]
+}
+
+fn u32_to_bytes(value: u32, endian: Endian) -> [u8; 4] {
+    match endian {
+        Endian::Little => value.to_le_bytes(),
+        Endian::Big => value.to_be_bytes(),
+    }
+}
+
+/// Lays out a frame with the given header and box fields by hand.
+fn make_frame(class_count: u32, box_count: u32, boxes: &[[u32; 6]], endian: Endian) -> Vec<u8> {
+    let mut bytes = vec![0u8; FRAME_SIZE];
+    bytes[0..4].copy_from_slice(&u32_to_bytes(class_count, endian));
+    bytes[4..8].copy_from_slice(&u32_to_bytes(box_count, endian));
+
+    bytes[8..]
+        .chunks_exact_mut(BOX_SIZE)
+        .zip(boxes)
+        .for_each(|(chunk, fields)| {
+            chunk
+                .chunks_exact_mut(4)
+                .zip(fields)
+                .for_each(|(chunk, &field)| chunk.copy_from_slice(&u32_to_bytes(field, endian)));
+        });
+
+    bytes
+}
+
+fn valid_box() -> impl Strategy<Value = [u32; 6]> {
+    (
+        0f32..1280.0,
+        0f32..960.0,
+        0f32..100.0,
+        0f32..100.0,
+        0f32..=1.0,
+        0u32..80,
+    )
+        .prop_map(|(x1, y1, w, h, score, class_num)| {
+            [
+                x1.to_bits(),
+                y1.to_bits(),
+                (x1 + w).to_bits(),
+                (y1 + h).to_bits(),
+                score.to_bits(),
+                class_num,
+            ]
+        })
+}
+
+proptest! {
+    #[test]
+    fn random_bytes_never_panic(bytes in vec(any::<u8>(), 0..FRAME_SIZE * 2), endian in endian()) {
+        if let Ok(result) = YoloResult::from_bytes(&bytes, endian) {
+            prop_assert_eq!(bytes.len(), FRAME_SIZE);
+            prop_assert!(result.box_count() <= BOXES_MAX_NUM);
+
+            for bbox in result.boxes() {
+                prop_assert!(bbox.x1.is_finite() && bbox.y1.is_finite());
+                prop_assert!(bbox.x2.is_finite() && bbox.y2.is_finite());
+                prop_assert!(bbox.x1 <= bbox.x2 && bbox.y1 <= bbox.y2);
+                prop_assert!(bbox.score >= 0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn random_frames_never_panic(bytes in vec(any::<u8>(), FRAME_SIZE), endian in endian()) {
+        let _ = YoloResult::from_bytes(&bytes, endian);
+    }
+
+    #[test]
+    fn valid_frames_are_decoded(
+        boxes in vec(valid_box(), 0..=BOXES_MAX_NUM),
+        class_count in any::<u32>(),
+        endian in endian(),
+    ) {
+        let bytes = make_frame(class_count, boxes.len() as u32, &boxes, endian);
+        let result = YoloResult::from_bytes(&bytes, endian).unwrap();
+
+        prop_assert_eq!(result.class_count(), class_count as usize);
+        prop_assert_eq!(result.box_count(), boxes.len());
+
+        for (bbox, fields) in result.boxes().iter().zip(&boxes) {
+            prop_assert_eq!(bbox.x1.to_bits(), fields[0]);
+            prop_assert_eq!(bbox.y2.to_bits(), fields[3]);
+            prop_assert_eq!(bbox.class_num, fields[5]);
+        }
+    }
+
+    #[test]
+    fn encoded_frames_round_trip(
+        boxes in vec(valid_box(), 0..=BOXES_MAX_NUM),
+        class_count in any::<u32>(),
+        endian in endian(),
+    ) {
+        let boxes = boxes.into_iter().map(|[x1, y1, x2, y2, score, class_num]| BoundingBox {
+            x1: f32::from_bits(x1),
+            y1: f32::from_bits(y1),
+            x2: f32::from_bits(x2),
+            y2: f32::from_bits(y2),
+            score: f32::from_bits(score),
+            class_num,
+        });
+        let result = YoloResult::builder()
+            .class_count(class_count)
+            .boxes(boxes)
+            .build()
+            .unwrap();
+
+        let bytes = result.to_bytes(endian);
+        prop_assert_eq!(bytes.len(), FRAME_SIZE);
+
+        let decoded = YoloResult::from_bytes(&bytes, endian).unwrap();
+        prop_assert_eq!(decoded, result);
+    }
+
+    #[test]
+    fn box_count_overflow_is_rejected(box_count in (BOXES_MAX_NUM as u32 + 1).., endian in endian()) {
+        let bytes = make_frame(80, box_count, &[], endian);
+        let result = YoloResult::from_bytes(&bytes, endian);
+        let is_overflow = matches!(result, Err(DecodeError::BoxCountOverflow { .. }));
+        prop_assert!(is_overflow);
+    }
+
+    #[test]
+    fn wrong_length_is_rejected(len in 0..FRAME_SIZE * 2, endian in endian()) {
+        prop_assume!(len != FRAME_SIZE);
+        let bytes = vec![0u8; len];
+        let result = YoloResult::from_bytes(&bytes, endian);
+        let is_invalid_length = matches!(result, Err(DecodeError::InvalidLength { .. }));
+        prop_assert!(is_invalid_length);
+    }
+}
+
+#[test]
+fn invalid_boxes_are_rejected() {
+    let endian = Endian::Little;
+    let decode = |fields: [f32; 5]| {
+        let bbox = fields.map(f32::to_bits);
+        let bbox = [bbox[0], bbox[1], bbox[2], bbox[3], bbox[4], 0];
+        let bytes = make_frame(80, 1, &[bbox], endian);
+        YoloResult::from_bytes(&bytes, endian)
+    };
+
+    assert!(matches!(
+        decode([f32::NAN, 0.0, 1.0, 1.0, 0.5]),
+        Err(DecodeError::NonFiniteValue {
+            index: 0,
+            field: "x1"
+        })
+    ));
+    assert!(matches!(
+        decode([0.0, 0.0, f32::INFINITY, 1.0, 0.5]),
+        Err(DecodeError::NonFiniteValue {
+            index: 0,
+            field: "x2"
+        })
+    ));
+    assert!(matches!(
+        decode([2.0, 0.0, 1.0, 1.0, 0.5]),
+        Err(DecodeError::InvertedBox { index: 0 })
+    ));
+    assert!(matches!(
+        decode([0.0, 0.0, 1.0, 1.0, -0.5]),
+        Err(DecodeError::NegativeScore { index: 0, .. })
+    ));
+}
+
+#[test]
+fn endian_is_respected() {
+    let bbox = [0f32, 0.0, 10.0, 20.0, 0.9].map(f32::to_bits);
+    let bbox = [bbox[0], bbox[1], bbox[2], bbox[3], bbox[4], 7];
+    let bytes = make_frame(80, 1, &[bbox], Endian::Big);
+
+    let result = YoloResult::from_bytes(&bytes, Endian::Big).unwrap();
+    assert_eq!(result.boxes()[0].class_num, 7);
+    assert_eq!(result.boxes()[0].y2, 20.0);
+
+    assert!(YoloResult::from_bytes(&bytes, Endian::Little).is_err());
+}
@@ -0,0 +1,357 @@
+use async_std::net::{TcpStream as AsyncTcpStream, UdpSocket as AsyncUdpSocket};
+use futures::prelude::*;
+use kneron_bbox_server::{
+    AsyncServer, AsyncUdpServer, BoundingBox, CameraMap, DecodeError, Endian, Error, Server,
+    UdpServer, YoloResult, FRAME_SIZE,
+};
+use std::{
+    collections::HashMap,
+    io::prelude::*,
+    net::{TcpStream, UdpSocket},
+    thread,
+    time::{Duration, Instant},
+};
+
+fn sample_results() -> Vec<YoloResult> {
+    (0..3)
+        .map(|idx| {
+            let bbox = BoundingBox {
+                x1: 10.0 * idx as f32,
+                y1: 20.0,
+                x2: 10.0 * idx as f32 + 50.0,
+                y2: 80.0,
+                score: 0.5,
+                class_num: idx,
+            };
+            YoloResult::builder()
+                .class_count(80)
+                .push_box(bbox)
+                .build()
+                .unwrap()
+        })
+        .collect()
+}
+
+#[test]
+fn server_reads_frames_from_one_connection() {
+    let server = Server::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let expect = sample_results();
+
+    let client = thread::spawn({
+        let results = expect.clone();
+        move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            for result in &results {
+                stream.write_all(&result.to_bytes(Endian::Little)).unwrap();
+            }
+        }
+    });
+
+    let received: Vec<_> = (&server)
+        .take(expect.len())
+        .map(|frame| frame.unwrap().result)
+        .collect();
+    assert_eq!(received, expect);
+    client.join().unwrap();
+}
+
+#[test]
+fn server_reports_truncated_frames() {
+    let server = Server::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let result = sample_results().remove(0);
+
+    let client = thread::spawn(move || {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let bytes = result.to_bytes(Endian::Big);
+        stream.write_all(&bytes[..100]).unwrap();
+    });
+
+    let err = server.recv().unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<Error>(),
+        Some(Error::TruncatedFrame { received: 100, .. })
+    ));
+    client.join().unwrap();
+}
+
+#[async_std::test]
+async fn async_server_stream_continues_after_invalid_frames() {
+    let server = AsyncServer::bind("127.0.0.1:0").await.unwrap();
+    let addr = server.local_addr().unwrap();
+    let expect = sample_results();
+
+    // One camera sends a truncated frame before the other sends valid
+    // frames.
+    let mut broken = AsyncTcpStream::connect(addr).await.unwrap();
+    let bytes = expect[0].to_bytes(Endian::Little);
+    broken.write_all(&bytes[..100]).await.unwrap();
+    drop(broken);
+
+    let client = async_std::task::spawn({
+        let results = expect.clone();
+        async move {
+            let mut stream = AsyncTcpStream::connect(addr).await.unwrap();
+            for result in &results {
+                stream
+                    .write_all(&result.to_bytes(Endian::Little))
+                    .await
+                    .unwrap();
+            }
+        }
+    });
+
+    let items: Vec<_> = server.into_stream().take(expect.len() + 1).collect().await;
+    let (frames, errors): (Vec<_>, Vec<_>) = items.into_iter().partition(|item| item.is_ok());
+    assert_eq!(errors.len(), 1);
+    let received: Vec<_> = frames
+        .into_iter()
+        .map(|frame| frame.unwrap().result)
+        .collect();
+    assert_eq!(received, expect);
+    client.await;
+}
+
+#[async_std::test]
+async fn async_server_accepts_the_next_client_after_eof() {
+    let server = AsyncServer::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .with_endian(Endian::Big);
+    let addr = server.local_addr().unwrap();
+    let expect = sample_results();
+
+    let client = async_std::task::spawn({
+        let results = expect.clone();
+        async move {
+            for result in &results {
+                let mut stream = AsyncTcpStream::connect(addr).await.unwrap();
+                stream
+                    .write_all(&result.to_bytes(Endian::Big))
+                    .await
+                    .unwrap();
+            }
+        }
+    });
+
+    let received: Vec<_> = server
+        .into_stream()
+        .take(expect.len())
+        .map_ok(|frame| frame.result)
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(received, expect);
+    client.await;
+}
+
+#[async_std::test]
+async fn async_server_serves_concurrent_cameras() {
+    let cameras: CameraMap = [("127.0.0.1".parse().unwrap(), "front".to_string())]
+        .into_iter()
+        .collect();
+    let server = AsyncServer::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .with_cameras(cameras);
+    let addr = server.local_addr().unwrap();
+    let expect = sample_results();
+
+    // Both clients stay connected until all frames are sent.
+    let mut streams = vec![];
+    for _ in 0..2 {
+        streams.push(AsyncTcpStream::connect(addr).await.unwrap());
+    }
+    for result in &expect {
+        for stream in &mut streams {
+            stream
+                .write_all(&result.to_bytes(Endian::Little))
+                .await
+                .unwrap();
+        }
+    }
+
+    let frames: Vec<_> = server
+        .into_stream()
+        .take(expect.len() * 2)
+        .try_collect()
+        .await
+        .unwrap();
+
+    let mut by_peer = HashMap::new();
+    for frame in frames {
+        assert_eq!(frame.source.camera_id.as_deref(), Some("front"));
+        by_peer
+            .entry(frame.source.addr)
+            .or_insert_with(Vec::new)
+            .push(frame.result);
+    }
+
+    assert_eq!(by_peer.len(), 2);
+    for stream in &streams {
+        let local_addr = stream.local_addr().unwrap();
+        assert_eq!(by_peer[&local_addr], expect);
+    }
+}
+
+#[test]
+fn udp_server_decodes_one_frame_per_datagram() {
+    let server = UdpServer::bind("127.0.0.1:0")
+        .unwrap()
+        .with_endian(Endian::Big);
+    let addr = server.local_addr().unwrap();
+    let expect = sample_results();
+
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    for result in &expect {
+        socket.send_to(&result.to_bytes(Endian::Big), addr).unwrap();
+    }
+
+    let received: Vec<_> = (&server)
+        .take(expect.len())
+        .map(|frame| frame.unwrap())
+        .collect();
+    for (frame, expect) in received.iter().zip(&expect) {
+        assert_eq!(frame.source.addr, socket.local_addr().unwrap());
+        assert_eq!(&frame.result, expect);
+    }
+}
+
+#[test]
+fn udp_server_rejects_short_and_oversized_datagrams() {
+    let server = UdpServer::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let bytes = sample_results()[0].to_bytes(Endian::Little);
+
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.send_to(&bytes[..100], addr).unwrap();
+    let mut oversized = bytes.clone();
+    oversized.push(0);
+    socket.send_to(&oversized, addr).unwrap();
+    socket.send_to(&bytes, addr).unwrap();
+
+    for received in [100, FRAME_SIZE + 1] {
+        let err = server.recv().unwrap_err();
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&DecodeError::InvalidLength {
+                expected: FRAME_SIZE,
+                received
+            })
+        );
+    }
+    assert!(server.recv().is_ok());
+}
+
+#[async_std::test]
+async fn async_udp_server_names_cameras() {
+    let cameras: CameraMap = [("127.0.0.1".parse().unwrap(), "front".to_string())]
+        .into_iter()
+        .collect();
+    let server = AsyncUdpServer::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .with_cameras(cameras);
+    let addr = server.local_addr().unwrap();
+    let expect = sample_results();
+
+    let socket = AsyncUdpSocket::bind("127.0.0.1:0").await.unwrap();
+    for result in &expect {
+        socket
+            .send_to(&result.to_bytes(Endian::Little), addr)
+            .await
+            .unwrap();
+    }
+
+    let frames: Vec<_> = server
+        .into_stream()
+        .take(expect.len())
+        .try_collect()
+        .await
+        .unwrap();
+    for (frame, expect) in frames.iter().zip(&expect) {
+        assert_eq!(frame.source.camera_id.as_deref(), Some("front"));
+        assert_eq!(&frame.result, expect);
+    }
+}
+
+#[async_std::test]
+async fn async_udp_server_stream_continues_after_invalid_datagrams() {
+    let server = AsyncUdpServer::bind("127.0.0.1:0").await.unwrap();
+    let addr = server.local_addr().unwrap();
+    let bytes = sample_results()[0].to_bytes(Endian::Little);
+
+    let socket = AsyncUdpSocket::bind("127.0.0.1:0").await.unwrap();
+    socket.send_to(&bytes[..100], addr).await.unwrap();
+    socket.send_to(&bytes, addr).await.unwrap();
+
+    let items: Vec<_> = server.into_stream().take(2).collect().await;
+    let err = items[0].as_ref().unwrap_err();
+    assert_eq!(
+        err.downcast_ref(),
+        Some(&DecodeError::InvalidLength {
+            expected: FRAME_SIZE,
+            received: 100
+        })
+    );
+    assert!(items[1].is_ok());
+}
+
+#[test]
+fn server_counts_connected_cameras() {
+    let server = Server::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let result = sample_results().remove(0);
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all(&result.to_bytes(Endian::Little)).unwrap();
+    server.recv().unwrap();
+    assert_eq!(server.connections(), 1);
+
+    drop(stream);
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while server.connections() > 0 {
+        assert!(Instant::now() < deadline, "the connection is not released");
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn server_releases_the_port_when_dropped() {
+    let server = Server::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let result = sample_results().remove(0);
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all(&result.to_bytes(Endian::Little)).unwrap();
+    server.recv().unwrap();
+
+    drop(server);
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while Server::bind(addr).is_err() {
+        assert!(Instant::now() < deadline, "the port is not released");
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[async_std::test]
+async fn async_server_releases_the_port_when_dropped() {
+    let server = AsyncServer::bind("127.0.0.1:0").await.unwrap();
+    let addr = server.local_addr().unwrap();
+    let result = sample_results().remove(0);
+
+    let mut stream = AsyncTcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(&result.to_bytes(Endian::Little))
+        .await
+        .unwrap();
+    server.recv().await.unwrap();
+
+    drop(server);
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while AsyncServer::bind(addr).await.is_err() {
+        assert!(Instant::now() < deadline, "the port is not released");
+        async_std::task::sleep(Duration::from_millis(10)).await;
+    }
+}
@@ -0,0 +1,123 @@
+use kneron_bbox_server::{
+    BoundingBox, CameraMap, Frame, Pace, RecordError, Recorder, Recording, Source, YoloResult,
+};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Creates frames received at the given interval.
+fn sample_frames(interval: Duration) -> Vec<Frame> {
+    let start = SystemTime::now();
+    let source = Source {
+        addr: "172.23.230.84:40312".parse().unwrap(),
+        camera_id: None,
+    };
+
+    (0..4)
+        .map(|idx| {
+            let boxes = (0..idx).map(|class_num| BoundingBox {
+                x1: 10.0,
+                y1: 20.0,
+                x2: 30.0 + idx as f32,
+                y2: 40.0,
+                score: 0.75,
+                class_num,
+            });
+            let result = YoloResult::builder()
+                .class_count(80)
+                .boxes(boxes)
+                .build()
+                .unwrap();
+            Frame {
+                source: source.clone(),
+                time: start + interval * idx,
+                result,
+            }
+        })
+        .collect()
+}
+
+/// Writes the frames to an in-memory recording.
+fn record(frames: &[Frame]) -> Vec<u8> {
+    let mut recorder = Recorder::new(vec![]).unwrap();
+    for frame in frames {
+        recorder.write(frame).unwrap();
+    }
+    recorder.into_inner()
+}
+
+#[test]
+fn recorded_frames_round_trip() {
+    let frames = sample_frames(Duration::from_millis(100));
+    let bytes = record(&frames);
+
+    let replayed: Vec<_> = Recording::new(bytes.as_slice())
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(replayed, frames);
+
+    let interval = replayed[1].time.duration_since(replayed[0].time).unwrap();
+    assert_eq!(interval, Duration::from_millis(100));
+}
+
+#[test]
+fn recording_names_cameras() {
+    let frames = sample_frames(Duration::ZERO);
+    let bytes = record(&frames);
+    let cameras: CameraMap = [("172.23.230.84".parse().unwrap(), "front".to_string())]
+        .into_iter()
+        .collect();
+
+    let frame = Recording::new(bytes.as_slice())
+        .unwrap()
+        .with_cameras(cameras)
+        .read()
+        .unwrap()
+        .unwrap();
+    assert_eq!(frame.source.camera_id.as_deref(), Some("front"));
+}
+
+#[test]
+fn invalid_recordings_are_rejected() {
+    let err = Recording::new(&b"not a recording"[..]).unwrap_err();
+    assert_eq!(err.downcast_ref(), Some(&RecordError::BadHeader));
+
+    let bytes = record(&sample_frames(Duration::ZERO));
+    let mut recording = Recording::new(&bytes[..bytes.len() - 1]).unwrap();
+    let err = recording.by_ref().find_map(|record| record.err()).unwrap();
+    assert_eq!(err.downcast_ref(), Some(&RecordError::TruncatedRecord));
+}
+
+#[test]
+fn replay_follows_the_pace() {
+    let frames = sample_frames(Duration::from_millis(200));
+    let bytes = record(&frames);
+
+    let since = Instant::now();
+    let replayed: Vec<_> = Recording::new(bytes.as_slice())
+        .unwrap()
+        .replay(Pace::Speed(10.0))
+        .map(|frame| frame.unwrap())
+        .collect();
+    let elapsed = since.elapsed();
+    assert_eq!(replayed, frames);
+    assert!(
+        elapsed >= Duration::from_millis(60),
+        "elapsed {:?}",
+        elapsed
+    );
+
+    let count = Recording::new(bytes.as_slice())
+        .unwrap()
+        .replay(Pace::Fastest)
+        .count();
+    assert_eq!(count, frames.len());
+}
+
+#[test]
+fn pace_is_parsed() {
+    assert_eq!("realtime".parse::<Pace>().unwrap(), Pace::RealTime);
+    assert_eq!("fastest".parse::<Pace>().unwrap(), Pace::Fastest);
+    assert_eq!("2.5".parse::<Pace>().unwrap(), Pace::Speed(2.5));
+    assert!("0".parse::<Pace>().is_err());
+    assert!("slow".parse::<Pace>().is_err());
+}
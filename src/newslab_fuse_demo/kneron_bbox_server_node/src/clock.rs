@@ -0,0 +1,37 @@
+//! Maps camera timestamps to the host clock.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Estimates the offset from a camera clock to the host clock.
+///
+/// The offset is the minimum observed difference between the receive
+/// time and the camera time, which is the clock offset plus the
+/// shortest transmission delay. Taking the minimum makes the
+/// estimate immune to delayed frames.
+#[derive(Debug, Clone, Default)]
+pub struct CameraClock {
+    offset_nanos: Option<i128>,
+}
+
+impl CameraClock {
+    /// Updates the offset by a frame and returns the corrected
+    /// camera time of the frame.
+    pub fn correct(&mut self, receive_time: SystemTime, camera_time: SystemTime) -> SystemTime {
+        let diff = nanos_since_epoch(receive_time) - nanos_since_epoch(camera_time);
+        let offset = match self.offset_nanos {
+            Some(offset) => offset.min(diff),
+            None => diff,
+        };
+        self.offset_nanos = Some(offset);
+
+        let nanos = nanos_since_epoch(camera_time) + offset;
+        UNIX_EPOCH + Duration::from_nanos(nanos.max(0) as u64)
+    }
+}
+
+fn nanos_since_epoch(time: SystemTime) -> i128 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(dur) => dur.as_nanos() as i128,
+        Err(err) => -(err.duration().as_nanos() as i128),
+    }
+}
@@ -0,0 +1,79 @@
+use kneron_bbox_server::Endian;
+use sensor_diagnostics::DiagnosticsConfig;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Config {
+    pub namespace: String,
+    pub topic: String,
+    /// The address that the server binds to.
+    pub addr: SocketAddr,
+    #[serde(default)]
+    pub transport: Transport,
+    #[serde(default)]
+    pub endian: Endian,
+    /// The TF frame id of detections from cameras without a frame id
+    /// of their own.
+    pub frame_id: String,
+    /// The width and height of the detection image. Boxes are
+    /// clipped to the image.
+    pub image_size: (u32, u32),
+    #[serde(default)]
+    pub clock: ClockSource,
+    #[serde(default)]
+    pub cameras: Vec<CameraConfig>,
+    /// Publish each named camera on the topic `<topic>/<name>`.
+    #[serde(default)]
+    pub topic_per_camera: bool,
+    /// The YAML or JSON5 file listing class names. The COCO class
+    /// names are used if it is not set.
+    #[serde(default)]
+    pub labels: Option<PathBuf>,
+    /// The minimum score of published boxes.
+    #[serde(default)]
+    pub min_score: f32,
+    /// The minimum scores by class names.
+    #[serde(default)]
+    pub class_thresholds: HashMap<String, f32>,
+    /// If not empty, only the boxes of listed classes are published.
+    #[serde(default)]
+    pub allow_classes: Vec<String>,
+    #[serde(default)]
+    pub diagnostics: DiagnosticsConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CameraConfig {
+    pub name: String,
+    pub ip: IpAddr,
+    /// The TF frame id of the camera, overriding the global one.
+    #[serde(default)]
+    pub frame_id: Option<String>,
+}
+
+/// The transport protocol that cameras send frames with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Transport {
+    #[default]
+    Tcp,
+    Udp,
+}
+
+/// The clock that message timestamps are taken from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClockSource {
+    /// The time when the frame is received.
+    #[default]
+    Receive,
+    /// The time recorded with the frame, shifted to the host clock by
+    /// the estimated offset between the clocks. Kneron frames carry no
+    /// timestamp, so it is only accepted with `--replay`.
+    Camera,
+}
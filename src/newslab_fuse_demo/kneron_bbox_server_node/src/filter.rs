@@ -0,0 +1,67 @@
+//! Filters detections by classes and scores before they are
+//! published.
+
+use crate::labels::LabelMap;
+use anyhow::Result;
+use kneron_bbox_server::BoundingBox;
+use std::collections::{HashMap, HashSet};
+
+/// Decides which boxes are published.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClassFilter {
+    /// The minimum score of classes without a specific threshold.
+    min_score: f32,
+    /// The minimum scores by class numbers.
+    thresholds: HashMap<usize, f32>,
+    /// If set, only the listed class numbers are published.
+    allow: Option<HashSet<usize>>,
+}
+
+impl ClassFilter {
+    /// Creates a filter. Class names in thresholds and the
+    /// allow-list are resolved by the label map.
+    pub fn new(
+        labels: &LabelMap,
+        min_score: f32,
+        thresholds: &HashMap<String, f32>,
+        allow: &[String],
+    ) -> Result<Self> {
+        let thresholds = thresholds
+            .iter()
+            .map(|(name, score)| Ok((labels.find(name)?, *score)))
+            .collect::<Result<_>>()?;
+        let allow = if allow.is_empty() {
+            None
+        } else {
+            let allow = allow
+                .iter()
+                .map(|name| labels.find(name))
+                .collect::<Result<_>>()?;
+            Some(allow)
+        };
+
+        Ok(Self {
+            min_score,
+            thresholds,
+            allow,
+        })
+    }
+
+    /// Checks if the box should be published.
+    pub fn accept(&self, bbox: &BoundingBox) -> bool {
+        let class_num = bbox.class_num as usize;
+
+        if let Some(allow) = &self.allow {
+            if !allow.contains(&class_num) {
+                return false;
+            }
+        }
+
+        let min_score = self
+            .thresholds
+            .get(&class_num)
+            .copied()
+            .unwrap_or(self.min_score);
+        bbox.score >= min_score
+    }
+}
@@ -0,0 +1,160 @@
+//! Class label maps that name the class numbers of detections.
+
+use anyhow::{bail, Context, Result};
+use std::{fs, path::Path};
+
+/// The class names of the COCO dataset, which the Kneron YOLO model
+/// is trained on.
+pub const COCO_80_LABELS: [&str; 80] = [
+    "person",
+    "bicycle",
+    "car",
+    "motorcycle",
+    "airplane",
+    "bus",
+    "train",
+    "truck",
+    "boat",
+    "traffic light",
+    "fire hydrant",
+    "stop sign",
+    "parking meter",
+    "bench",
+    "bird",
+    "cat",
+    "dog",
+    "horse",
+    "sheep",
+    "cow",
+    "elephant",
+    "bear",
+    "zebra",
+    "giraffe",
+    "backpack",
+    "umbrella",
+    "handbag",
+    "tie",
+    "suitcase",
+    "frisbee",
+    "skis",
+    "snowboard",
+    "sports ball",
+    "kite",
+    "baseball bat",
+    "baseball glove",
+    "skateboard",
+    "surfboard",
+    "tennis racket",
+    "bottle",
+    "wine glass",
+    "cup",
+    "fork",
+    "knife",
+    "spoon",
+    "bowl",
+    "banana",
+    "apple",
+    "sandwich",
+    "orange",
+    "broccoli",
+    "carrot",
+    "hot dog",
+    "pizza",
+    "donut",
+    "cake",
+    "chair",
+    "couch",
+    "potted plant",
+    "bed",
+    "dining table",
+    "toilet",
+    "tv",
+    "laptop",
+    "mouse",
+    "remote",
+    "keyboard",
+    "cell phone",
+    "microwave",
+    "oven",
+    "toaster",
+    "sink",
+    "refrigerator",
+    "book",
+    "clock",
+    "vase",
+    "scissors",
+    "teddy bear",
+    "hair drier",
+    "toothbrush",
+];
+
+/// Maps class numbers to class names.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LabelMap {
+    names: Vec<String>,
+}
+
+impl Default for LabelMap {
+    fn default() -> Self {
+        Self::coco80()
+    }
+}
+
+impl LabelMap {
+    /// Creates the label map of the COCO dataset.
+    pub fn coco80() -> Self {
+        Self {
+            names: COCO_80_LABELS.iter().map(|name| name.to_string()).collect(),
+        }
+    }
+
+    /// Loads a list of class names from a YAML or JSON5 file. The
+    /// format is chosen by the file extension, and the class number
+    /// of a name is its index in the list.
+    pub fn load<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("unable to read label file {}", path.display()))?;
+
+        let names: Vec<String> = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("yaml" | "yml") => serde_yaml::from_str(&text)?,
+            Some("json5" | "json") => json5::from_str(&text)?,
+            _ => bail!(
+                "unable to recognize the format of label file {}, expect a .yaml or .json5 file",
+                path.display()
+            ),
+        };
+
+        if names.is_empty() {
+            bail!("the label file {} is empty", path.display());
+        }
+
+        Ok(Self { names })
+    }
+
+    /// Returns the number of classes.
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    /// Returns the name of a class number if it is known.
+    pub fn name(&self, class_num: usize) -> Option<&str> {
+        self.names.get(class_num).map(|name| name.as_str())
+    }
+
+    /// Finds the class number by a class name or a class number in
+    /// text.
+    pub fn find(&self, name: &str) -> Result<usize> {
+        if let Some(class_num) = self.names.iter().position(|other| other == name) {
+            return Ok(class_num);
+        }
+
+        match name.parse() {
+            Ok(class_num) if class_num < self.len() => Ok(class_num),
+            _ => bail!("unknown class '{}'", name),
+        }
+    }
+}
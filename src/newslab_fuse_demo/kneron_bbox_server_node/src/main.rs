@@ -1,121 +1,408 @@
 //! Provides a ROS node that starts a server listening to detection
-//! messages from a Kneron camera.
+//! messages from Kneron cameras.
 
-use anyhow::Result;
+mod clock;
+mod config;
+mod filter;
+mod labels;
+
+use anyhow::{ensure, Result};
 use clap::Parser;
-use kneron_bbox_server::{BoundingBox, Server};
+use clock::CameraClock;
+use config::{ClockSource, Config, Transport};
+use filter::ClassFilter;
+use kneron_bbox_server::{
+    BoundingBox, CameraMap, DecodeError, Error, Frame, Pace, RecordError, Recorder, Recording,
+    Server, UdpServer,
+};
+use labels::LabelMap;
 use r2r::{
     builtin_interfaces::msg::Time,
     geometry_msgs::msg::{Point, Pose, Pose2D, PoseWithCovariance, Quaternion},
+    log_info, log_warn,
     std_msgs::msg::Header,
     vision_msgs::msg::{
         BoundingBox2D, Detection2D, Detection2DArray, ObjectHypothesis, ObjectHypothesisWithPose,
     },
-    Context, Node, QosProfile,
+    Context, Node, Publisher, QosProfile,
+};
+use sensor_diagnostics::{spawn_diagnostics, SensorMonitor};
+use std::{
+    collections::HashMap,
+    fs, iter,
+    net::IpAddr,
+    path::PathBuf,
+    process,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
-use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The interval to flush buffered records to the recording file.
+const RECORD_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
 
 #[derive(Debug, Parser)]
 struct Opts {
-    #[clap(long, default_value = "kneron_detection")]
-    pub topic: String,
-    #[clap(long, default_value = "/")]
-    pub namespace: String,
+    #[clap(long)]
+    pub config: String,
+    /// Record received frames to the file.
+    #[clap(long)]
+    pub record: Option<PathBuf>,
+    /// Replay frames from a recording instead of listening to cameras.
+    #[clap(long, conflicts_with = "record")]
+    pub replay: Option<PathBuf>,
+    /// The replay pace, which is 'realtime', 'fastest' or a speed
+    /// factor.
+    #[clap(long, default_value = "realtime")]
+    pub pace: Pace,
 }
 
 fn main() -> Result<()> {
     let opts = Opts::parse();
+    let config: Config = {
+        let text = fs::read_to_string(&opts.config)?;
+        json5::from_str(&text)?
+    };
+
+    // Kneron frames carry no timestamp. Camera times are only known
+    // from recordings.
+    ensure!(
+        config.clock != ClockSource::Camera || opts.replay.is_some(),
+        "\"clock\": \"camera\" requires --replay, since Kneron cameras send no timestamps. \
+         Use \"clock\": \"receive\" instead."
+    );
+
+    // Create health monitors for the server and for each camera.
+    let monitor = Arc::new(SensorMonitor::new(
+        concat!(env!("CARGO_PKG_NAME"), ": detections"),
+        &config.addr.to_string(),
+        &config.diagnostics,
+    ));
+    let camera_monitors: HashMap<IpAddr, Arc<SensorMonitor>> = config
+        .cameras
+        .iter()
+        .map(|camera| {
+            let name = format!("{}: camera {}", env!("CARGO_PKG_NAME"), camera.name);
+            let monitor = SensorMonitor::new(&name, &camera.ip.to_string(), &config.diagnostics);
+            (camera.ip, Arc::new(monitor))
+        })
+        .collect();
+
+    // Start the server listening to Kneron cameras. Frames are paired
+    // with camera times, which are only available in recordings.
+    // Replayed frames are received at the time they are replayed.
+    let cameras: CameraMap = config
+        .cameras
+        .iter()
+        .map(|camera| (camera.ip, camera.name.clone()))
+        .collect();
+    let mut frames: Box<dyn Iterator<Item = Result<(Frame, Option<SystemTime>)>>> = match &opts
+        .replay
+    {
+        Some(path) => {
+            let recording = Recording::open(path)?.with_cameras(cameras);
+            let records = recording.replay(opts.pace).map(|frame| {
+                let frame = frame?;
+                let camera_time = frame.time;
+                let frame = Frame {
+                    time: SystemTime::now(),
+                    ..frame
+                };
+                Ok((frame, Some(camera_time)))
+            });
+            Box::new(records)
+        }
+        None => match config.transport {
+            Transport::Tcp => {
+                let server = Server::bind(config.addr)?
+                    .with_endian(config.endian)
+                    .with_cameras(cameras);
+                let server = Arc::new(server);
+
+                // Report if any camera is connected.
+                thread::spawn({
+                    let server = server.clone();
+                    let monitor = monitor.clone();
+                    let period = config.diagnostics.period();
+                    move || loop {
+                        monitor.set_connected(server.connections() > 0);
+                        thread::sleep(period);
+                    }
+                });
+
+                Box::new(iter::repeat_with(move || server.recv()).map(|frame| Ok((frame?, None))))
+            }
+            Transport::Udp => {
+                let server = UdpServer::bind(config.addr)?
+                    .with_endian(config.endian)
+                    .with_cameras(cameras);
+                Box::new(server.map(|frame| Ok((frame?, None))))
+            }
+        },
+    };
+    let recorder = opts
+        .record
+        .as_ref()
+        .map(Recorder::create)
+        .transpose()?
+        .map(|recorder| Arc::new(Mutex::new(recorder)));
+    if let Some(recorder) = &recorder {
+        // Flush buffered records periodically, even if cameras go
+        // quiet.
+        thread::spawn({
+            let recorder = recorder.clone();
+            move || loop {
+                thread::sleep(RECORD_FLUSH_INTERVAL);
+                if let Err(err) = recorder.lock().unwrap().flush() {
+                    log_warn!(
+                        env!("CARGO_PKG_NAME"),
+                        "Unable to flush the recording: {:#}",
+                        err
+                    );
+                }
+            }
+        });
+
+        // Keep the buffered records when the node is interrupted.
+        ctrlc::set_handler({
+            let recorder = recorder.clone();
+            move || {
+                if let Err(err) = recorder.lock().unwrap().flush() {
+                    log_warn!(
+                        env!("CARGO_PKG_NAME"),
+                        "Unable to flush the recording: {:#}",
+                        err
+                    );
+                }
+                process::exit(130);
+            }
+        })?;
+    }
+
+    // Load class names and the class filter.
+    let labels = match &config.labels {
+        Some(path) => LabelMap::load(path)?,
+        None => LabelMap::coco80(),
+    };
+    let filter = ClassFilter::new(
+        &labels,
+        config.min_score,
+        &config.class_thresholds,
+        &config.allow_classes,
+    )?;
+    let mut class_count_checked = false;
 
-    // Start the server listening to a Kneron camera.
-    let server = Server::new()?;
+    // Set up per-camera frame ids, sequence counters and clocks.
+    let frame_ids: HashMap<IpAddr, String> = config
+        .cameras
+        .iter()
+        .filter_map(|camera| Some((camera.ip, camera.frame_id.clone()?)))
+        .collect();
+    let mut sequences: HashMap<IpAddr, u64> = HashMap::new();
+    let mut clocks: HashMap<IpAddr, CameraClock> = HashMap::new();
 
     // Start a ROS node
     let ctx = Context::create()?;
-    let mut node = Node::create(ctx, env!("CARGO_PKG_NAME"), &opts.namespace)?;
+    let mut node = Node::create(ctx, env!("CARGO_PKG_NAME"), &config.namespace)?;
 
-    // Create a ROS publisher.
+    // Publish diagnostics.
+    let monitors = iter::once(monitor.clone())
+        .chain(camera_monitors.values().cloned())
+        .collect();
+    spawn_diagnostics(&mut node, &config.diagnostics, monitors)?;
+
+    // Create ROS publishers.
     let publisher =
-        node.create_publisher::<Detection2DArray>(&opts.topic, QosProfile::default())?;
-
-    server
-        .enumerate()
-        .try_for_each(|(frame_id, result)| -> Result<()> {
-            let result = result?;
-            let header = {
-                let systime = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
-                Header {
-                    stamp: Time {
-                        sec: systime.as_secs() as i32,
-                        nanosec: systime.subsec_nanos() as u32,
-                    },
-                    frame_id: frame_id.to_string(),
+        node.create_publisher::<Detection2DArray>(&config.topic, QosProfile::default())?;
+    let camera_publishers: HashMap<String, Publisher<Detection2DArray>> = if config.topic_per_camera
+    {
+        config
+            .cameras
+            .iter()
+            .map(|camera| {
+                let topic = format!("{}/{}", config.topic, camera.name);
+                let publisher =
+                    node.create_publisher::<Detection2DArray>(&topic, QosProfile::default())?;
+                anyhow::Ok((camera.name.clone(), publisher))
+            })
+            .collect::<Result<_>>()?
+    } else {
+        HashMap::new()
+    };
+
+    let result = frames.try_for_each(|frame| -> Result<()> {
+        let (frame, camera_time) = match frame {
+            Ok(frame) => frame,
+            Err(err) if matches!(err.downcast_ref::<Error>(), Some(Error::Stopped)) => {
+                return Err(err)
+            }
+            Err(err) => {
+                if err.downcast_ref::<DecodeError>().is_some() {
+                    monitor.count_decode_error();
+                } else if err.downcast_ref::<Error>().is_some()
+                    || err.downcast_ref::<RecordError>().is_some()
+                {
+                    monitor.count_invalid();
+                } else {
+                    monitor.count_dropped();
                 }
+
+                log_warn!(env!("CARGO_PKG_NAME"), "Drop a frame: {:#}", err);
+                return Ok(());
+            }
+        };
+        if let Some(recorder) = &recorder {
+            recorder.lock().unwrap().write(&frame)?;
+        }
+
+        let Frame {
+            source,
+            time: receive_time,
+            result,
+        } = frame;
+        let ip = source.addr.ip();
+
+        monitor.bump();
+        if let Some(camera_monitor) = camera_monitors.get(&ip) {
+            camera_monitor.bump();
+        }
+
+        if !class_count_checked && result.class_count() != labels.len() {
+            log_warn!(
+                env!("CARGO_PKG_NAME"),
+                "The camera reports {} classes, but {} class names are loaded",
+                result.class_count(),
+                labels.len()
+            );
+            class_count_checked = true;
+        }
+
+        let seq = {
+            let seq = sequences.entry(ip).or_insert(0);
+            let curr = *seq;
+            *seq += 1;
+            curr
+        };
+
+        let header = {
+            let stamp = match (config.clock, camera_time) {
+                (ClockSource::Camera, Some(camera_time)) => clocks
+                    .entry(ip)
+                    .or_default()
+                    .correct(receive_time, camera_time),
+                _ => receive_time,
             };
+            let systime = stamp.duration_since(UNIX_EPOCH).unwrap();
+            Header {
+                stamp: Time {
+                    sec: systime.as_secs() as i32,
+                    nanosec: systime.subsec_nanos() as u32,
+                },
+                frame_id: frame_ids.get(&ip).unwrap_or(&config.frame_id).to_string(),
+            }
+        };
 
-            let detections: Vec<_> = result
-                .boxes()
-                .iter()
-                .map(|ibbox| {
-                    let BoundingBox {
-                        x1,
-                        y1,
-                        x2,
-                        y2,
-                        score,
-                        class_num,
-                    } = *ibbox;
-                    let size_x = x2 as f64 - x1 as f64;
-                    let size_y = y2 as f64 - y1 as f64;
-                    let cx = x1 as f64 + size_x / 2.0;
-                    let cy = y1 as f64 + size_y / 2.0;
-
-                    Detection2D {
-                        header: header.clone(),
-                        results: vec![ObjectHypothesisWithPose {
-                            hypothesis: ObjectHypothesis {
-                                class_id: class_num.to_string(),
-                                score: score as f64,
-                            },
-                            pose: PoseWithCovariance {
-                                pose: Pose {
-                                    position: Point {
-                                        x: cx,
-                                        y: cy,
-                                        z: 0.0,
-                                    },
-                                    orientation: Quaternion {
-                                        x: 0.0,
-                                        y: 0.0,
-                                        z: 0.0,
-                                        w: 1.0,
-                                    },
+        let detections: Vec<_> = result
+            .boxes()
+            .iter()
+            .filter(|ibbox| filter.accept(ibbox))
+            .filter_map(|ibbox| clip_box(ibbox, config.image_size))
+            .enumerate()
+            .map(|(index, ibbox)| {
+                let BoundingBox {
+                    x1,
+                    y1,
+                    x2,
+                    y2,
+                    score,
+                    class_num,
+                } = ibbox;
+                let size_x = x2 as f64 - x1 as f64;
+                let size_y = y2 as f64 - y1 as f64;
+                let cx = x1 as f64 + size_x / 2.0;
+                let cy = y1 as f64 + size_y / 2.0;
+
+                Detection2D {
+                    header: header.clone(),
+                    results: vec![ObjectHypothesisWithPose {
+                        hypothesis: ObjectHypothesis {
+                            class_id: labels
+                                .name(class_num as usize)
+                                .map(|name| name.to_string())
+                                .unwrap_or_else(|| class_num.to_string()),
+                            score: score as f64,
+                        },
+                        pose: PoseWithCovariance {
+                            pose: Pose {
+                                position: Point {
+                                    x: cx,
+                                    y: cy,
+                                    z: 0.0,
+                                },
+                                orientation: Quaternion {
+                                    x: 0.0,
+                                    y: 0.0,
+                                    z: 0.0,
+                                    w: 1.0,
                                 },
-                                covariance: identity_covariance(),
-                            },
-                        }],
-                        bbox: BoundingBox2D {
-                            center: Pose2D {
-                                x: cx,
-                                y: cy,
-                                theta: 0.0,
                             },
-                            size_x,
-                            size_y,
+                            covariance: identity_covariance(),
                         },
-                        id: "".to_string(),
-                    }
-                })
-                .collect();
-            let msg = Detection2DArray { header, detections };
+                    }],
+                    bbox: BoundingBox2D {
+                        center: Pose2D {
+                            x: cx,
+                            y: cy,
+                            theta: 0.0,
+                        },
+                        size_x,
+                        size_y,
+                    },
+                    // The id is in the form <frame sequence>-<box index>.
+                    id: format!("{}-{}", seq, index),
+                }
+            })
+            .collect();
+        let msg = Detection2DArray { header, detections };
 
-            publisher.publish(&msg)?;
+        let publisher = source
+            .camera_id
+            .as_ref()
+            .and_then(|camera_id| camera_publishers.get(camera_id))
+            .unwrap_or(&publisher);
+        publisher.publish(&msg)?;
 
-            Ok(())
-        })?;
+        Ok(())
+    });
 
+    // Keep the buffered records when the node stops.
+    if let Some(recorder) = &recorder {
+        recorder.lock().unwrap().flush()?;
+    }
+    result?;
+
+    log_info!(env!("CARGO_PKG_NAME"), "The replay is finished");
     Ok(())
 }
 
+/// Clips the box to the image of size (width, height). It returns
+/// `None` if the box is completely outside the image.
+fn clip_box(bbox: &BoundingBox, (width, height): (u32, u32)) -> Option<BoundingBox> {
+    let (width, height) = (width as f32, height as f32);
+    let x1 = bbox.x1.clamp(0.0, width);
+    let y1 = bbox.y1.clamp(0.0, height);
+    let x2 = bbox.x2.clamp(0.0, width);
+    let y2 = bbox.y2.clamp(0.0, height);
+
+    (x1 < x2 && y1 < y2).then(|| BoundingBox {
+        x1,
+        y1,
+        x2,
+        y2,
+        ..bbox.clone()
+    })
+}
+
 /// Generated a flattened 6x6 identity matrix
 fn identity_covariance() -> Vec<f64> {
     let mut matrix = vec![0f64; 36];
@@ -0,0 +1,116 @@
+//! Emulates a Kneron camera that connects to the bbox server and
+//! sends detection frames, either replayed from a script file or
+//! generated on the fly.
+
+mod script;
+mod synthetic;
+
+use anyhow::Result;
+use clap::{Parser, Subcommand};
+use kneron_bbox_server::{Endian, YoloResult};
+use std::{
+    io::prelude::*,
+    net::{SocketAddr, TcpStream},
+    path::PathBuf,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// The number of classes of the COCO-trained Kneron YOLO model.
+const COCO_CLASS_COUNT: u32 = 80;
+
+#[derive(Debug, Parser)]
+struct Opts {
+    #[clap(long, default_value = "127.0.0.1:8700", help = "Server address.")]
+    pub addr: SocketAddr,
+    #[clap(long, default_value = "little", help = "Byte order of sent frames.")]
+    pub endian: Endian,
+    #[clap(subcommand)]
+    pub source: Source,
+}
+
+#[derive(Debug, Subcommand)]
+enum Source {
+    /// Replays frames from a JSON5 or CSV script.
+    Script {
+        #[clap(long)]
+        file: PathBuf,
+        #[clap(long, help = "Restart the script after the last frame.")]
+        repeat: bool,
+    },
+    /// Generates boxes moving across the image.
+    Synthetic {
+        #[clap(long, default_value = "10", help = "Frame rate in Hz.")]
+        rate: f64,
+        #[clap(long, default_value = "3")]
+        num_boxes: usize,
+        #[clap(long, default_value = "1280")]
+        width: f32,
+        #[clap(long, default_value = "960")]
+        height: f32,
+        #[clap(long, help = "Stop after sending this number of frames.")]
+        count: Option<usize>,
+    },
+}
+
+fn main() -> Result<()> {
+    let opts = Opts::parse();
+
+    let mut stream = TcpStream::connect(opts.addr)?;
+    eprintln!("Connected to server {}", opts.addr);
+
+    match opts.source {
+        Source::Script { file, repeat } => {
+            let frames = script::load(&file)?;
+
+            loop {
+                send_frames(&mut stream, opts.endian, frames.iter().cloned())?;
+                if !repeat {
+                    break;
+                }
+            }
+        }
+        Source::Synthetic {
+            rate,
+            num_boxes,
+            width,
+            height,
+            count,
+        } => {
+            let frames = synthetic::frames(rate, num_boxes, [width, height])?;
+
+            match count {
+                Some(count) => send_frames(&mut stream, opts.endian, frames.take(count))?,
+                None => send_frames(&mut stream, opts.endian, frames)?,
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Sends frames to the server, each at its time offset from the
+/// first frame.
+fn send_frames<I>(stream: &mut TcpStream, endian: Endian, frames: I) -> Result<()>
+where
+    I: IntoIterator<Item = (Duration, YoloResult)>,
+{
+    let start = Instant::now();
+
+    for (offset, result) in frames {
+        let until = start + offset;
+        let now = Instant::now();
+        if now < until {
+            thread::sleep(until - now);
+        }
+
+        stream.write_all(&result.to_bytes(endian))?;
+        eprintln!(
+            "Sent a frame with {} boxes at {:.3}s",
+            result.box_count(),
+            offset.as_secs_f64()
+        );
+    }
+
+    Ok(())
+}
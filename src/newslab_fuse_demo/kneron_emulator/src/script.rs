@@ -0,0 +1,279 @@
+//! Loads detection scripts from JSON5 or CSV files.
+
+use crate::COCO_CLASS_COUNT;
+use anyhow::{bail, ensure, Context, Result};
+use kneron_bbox_server::{BoundingBox, YoloResult};
+use serde::Deserialize;
+use std::{fs, path::Path, time::Duration};
+
+/// A frame in a JSON5 script.
+#[derive(Debug, Clone, Deserialize)]
+struct ScriptFrame {
+    /// The time offset from the first frame in seconds.
+    pub time: f64,
+    #[serde(default = "default_class_count")]
+    pub class_count: u32,
+    #[serde(default)]
+    pub boxes: Vec<ScriptBox>,
+}
+
+/// A box in a JSON5 script.
+#[derive(Debug, Clone, Deserialize)]
+struct ScriptBox {
+    pub x1: f32,
+    pub y1: f32,
+    pub x2: f32,
+    pub y2: f32,
+    pub score: f32,
+    pub class_num: u32,
+}
+
+impl From<ScriptBox> for BoundingBox {
+    fn from(from: ScriptBox) -> Self {
+        let ScriptBox {
+            x1,
+            y1,
+            x2,
+            y2,
+            score,
+            class_num,
+        } = from;
+
+        Self {
+            x1,
+            y1,
+            x2,
+            y2,
+            score,
+            class_num,
+        }
+    }
+}
+
+/// Loads a script and returns frames along with their time offsets.
+///
+/// Files with the `.csv` extension are parsed as CSV. Otherwise they
+/// are parsed as JSON5.
+pub fn load(path: &Path) -> Result<Vec<(Duration, YoloResult)>> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("unable to read script {}", path.display()))?;
+    let is_csv = path.extension().and_then(|ext| ext.to_str()) == Some("csv");
+    parse(&text, is_csv)
+}
+
+/// Parses a CSV or JSON5 script.
+fn parse(text: &str, is_csv: bool) -> Result<Vec<(Duration, YoloResult)>> {
+    let frames = if is_csv {
+        parse_csv(text)?
+    } else {
+        json5::from_str(text)?
+    };
+
+    to_results(frames)
+}
+
+/// Converts script frames to Kneron frames.
+fn to_results(frames: Vec<ScriptFrame>) -> Result<Vec<(Duration, YoloResult)>> {
+    ensure!(!frames.is_empty(), "the script has no frames");
+    let mut prev_time = 0.0;
+
+    frames
+        .into_iter()
+        .enumerate()
+        .map(|(index, frame)| {
+            let ScriptFrame {
+                time,
+                class_count,
+                boxes,
+            } = frame;
+
+            ensure!(
+                time.is_finite() && time >= prev_time,
+                "the time of frame {} must be finite and non-decreasing",
+                index
+            );
+            prev_time = time;
+
+            if let Some(bbox) = boxes.iter().find(|bbox| bbox.class_num >= class_count) {
+                bail!(
+                    "frame {} has class_num {}, but the class count is {}",
+                    index,
+                    bbox.class_num,
+                    class_count
+                );
+            }
+
+            let result = YoloResult::builder()
+                .class_count(class_count)
+                .boxes(boxes.into_iter().map(BoundingBox::from))
+                .build()
+                .with_context(|| format!("frame {} is invalid", index))?;
+
+            Ok((Duration::from_secs_f64(time), result))
+        })
+        .collect()
+}
+
+/// Parses a CSV script.
+///
+/// The file starts with the header `time,x1,y1,x2,y2,score,class_num`.
+/// Each row describes a box and consecutive rows with the same time
+/// form a frame. A row with only the time column is a frame without
+/// boxes.
+fn parse_csv(text: &str) -> Result<Vec<ScriptFrame>> {
+    const HEADER: [&str; 7] = ["time", "x1", "y1", "x2", "y2", "score", "class_num"];
+
+    let mut lines = text
+        .lines()
+        .enumerate()
+        .map(|(idx, line)| (idx + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty());
+
+    match lines.next() {
+        Some((_, header)) => {
+            let header: Vec<_> = header.split(',').map(str::trim).collect();
+            ensure!(
+                header == HEADER,
+                "the CSV header must be '{}'",
+                HEADER.join(",")
+            );
+        }
+        None => bail!("the CSV script is empty"),
+    }
+
+    let mut frames: Vec<ScriptFrame> = vec![];
+
+    for (lineno, line) in lines {
+        let cols: Vec<_> = line.split(',').map(str::trim).collect();
+        ensure!(
+            cols.len() == HEADER.len(),
+            "line {}: expect {} columns, but found {}",
+            lineno,
+            HEADER.len(),
+            cols.len()
+        );
+
+        let time: f64 = cols[0]
+            .parse()
+            .with_context(|| format!("line {}: invalid time", lineno))?;
+        let bbox = if cols[1..].iter().all(|col| col.is_empty()) {
+            None
+        } else {
+            let parse_f32 = |idx: usize| -> Result<f32> {
+                cols[idx]
+                    .parse()
+                    .with_context(|| format!("line {}: invalid {}", lineno, HEADER[idx]))
+            };
+
+            Some(ScriptBox {
+                x1: parse_f32(1)?,
+                y1: parse_f32(2)?,
+                x2: parse_f32(3)?,
+                y2: parse_f32(4)?,
+                score: parse_f32(5)?,
+                class_num: cols[6]
+                    .parse()
+                    .with_context(|| format!("line {}: invalid class_num", lineno))?,
+            })
+        };
+
+        match frames.last_mut() {
+            Some(frame) if frame.time == time => frame.boxes.extend(bbox),
+            _ => frames.push(ScriptFrame {
+                time,
+                class_count: COCO_CLASS_COUNT,
+                boxes: bbox.into_iter().collect(),
+            }),
+        }
+    }
+
+    Ok(frames)
+}
+
+fn default_class_count() -> u32 {
+    COCO_CLASS_COUNT
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CSV_HEADER: &str = "time,x1,y1,x2,y2,score,class_num";
+
+    #[test]
+    fn parse_csv_frames() {
+        let text = format!(
+            "{}\n\
+             0.0,10,20,30,40,0.5,1\n\
+             0.0,50,60,70,80,0.75,2\n\
+             0.1,,,,,,\n\
+             0.2,10,20,30,40,0.5,0\n",
+            CSV_HEADER
+        );
+        let frames = parse(&text, true).unwrap();
+
+        let summary: Vec<_> = frames
+            .iter()
+            .map(|(time, result)| (time.as_millis(), result.box_count()))
+            .collect();
+        assert_eq!(summary, [(0, 2), (100, 0), (200, 1)]);
+        assert_eq!(frames[0].1.boxes()[1].class_num, 2);
+    }
+
+    #[test]
+    fn parse_json5_frames() {
+        let text = r#"[
+            { time: 0.0, boxes: [{ x1: 10, y1: 20, x2: 30, y2: 40, score: 0.5, class_num: 1 }] },
+            { time: 0.5, class_count: 3 },
+        ]"#;
+        let frames = parse(text, false).unwrap();
+
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].1.class_count(), COCO_CLASS_COUNT as usize);
+        assert_eq!(frames[1].0, Duration::from_millis(500));
+        assert_eq!(frames[1].1.class_count(), 3);
+        assert_eq!(frames[1].1.box_count(), 0);
+    }
+
+    #[test]
+    fn reject_malformed_csv_rows() {
+        for row in [
+            "0.0,10,20,30,40,0.5",
+            "0.0,10,20,30,40,0.5,1,2",
+            "zero,10,20,30,40,0.5,1",
+            "0.0,10,twenty,30,40,0.5,1",
+            "0.0,10,20,30,40,0.5,-1",
+            "0.0,30,20,10,40,0.5,1",
+        ] {
+            let text = format!("{}\n{}\n", CSV_HEADER, row);
+            assert!(parse(&text, true).is_err(), "{} is accepted", row);
+        }
+
+        let text = "time,x1,y1,x2,y2,class_num,score\n0.0,10,20,30,40,1,0.5\n";
+        assert!(parse(text, true).is_err());
+    }
+
+    #[test]
+    fn reject_decreasing_times() {
+        let text = format!("{}\n0.2,,,,,,\n0.1,,,,,,\n", CSV_HEADER);
+        assert!(parse(&text, true).is_err());
+    }
+
+    #[test]
+    fn reject_empty_scripts() {
+        assert!(parse("", true).is_err());
+        assert!(parse(CSV_HEADER, true).is_err());
+        assert!(parse("[]", false).is_err());
+    }
+
+    #[test]
+    fn reject_out_of_range_class_ids() {
+        let text = format!("{}\n0.0,10,20,30,40,0.5,80\n", CSV_HEADER);
+        assert!(parse(&text, true).is_err());
+
+        let text = r#"[
+            { time: 0.0, class_count: 2, boxes: [{ x1: 10, y1: 20, x2: 30, y2: 40, score: 0.5, class_num: 2 }] },
+        ]"#;
+        assert!(parse(text, false).is_err());
+    }
+}
@@ -0,0 +1,114 @@
+//! Generates synthetic boxes moving across the image.
+
+use crate::COCO_CLASS_COUNT;
+use anyhow::{ensure, Result};
+use kneron_bbox_server::{BoundingBox, YoloResult, BOXES_MAX_NUM};
+use std::time::Duration;
+
+/// The speed of boxes in image widths (or heights) per second.
+const SPEED: f32 = 0.2;
+
+/// Creates an infinite sequence of frames at `rate` Hz.
+///
+/// Each box bounces between the image borders with its own phase and
+/// direction. The class number of the `i`-th box is `i`.
+pub fn frames(
+    rate: f64,
+    num_boxes: usize,
+    [width, height]: [f32; 2],
+) -> Result<impl Iterator<Item = (Duration, YoloResult)>> {
+    // Reject rates whose frame interval does not fit in a Duration.
+    ensure!(
+        rate.is_finite() && rate > 0.0 && 1.0 / rate <= u32::MAX as f64,
+        "the frame rate must be a positive number, but get {}",
+        rate
+    );
+    ensure!(
+        num_boxes <= BOXES_MAX_NUM,
+        "the number of boxes must not exceed {}",
+        BOXES_MAX_NUM
+    );
+    ensure!(
+        width > 0.0 && height > 0.0,
+        "the image size must be positive"
+    );
+
+    let box_w = width / 6.0;
+    let box_h = height / 6.0;
+    let period = Duration::from_secs_f64(1.0 / rate);
+
+    let iter = (0u32..).map(move |index| {
+        let offset = period * index;
+        let secs = offset.as_secs_f32();
+
+        let boxes = (0..num_boxes).map(|nth| {
+            let phase = nth as f32 / num_boxes as f32;
+            let x1 = triangle_wave(secs * SPEED + phase) * (width - box_w);
+            let y1 = triangle_wave(secs * SPEED * 0.5 + phase * 2.0) * (height - box_h);
+
+            BoundingBox {
+                x1,
+                y1,
+                x2: x1 + box_w,
+                y2: y1 + box_h,
+                score: 0.5 + 0.5 * triangle_wave(secs + phase),
+                class_num: nth as u32,
+            }
+        });
+
+        let result = YoloResult::builder()
+            .class_count(COCO_CLASS_COUNT)
+            .boxes(boxes)
+            .build()
+            .unwrap();
+
+        (offset, result)
+    });
+
+    Ok(iter)
+}
+
+/// A triangle wave with period 1 that ranges in [0, 1].
+fn triangle_wave(t: f32) -> f32 {
+    let frac = t.rem_euclid(1.0);
+    1.0 - (2.0 * frac - 1.0).abs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reject_invalid_rates() {
+        for rate in [0.0, -10.0, 1e-300, f64::NAN, f64::INFINITY] {
+            assert!(
+                frames(rate, 1, [640.0, 480.0]).is_err(),
+                "{} is accepted",
+                rate
+            );
+        }
+    }
+
+    #[test]
+    fn generate_boxes_in_image() {
+        let [width, height] = [640.0, 480.0];
+        let frames: Vec<_> = frames(10.0, 4, [width, height])
+            .unwrap()
+            .take(100)
+            .collect();
+
+        for (index, (offset, result)) in frames.iter().enumerate() {
+            assert_eq!(*offset, Duration::from_millis(100) * index as u32);
+            assert_eq!(result.class_count(), COCO_CLASS_COUNT as usize);
+
+            let class_nums: Vec<_> = result.boxes().iter().map(|bbox| bbox.class_num).collect();
+            assert_eq!(class_nums, [0, 1, 2, 3]);
+
+            for bbox in result.boxes() {
+                assert!(0.0 <= bbox.x1 && bbox.x1 < bbox.x2 && bbox.x2 <= width);
+                assert!(0.0 <= bbox.y1 && bbox.y1 < bbox.y2 && bbox.y2 <= height);
+                assert!((0.5..=1.0).contains(&bbox.score));
+            }
+        }
+    }
+}
@@ -0,0 +1,148 @@
+//! Draws bboxes and projected points on camera views. It is shared by
+//! the OpenCV GUI and the image outputs.
+
+use crate::{color_sampling::sample_rgb, config::CameraConfig, message as msg};
+use anyhow::Result;
+use nalgebra as na;
+use opencv::{
+    core::{Point2f, Point2i, Scalar, CV_8UC3},
+    imgproc::{self, FONT_HERSHEY_SIMPLEX, LINE_8},
+    prelude::*,
+};
+use palette::{Hsv, IntoColor, RgbHue, Srgb};
+use rayon::prelude::*;
+use std::{collections::HashMap, ops::RangeInclusive};
+
+/// Colors projected points of a camera. Points in bboxes are colored
+/// by classes. Other points are gray on cameras giving detections, or
+/// are colored by distances otherwise.
+pub struct PointPainter {
+    distance_range: RangeInclusive<f32>,
+    hue_range: [f32; 2],
+    has_detections: bool,
+}
+
+impl PointPainter {
+    pub fn new(config: &CameraConfig) -> Self {
+        let [min, max] = config.distance_range;
+
+        Self {
+            distance_range: min..=max,
+            hue_range: config.hue_range,
+            has_detections: config.det_topic.is_some(),
+        }
+    }
+
+    /// Returns the RGB color of the point, or `None` if it is out of
+    /// the distance range.
+    pub fn color(&self, assoc: &msg::Association) -> Option<[f64; 3]> {
+        let distance = na::distance(&na::Point3::origin(), &assoc.pcd_point.position);
+        if !self.distance_range.contains(&distance) {
+            return None;
+        }
+
+        let rgb = match assoc.object.as_deref() {
+            Some(msg::Object {
+                class_id: Some(class_id),
+                ..
+            }) => sample_rgb(class_id),
+            _ if self.has_detections => [0.5, 0.5, 0.5],
+            _ => self.distance_rgb(distance),
+        };
+        Some(rgb)
+    }
+
+    /// Draws points on the canvas. Points falling on the same pixel
+    /// are drawn once.
+    pub fn draw_points(&self, canvas: &mut Mat, assocs: &[msg::Association]) -> Result<()> {
+        let pixels: HashMap<_, _> = assocs
+            .par_iter()
+            .filter_map(|assoc| {
+                let color = to_scalar(self.color(assoc)?);
+                let center = to_pixel(&assoc.img_point);
+                Some(([center.x, center.y], (center, color)))
+            })
+            .collect();
+
+        for (center, color) in pixels.into_values() {
+            imgproc::circle(
+                canvas, center, 1, // radius
+                color, 1, // thickness
+                LINE_8, 0, // shift
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn distance_rgb(&self, distance: f32) -> [f64; 3] {
+        let dist_min = *self.distance_range.start();
+        let dist_max = *self.distance_range.end();
+        let [hue_min, hue_max] = self.hue_range;
+        let ratio = (distance - dist_min) / (dist_max - dist_min);
+        let hue = RgbHue::from_degrees(ratio * (hue_max - hue_min) + hue_min);
+        let rgb: Srgb = Hsv::new(hue, 1.0, 1.0).into_color();
+        let (r, g, b) = rgb.into_components();
+        [r as f64, g as f64, b as f64]
+    }
+}
+
+/// Draws bboxes colored by classes, optionally with class names above.
+pub fn draw_objects(
+    canvas: &mut Mat,
+    objects: &[msg::Object],
+    thickness: i32,
+    with_labels: bool,
+) -> Result<()> {
+    for object in objects {
+        let color = to_scalar(class_rgb(object));
+
+        imgproc::rectangle(
+            canvas,
+            object.rect,
+            color,
+            thickness,
+            LINE_8,
+            0, // shift
+        )?;
+
+        if let (true, Some(class_id)) = (with_labels, &object.class_id) {
+            imgproc::put_text(
+                canvas,
+                class_id,
+                Point2i::new(object.rect.x, object.rect.y - 5),
+                FONT_HERSHEY_SIMPLEX,
+                0.8, // font_scale
+                color,
+                2, // thickness
+                LINE_8,
+                false, // bottom_left_origin
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Creates a black BGR canvas.
+pub fn make_canvas([h, w]: [usize; 2]) -> Result<Mat> {
+    let mat = Mat::new_rows_cols_with_default(h as i32, w as i32, CV_8UC3, Scalar::all(0.0))?;
+    Ok(mat)
+}
+
+/// The color of the class, or white if the class is unknown.
+pub fn class_rgb(object: &msg::Object) -> [f64; 3] {
+    match &object.class_id {
+        Some(class_id) => sample_rgb(class_id),
+        None => [1.0, 1.0, 1.0],
+    }
+}
+
+/// Converts an RGB color in [0, 1] to a BGR scalar in [0, 255].
+pub fn to_scalar([r, g, b]: [f64; 3]) -> Scalar {
+    Scalar::new(b * 255.0, g * 255.0, r * 255.0, 0.0)
+}
+
+pub fn to_pixel(point: &Point2f) -> Point2i {
+    Point2i::new(point.x.round() as i32, point.y.round() as i32)
+}
@@ -1,23 +1,26 @@
+mod v0_1;
+
 use crate::yaml_loader::YamlPath;
-use anyhow::Result;
+use anyhow::{Context as _, Result};
 // use cv_convert::{OpenCvPose, TryIntoCv};
 use itertools::Itertools;
 use nalgebra as na;
 use noisy_float::prelude::*;
 use opencv::prelude::*;
+use sensor_diagnostics::DiagnosticsConfig;
 use serde::{de::Error as _, Deserialize, Deserializer};
 use serde_loader::Json5Path;
 use serde_semver::SemverReq;
 // use slice_of_array::prelude::*;
-use std::{mem, num::NonZeroUsize, ops::RangeInclusive};
+use std::{collections::HashSet, mem, num::NonZeroUsize, ops::RangeInclusive, path::Path};
 
 /// Version marker type.
 #[derive(Debug, Clone, SemverReq)]
-#[version("0.1.0")]
+#[version("0.2.0")]
 pub struct Version;
 
 /// The type defines the configuration file format.
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone)]
 pub struct Config {
     /// Config format version.
     pub version: Version,
@@ -29,56 +32,254 @@ pub struct Config {
     pub pcd_topic: String,
     pub pcd_roi: PointCloudRoi,
 
+    /// The cameras fused with the point cloud.
+    pub cameras: Vec<CameraConfig>,
+
+    /// Pair point clouds with camera messages by header stamps. The
+    /// newest messages are fused regardless of stamps if it is not set.
+    pub sync: Option<SyncConfig>,
+
+    /// ROS topics to publish the fusion results besides the ones of
+    /// each camera.
+    pub outputs: OutputConfig,
+
+    /// Thresholds of diagnostics.
+    pub diagnostics: DiagnosticsConfig,
+}
+
+impl Config {
+    /// Loads a config file. Files of version 0.1.0 are migrated to the
+    /// current version.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let VersionOnly { version } = Json5Path::open_and_take(path)
+            .with_context(|| format!("{} must be of version 0.2 or 0.1", path.display()))?;
+
+        let config = match version {
+            AnyVersion::Current(_) => Json5Path::open_and_take(path)?,
+            AnyVersion::V0_1(_) => {
+                let config: v0_1::Config = Json5Path::open_and_take(path)?;
+                config.into()
+            }
+        };
+        Ok(config)
+    }
+}
+
+impl<'de> Deserialize<'de> for Config {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let UncheckedConfig {
+            version,
+            namespace,
+            pcd_topic,
+            pcd_roi,
+            cameras,
+            sync,
+            outputs,
+            diagnostics,
+        } = UncheckedConfig::deserialize(deserializer)?;
+
+        if cameras.is_empty() {
+            return Err(D::Error::custom("at least one camera is required"));
+        }
+
+        let mut names = HashSet::new();
+        for camera in &cameras {
+            if !names.insert(&camera.name) {
+                return Err(D::Error::custom(format!(
+                    "camera name '{}' is used more than once",
+                    camera.name
+                )));
+            }
+            if camera.image_topic.is_none() && camera.det_topic.is_none() {
+                return Err(D::Error::custom(format!(
+                    "camera '{}' requires image_topic or det_topic",
+                    camera.name
+                )));
+            }
+        }
+
+        Ok(Self {
+            version,
+            namespace,
+            pcd_topic,
+            pcd_roi,
+            cameras,
+            sync,
+            outputs,
+            diagnostics,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct UncheckedConfig {
+    version: Version,
+    namespace: String,
+    pcd_topic: String,
+    pcd_roi: PointCloudRoi,
+    cameras: Vec<CameraConfig>,
+    #[serde(default)]
+    sync: Option<SyncConfig>,
+    #[serde(default)]
+    outputs: OutputConfig,
+    #[serde(default)]
+    diagnostics: DiagnosticsConfig,
+}
+
+/// Reads the version of a config file of any version.
+#[derive(Deserialize)]
+struct VersionOnly {
+    version: AnyVersion,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AnyVersion {
+    Current(Version),
+    V0_1(v0_1::Version),
+}
+
+/// A camera giving images, detections or both.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CameraConfig {
+    /// The name in window titles and diagnostics.
+    pub name: String,
+
+    /// Input topic for images.
+    #[serde(default)]
+    pub image_topic: Option<String>,
+    /// Subscribes JPEG or PNG images of `sensor_msgs/CompressedImage`
+    /// on `image_topic` instead.
+    #[serde(default)]
+    pub image_compressed: bool,
     /// Input topic for 2D detected objects.
-    pub kneron_det_topic: String,
-    /// The intrinsic parameters file.
-    pub kneron_intrinsics_file: YamlPath<MrptCalibration>,
-    /// The extrinsic parameters file.
-    kneron_extrinsics_file: Json5Path<na::Isometry3<f64>>,
-    pub kneron_det_present_size: usize,
-    pub kneron_fused_present_size: usize,
-    pub kneron_image_hw: [NonZeroUsize; 2],
-    pub kneron_det_hw: [NonZeroUsize; 2],
-    pub kneron_pcd_rotate_90: bool,
-    pub kneron_image_roi_tlbr: [usize; 4],
-    pub kneron_distance_range: [f32; 2],
-
-    /// Input topic for image.
-    pub otobrite_img_topic: String,
-    pub otobrite_image_hw: [NonZeroUsize; 2],
-    pub otobrite_image_roi_tlbr: [usize; 4],
-    pub otobrite_image_rotate_180: bool,
-    pub otobrite_pcd_rotate_90: bool,
-    pub otobrite_raw_present_size: usize,
-    pub otobrite_fused_present_size: usize,
-    pub otobrite_distance_range: [f32; 2],
-    pub otobrite_hue_range: [f32; 2],
+    #[serde(default)]
+    pub det_topic: Option<String>,
+
+    pub image_hw: [NonZeroUsize; 2],
+    /// The size of detection coordinates. It is `image_hw` if not set.
+    #[serde(default)]
+    det_hw: Option<[NonZeroUsize; 2]>,
+    #[serde(default)]
+    pub image_rotate_180: bool,
+    #[serde(default)]
+    pub pcd_rotate_90: bool,
     /// The intrinsic parameters file.
-    pub otobrite_intrinsics_file: YamlPath<MrptCalibration>,
+    pub intrinsics_file: YamlPath<MrptCalibration>,
     /// The extrinsic parameters file.
-    otobrite_extrinsics_file: Json5Path<na::Isometry3<f64>>,
+    extrinsics_file: Json5Path<na::Isometry3<f64>>,
+
+    /// Points within the distance range are drawn.
+    pub distance_range: [f32; 2],
+    /// Points outside bboxes are colored by distances in the hue range
+    /// on cameras without detections.
+    #[serde(default = "default_hue_range")]
+    pub hue_range: [f32; 2],
+    pub image_roi_tlbr: [usize; 4],
+    /// The window size of the image or bboxes.
+    pub raw_present_size: usize,
+    /// The window size of the fused view.
+    pub fused_present_size: usize,
+
+    /// ROS topics to publish the fusion results of the camera.
+    #[serde(default)]
+    pub outputs: CameraOutputConfig,
 }
 
-impl Config {
-    pub fn otobrite_pose(&self) -> na::Isometry3<f64> {
-        if self.otobrite_pcd_rotate_90 {
-            *self.otobrite_extrinsics_file
+impl CameraConfig {
+    pub fn pose(&self) -> na::Isometry3<f64> {
+        if self.pcd_rotate_90 {
+            *self.extrinsics_file
                 * na::UnitQuaternion::from_euler_angles(0.0, 0.0, 90.0.to_radians())
         } else {
-            *self.otobrite_extrinsics_file
+            *self.extrinsics_file
         }
     }
 
-    pub fn kneron_pose(&self) -> na::Isometry3<f64> {
-        if self.kneron_pcd_rotate_90 {
-            *self.kneron_extrinsics_file
-                * na::UnitQuaternion::from_euler_angles(0.0, 0.0, 90.0.to_radians())
-        } else {
-            *self.kneron_extrinsics_file
+    pub fn det_hw(&self) -> [NonZeroUsize; 2] {
+        self.det_hw.unwrap_or(self.image_hw)
+    }
+}
+
+fn default_hue_range() -> [f32; 2] {
+    [30.0, 300.0]
+}
+
+/// The approximate time synchronization parameters.
+#[derive(Debug, Clone)]
+pub struct SyncConfig {
+    /// A point cloud waits for camera messages at most this many
+    /// seconds of newer stamps.
+    pub window_secs: f64,
+    /// The maximum stamp difference in seconds of paired messages.
+    pub tolerance_secs: f64,
+}
+
+impl<'de> Deserialize<'de> for SyncConfig {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let UncheckedSyncConfig {
+            window_secs,
+            tolerance_secs,
+        } = UncheckedSyncConfig::deserialize(deserializer)?;
+
+        if !(window_secs.is_finite() && window_secs > 0.0) {
+            return Err(D::Error::custom(format!(
+                "window_secs must be a positive number, but get {}",
+                window_secs
+            )));
+        }
+        if !(tolerance_secs.is_finite() && tolerance_secs >= 0.0) {
+            return Err(D::Error::custom(format!(
+                "tolerance_secs must be a non-negative number, but get {}",
+                tolerance_secs
+            )));
         }
+
+        Ok(Self {
+            window_secs,
+            tolerance_secs,
+        })
     }
 }
 
+#[derive(Deserialize)]
+struct UncheckedSyncConfig {
+    window_secs: f64,
+    tolerance_secs: f64,
+}
+
+/// Output topics of the fusion results over all cameras. Each output
+/// is published only if its topic is set.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct OutputConfig {
+    /// The ROI and the boxes of the points in each bbox.
+    pub marker_topic: Option<String>,
+    /// Class names numbered from 1 in the `label` field. Points outside
+    /// bboxes or of unlisted classes are labelled 0.
+    pub labels: Vec<String>,
+}
+
+/// Output topics of the fusion results of a camera. Each output is
+/// published only if its topic is set.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct CameraOutputConfig {
+    /// In-view points colored by the image, in the `rgb` field.
+    pub colored_pcd_topic: Option<String>,
+    /// In-view points with the `object` and `label` fields.
+    pub labeled_pcd_topic: Option<String>,
+    /// The image or bboxes with projected points.
+    pub image_topic: Option<String>,
+}
+
 /// The type defines the calibration parameter file generated by MRPT
 /// camera-calib.
 #[derive(Debug, Clone, Deserialize)]
@@ -331,3 +532,27 @@ impl Roi3D {
         segments
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reject_invalid_sync_config() {
+        let config: SyncConfig =
+            json5::from_str("{ window_secs: 0.3, tolerance_secs: 0.05 }").unwrap();
+        assert_eq!(config.window_secs, 0.3);
+        assert_eq!(config.tolerance_secs, 0.05);
+
+        for text in [
+            "{ window_secs: 0, tolerance_secs: 0.05 }",
+            "{ window_secs: NaN, tolerance_secs: 0.05 }",
+            "{ window_secs: Infinity, tolerance_secs: 0.05 }",
+            "{ window_secs: 0.3, tolerance_secs: -0.05 }",
+            "{ window_secs: 0.3, tolerance_secs: NaN }",
+        ] {
+            let result: Result<SyncConfig, _> = json5::from_str(text);
+            assert!(result.is_err(), "{} is accepted", text);
+        }
+    }
+}
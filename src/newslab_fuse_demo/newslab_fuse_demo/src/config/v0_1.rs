@@ -0,0 +1,170 @@
+//! The config format of version 0.1.0, which has a fixed Otobrite
+//! camera giving images and a fixed Kneron camera giving detections.
+
+use super::{
+    CameraConfig, CameraOutputConfig, MrptCalibration, OutputConfig, PointCloudRoi, SyncConfig,
+};
+use crate::yaml_loader::YamlPath;
+use nalgebra as na;
+use sensor_diagnostics::DiagnosticsConfig;
+use serde::Deserialize;
+use serde_loader::Json5Path;
+use serde_semver::SemverReq;
+use std::num::NonZeroUsize;
+
+/// Version marker type.
+#[derive(Debug, Clone, SemverReq)]
+#[version("0.1.0")]
+pub struct Version;
+
+#[derive(Deserialize)]
+pub struct Config {
+    #[allow(dead_code)]
+    version: Version,
+    namespace: String,
+    pcd_topic: String,
+    pcd_roi: PointCloudRoi,
+
+    kneron_det_topic: String,
+    kneron_intrinsics_file: YamlPath<MrptCalibration>,
+    kneron_extrinsics_file: Json5Path<na::Isometry3<f64>>,
+    kneron_det_present_size: usize,
+    kneron_fused_present_size: usize,
+    kneron_image_hw: [NonZeroUsize; 2],
+    kneron_det_hw: [NonZeroUsize; 2],
+    kneron_pcd_rotate_90: bool,
+    kneron_image_roi_tlbr: [usize; 4],
+    kneron_distance_range: [f32; 2],
+
+    otobrite_img_topic: String,
+    otobrite_image_hw: [NonZeroUsize; 2],
+    otobrite_image_roi_tlbr: [usize; 4],
+    otobrite_image_rotate_180: bool,
+    otobrite_pcd_rotate_90: bool,
+    otobrite_raw_present_size: usize,
+    otobrite_fused_present_size: usize,
+    otobrite_distance_range: [f32; 2],
+    otobrite_hue_range: [f32; 2],
+    otobrite_intrinsics_file: YamlPath<MrptCalibration>,
+    otobrite_extrinsics_file: Json5Path<na::Isometry3<f64>>,
+
+    #[serde(default)]
+    sync: Option<SyncConfig>,
+    #[serde(default)]
+    outputs: Outputs,
+    #[serde(default)]
+    diagnostics: DiagnosticsConfig,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct Outputs {
+    colored_pcd_topic: Option<String>,
+    labeled_pcd_topic: Option<String>,
+    otobrite_image_topic: Option<String>,
+    kneron_image_topic: Option<String>,
+    marker_topic: Option<String>,
+    labels: Vec<String>,
+}
+
+impl From<Config> for super::Config {
+    fn from(from: Config) -> Self {
+        let Config {
+            version: _,
+            namespace,
+            pcd_topic,
+            pcd_roi,
+            kneron_det_topic,
+            kneron_intrinsics_file,
+            kneron_extrinsics_file,
+            kneron_det_present_size,
+            kneron_fused_present_size,
+            kneron_image_hw,
+            kneron_det_hw,
+            kneron_pcd_rotate_90,
+            kneron_image_roi_tlbr,
+            kneron_distance_range,
+            otobrite_img_topic,
+            otobrite_image_hw,
+            otobrite_image_roi_tlbr,
+            otobrite_image_rotate_180,
+            otobrite_pcd_rotate_90,
+            otobrite_raw_present_size,
+            otobrite_fused_present_size,
+            otobrite_distance_range,
+            otobrite_hue_range,
+            otobrite_intrinsics_file,
+            otobrite_extrinsics_file,
+            sync,
+            outputs,
+            diagnostics,
+        } = from;
+        let Outputs {
+            colored_pcd_topic,
+            labeled_pcd_topic,
+            otobrite_image_topic,
+            kneron_image_topic,
+            marker_topic,
+            labels,
+        } = outputs;
+
+        let kneron = CameraConfig {
+            name: "Kneron".to_string(),
+            image_topic: None,
+            image_compressed: false,
+            det_topic: Some(kneron_det_topic),
+            image_hw: kneron_image_hw,
+            det_hw: Some(kneron_det_hw),
+            image_rotate_180: false,
+            pcd_rotate_90: kneron_pcd_rotate_90,
+            intrinsics_file: kneron_intrinsics_file,
+            extrinsics_file: kneron_extrinsics_file,
+            distance_range: kneron_distance_range,
+            hue_range: otobrite_hue_range,
+            image_roi_tlbr: kneron_image_roi_tlbr,
+            raw_present_size: kneron_det_present_size,
+            fused_present_size: kneron_fused_present_size,
+            outputs: CameraOutputConfig {
+                colored_pcd_topic: None,
+                labeled_pcd_topic,
+                image_topic: kneron_image_topic,
+            },
+        };
+        let otobrite = CameraConfig {
+            name: "Otobrite".to_string(),
+            image_topic: Some(otobrite_img_topic),
+            image_compressed: false,
+            det_topic: None,
+            image_hw: otobrite_image_hw,
+            det_hw: None,
+            image_rotate_180: otobrite_image_rotate_180,
+            pcd_rotate_90: otobrite_pcd_rotate_90,
+            intrinsics_file: otobrite_intrinsics_file,
+            extrinsics_file: otobrite_extrinsics_file,
+            distance_range: otobrite_distance_range,
+            hue_range: otobrite_hue_range,
+            image_roi_tlbr: otobrite_image_roi_tlbr,
+            raw_present_size: otobrite_raw_present_size,
+            fused_present_size: otobrite_fused_present_size,
+            outputs: CameraOutputConfig {
+                colored_pcd_topic,
+                labeled_pcd_topic: None,
+                image_topic: otobrite_image_topic,
+            },
+        };
+
+        Self {
+            version: super::Version,
+            namespace,
+            pcd_topic,
+            pcd_roi,
+            cameras: vec![otobrite, kneron],
+            sync,
+            outputs: OutputConfig {
+                marker_topic,
+                labels,
+            },
+            diagnostics,
+        }
+    }
+}
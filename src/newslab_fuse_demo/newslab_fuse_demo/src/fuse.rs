@@ -1,82 +1,127 @@
-use std::slice;
-
 use crate::{
-    config::{Config, Roi3D},
+    config::{CameraConfig, Config, Roi3D},
     message as msg,
     point_projection::{CameraParams, PointProjector},
+    sync::{SyncOutput, Synchronizer},
 };
-use anyhow::{bail, ensure, Result};
+use anyhow::{anyhow, bail, ensure, Result};
 use async_std::task::spawn_blocking;
-use fast_yuv442_to_rgb24::uvy422_to_bgr24::uyvy422_to_bgr24_chunk16_many;
 use futures::prelude::*;
 use itertools::chain;
 use nalgebra as na;
 use opencv::{
-    core::{Rect, Scalar, Vec3b, VecN, CV_8UC3, ROTATE_180},
+    core::{Rect, Vector, CV_8U, NORM_MINMAX, ROTATE_180},
+    imgcodecs::{self, IMREAD_COLOR},
+    imgproc::{
+        self, COLOR_BayerBG2BGR, COLOR_BayerGB2BGR, COLOR_BayerGR2BGR, COLOR_BayerRG2BGR,
+        COLOR_BGRA2BGR, COLOR_GRAY2BGR, COLOR_RGB2BGR, COLOR_RGBA2BGR, COLOR_YUV2BGR_NV12,
+        COLOR_YUV2BGR_NV21, COLOR_YUV2BGR_UYVY, COLOR_YUV2BGR_YUYV,
+    },
     prelude::*,
 };
 use ownref::ArcRefA as ARef;
 use r2r::{
     geometry_msgs::msg::Pose2D,
     log_error,
-    sensor_msgs::msg::{Image, PointCloud2, PointField},
+    sensor_msgs::msg::{CompressedImage, Image, PointCloud2, PointField},
+    std_msgs::msg::Header,
     vision_msgs::msg::{BoundingBox2D, Detection2DArray},
 };
 use rayon::prelude::*;
+use sensor_diagnostics::SensorMonitor;
+use std::sync::Arc;
 
 /// Starts a image and point cloud fusing processor.
 ///
 /// # Parameters
 /// - `input_stream`: the stream to be transformed.
 /// - `config`: Configuration data.
+/// - `monitor`: Counts processed, dropped, failed and unpaired
+///   messages.
 pub fn start(
     mut input_stream: impl Stream<Item = msg::InputMessage> + Unpin + Send,
     config: &Config,
+    monitor: Arc<SensorMonitor>,
 ) -> Result<impl Stream<Item = msg::FuseMessage> + Send> {
     // Initialize the state
     let mut state = State::new(config)?;
+    let mut synchronizer = config
+        .sync
+        .as_ref()
+        .map(|sync| Synchronizer::new(sync, &config.cameras));
 
     // Create an input and an output channels.
     let (input_tx, input_rx) = flume::bounded(2);
     let (output_tx, output_rx) = flume::bounded(2);
 
-    // Forward the stream to the input channel.
-    let forward_future = async move {
-        while let Some(msg) = input_stream.next().await {
-            let result = input_tx.try_send(msg);
-            match result {
-                Ok(()) => {}
-                Err(flume::TrySendError::Full(_)) => {}
-                Err(flume::TrySendError::Disconnected(_)) => break,
+    // Pair messages by stamps if it is enabled, and forward the
+    // batches to the input channel. Pairing happens before the channel
+    // so that a busy worker drops whole batches rather than inputs
+    // waiting to be paired.
+    let forward_future = {
+        let monitor = monitor.clone();
+        async move {
+            while let Some(in_msg) = input_stream.next().await {
+                let in_msgs = match &mut synchronizer {
+                    Some(synchronizer) => {
+                        let SyncOutput {
+                            messages,
+                            unmatched,
+                            late,
+                        } = synchronizer.push(in_msg);
+                        (0..unmatched).for_each(|_| monitor.count_unmatched());
+                        (0..late).for_each(|_| monitor.count_late());
+                        messages
+                    }
+                    None => vec![in_msg],
+                };
+                if in_msgs.is_empty() {
+                    continue;
+                }
+
+                let result = input_tx.try_send(in_msgs);
+                match result {
+                    Ok(()) => {}
+                    Err(flume::TrySendError::Full(in_msgs)) => {
+                        in_msgs.iter().for_each(|_| monitor.count_dropped());
+                    }
+                    Err(flume::TrySendError::Disconnected(_)) => break,
+                }
             }
         }
     };
 
     // Spawn a non-async loop task that updates the state whenever a
-    // message arrives.
+    // batch of messages arrives.
     let handle_future = spawn_blocking(move || {
-        'msg_loop: while let Ok(in_msg) = input_rx.recv() {
-            // Update the state
-            let result = state.map_msg(in_msg);
-
-            // Print log if an error is returned
-            let out_msgs = match result {
-                Ok(msgs) => msgs,
-                Err(err) => {
-                    log_error!(
-                        env!("CARGO_PKG_NAME"),
-                        "Unable to process an input message: {:#}",
-                        err
-                    );
-                    continue;
-                }
-            };
+        'msg_loop: while let Ok(in_msgs) = input_rx.recv() {
+            for in_msg in in_msgs {
+                // Update the state
+                let result = state.map_msg(in_msg);
+
+                // Print log if an error is returned
+                let out_msgs = match result {
+                    Ok(msgs) => {
+                        monitor.bump();
+                        msgs
+                    }
+                    Err(err) => {
+                        monitor.count_decode_error();
+                        log_error!(
+                            env!("CARGO_PKG_NAME"),
+                            "Unable to process an input message: {:#}",
+                            err
+                        );
+                        continue;
+                    }
+                };
 
-            // Forward the output messages to the output channel.
-            for msg in out_msgs {
-                let result = output_tx.send(msg);
-                if result.is_err() {
-                    break 'msg_loop;
+                // Forward the output messages to the output channel.
+                for msg in out_msgs {
+                    let result = output_tx.send(msg);
+                    if result.is_err() {
+                        break 'msg_loop;
+                    }
                 }
             }
         }
@@ -93,54 +138,22 @@ pub fn start(
 /// The state maintained by the fusing algorithm.
 struct State {
     cache: Cache,
-    otobrite_projector: PointProjector,
-    otobrite_rotate_180: bool,
-    kneron_projector: PointProjector,
-    kneron_scale_hw: [f64; 2],
+    cameras: Vec<CameraState>,
     pcd_roi: Option<Roi3D>,
 }
 
 impl State {
     /// Create a new state.
     pub fn new(config: &Config) -> Result<Self> {
-        let otobrite_projector = {
-            let [h, w] = config.otobrite_image_hw;
-            let camera_params =
-                CameraParams::new(&config.otobrite_intrinsics_file, &config.otobrite_pose())?;
-
-            PointProjector {
-                height: h.get(),
-                width: w.get(),
-                camera_params,
-            }
-        };
-        let kneron_projector = {
-            let [h, w] = config.kneron_image_hw;
-            let camera_params =
-                CameraParams::new(&config.kneron_intrinsics_file, &config.kneron_pose())?;
-
-            PointProjector {
-                height: h.get(),
-                width: w.get(),
-                camera_params,
-            }
-        };
-
-        let kneron_scale_hw = {
-            let [image_h, image_w] = config.kneron_image_hw;
-            let [det_h, det_w] = config.kneron_det_hw;
-            [
-                image_h.get() as f64 / det_h.get() as f64,
-                image_w.get() as f64 / det_w.get() as f64,
-            ]
-        };
+        let cameras: Vec<_> = config
+            .cameras
+            .iter()
+            .map(CameraState::new)
+            .collect::<Result<_>>()?;
 
         Ok(Self {
-            otobrite_projector,
-            kneron_projector,
-            otobrite_rotate_180: config.otobrite_image_rotate_180,
             cache: Cache::default(),
-            kneron_scale_hw,
+            cameras,
             pcd_roi: config.pcd_roi.to_roi(),
         })
     }
@@ -152,160 +165,82 @@ impl State {
             M::PointCloud2(pcd) => {
                 self.update_pcd(pcd)?;
 
-                let Cache {
-                    points,
-                    otobrite_image,
-                    kneron_bboxes,
-                    otobrite_assocs,
-                    kneron_assocs,
-                } = &self.cache;
-
-                let kiss3d_msg: Option<msg::FuseMessage> = points.as_ref().map(|points| {
-                    let points = points.clone();
-                    let kneron_assocs = kneron_assocs.clone();
-                    msg::Kiss3dMessage {
-                        points,
-                        kneron_assocs,
-                    }
-                    .into()
-                });
-                let kneron_msg: msg::FuseMessage = msg::KneronMessage {
-                    objects: kneron_bboxes.as_ref().map(|bboxes| bboxes.objects.clone()),
-                    assocs: kneron_assocs.clone(),
-                }
-                .into();
-                let otobrite_msg: msg::FuseMessage = msg::OtobriteMessage {
-                    image: otobrite_image.clone(),
-                    assocs: otobrite_assocs.clone(),
-                }
-                .into();
+                let kiss3d_msg = self.kiss3d_msg().map(msg::FuseMessage::from);
+                let camera_msgs =
+                    (0..self.cameras.len()).map(|index| self.camera_msg(index, true).into());
 
-                chain!(kiss3d_msg, [kneron_msg, otobrite_msg]).collect()
+                chain!(kiss3d_msg, camera_msgs).collect()
             }
-            M::OtobriteImage(img) => {
-                self.update_otobrite_image(img)?;
-
-                let Cache {
-                    otobrite_image,
-                    otobrite_assocs,
-                    ..
-                } = &self.cache;
-
-                let out_msg: msg::FuseMessage = msg::OtobriteMessage {
-                    image: otobrite_image.clone(),
-                    assocs: otobrite_assocs.clone(),
-                }
-                .into();
-                vec![out_msg]
+            M::Image { camera, image } => {
+                self.camera_mut(camera)?.update_image(image)?;
+
+                vec![self.camera_msg(camera, false).into()]
             }
-            M::BBox(det) => {
-                self.update_kneron_det(det);
-                let Cache {
-                    points,
-                    kneron_bboxes,
-                    kneron_assocs,
-                    ..
-                } = &self.cache;
-
-                let kiss3d_msg: Option<msg::FuseMessage> = points.as_ref().map(|points| {
-                    let points = points.clone();
-                    let kneron_assocs = kneron_assocs.clone();
-                    msg::Kiss3dMessage {
-                        points,
-                        kneron_assocs,
-                    }
-                    .into()
-                });
-                let kneron_msg: msg::FuseMessage = msg::KneronMessage {
-                    objects: kneron_bboxes.as_ref().map(|bboxes| bboxes.objects.clone()),
-                    assocs: kneron_assocs.clone(),
-                }
-                .into();
+            M::CompressedImage { camera, image } => {
+                self.camera_mut(camera)?.update_compressed_image(image)?;
+
+                vec![self.camera_msg(camera, false).into()]
+            }
+            M::BBox { camera, det } => {
+                self.camera_mut(camera)?.update_det(det);
+                self.update_assocs(camera);
 
-                chain!(kiss3d_msg, [kneron_msg]).collect()
+                let kiss3d_msg = self.kiss3d_msg().map(msg::FuseMessage::from);
+                chain!(kiss3d_msg, [self.camera_msg(camera, false).into()]).collect()
             }
         };
         Ok(out_msgs)
     }
 
-    /// Processes a Kneron detection message and updates its state.
-    pub fn update_kneron_det(&mut self, det: Detection2DArray) {
-        let [scale_h, scale_w] = self.kneron_scale_hw;
-
-        let objects: Vec<_> = det
-            .detections
-            .par_iter()
-            .map(|det| {
-                let class_id = det
-                    .results
-                    .get(0)
-                    .map(|res| res.hypothesis.class_id.clone());
-                let BoundingBox2D {
-                    size_x,
-                    size_y,
-                    center: Pose2D { x: cx, y: cy, .. },
-                } = det.bbox;
-
-                // left-top x and y
-                let ltx = (cx - size_x / 2.0) * scale_w;
-                let lty = (cy - size_y / 2.0) * scale_h;
-
-                let width = size_x * scale_w;
-                let height = size_y * scale_h;
+    fn camera_mut(&mut self, index: usize) -> Result<&mut CameraState> {
+        self.cameras
+            .get_mut(index)
+            .ok_or_else(|| anyhow!("Camera index {} is out of range", index))
+    }
 
-                msg::Object {
-                    class_id,
-                    rect: Rect {
-                        x: ltx as i32,
-                        y: lty as i32,
-                        width: width as i32,
-                        height: height as i32,
-                    },
-                }
+    fn kiss3d_msg(&self) -> Option<msg::Kiss3dMessage> {
+        let points = self.cache.points.as_ref()?;
+        let detections = self
+            .cameras
+            .iter()
+            .enumerate()
+            .filter(|(_, camera)| camera.has_detections)
+            .filter_map(|(index, camera)| {
+                Some(msg::DetectionAssocs {
+                    camera: index,
+                    objects: camera.bboxes.as_ref().map(|bboxes| bboxes.objects.clone()),
+                    assocs: camera.assocs.clone()?,
+                })
             })
             .collect();
-        let objects = ARef::new(objects);
-        // let index: RectRTree = objects.clone().flatten().collect();
-
-        self.cache.kneron_bboxes = Some(BBoxIndex {
-            objects: objects.clone(),
-            // index,
-        });
-        self.update_kneron_assocs();
-    }
-
-    /// Processes an image from the Otobrite camera.
-    pub fn update_otobrite_image(&mut self, image: Image) -> Result<()> {
-        // Check image size
-        {
-            let expect_h = self.otobrite_projector.height;
-            let expect_w = self.otobrite_projector.width;
-            let image_h = image.height;
-            let image_w = image.width;
-
-            let ok = expect_h as u32 == image_h && expect_w as u32 == image_w;
-            ensure!(
-                ok,
-                "Expect {}x{} sized image, but received an image with size {}x{}",
-                expect_w,
-                expect_h,
-                image_w,
-                image_h
-            );
-        }
 
-        let mat = image_to_mat(&image)?;
-        let mat = if self.otobrite_rotate_180 {
-            let mut out = Mat::default();
-            opencv::core::rotate(&mat, &mut out, ROTATE_180)?;
-            out
-        } else {
-            mat
-        };
-
-        self.cache.otobrite_image = Some(mat);
+        Some(msg::Kiss3dMessage {
+            points: points.clone(),
+            pcd_header: self.cache.pcd_header.clone(),
+            detections,
+        })
+    }
 
-        Ok(())
+    fn camera_msg(&self, index: usize, new_pcd: bool) -> msg::CameraMessage {
+        let CameraState {
+            image,
+            image_header,
+            bboxes,
+            det_header,
+            assocs,
+            ..
+        } = &self.cameras[index];
+
+        msg::CameraMessage {
+            camera: index,
+            image: image.clone(),
+            image_header: image_header.clone(),
+            objects: bboxes.as_ref().map(|bboxes| bboxes.objects.clone()),
+            det_header: det_header.clone(),
+            assocs: assocs.clone(),
+            pcd_header: self.cache.pcd_header.clone(),
+            new_pcd,
+        }
     }
 
     /// Processes a point cloud message from LiDAR.
@@ -323,58 +258,22 @@ impl State {
             .collect();
 
         self.cache.points = Some(ARef::new(points));
+        self.cache.pcd_header = Some(pcd.header);
 
-        self.update_kneron_assocs();
-        self.update_otobrite_assocs();
+        (0..self.cameras.len()).for_each(|index| self.update_assocs(index));
         Ok(())
     }
 
-    /// Compute LiDAR points to Otobrite image points associations.
-    fn update_otobrite_assocs(&mut self) {
-        let points = match self.cache.points.as_ref() {
-            Some(points) => points,
-            None => return,
-        };
-
-        // let points = if let Some(roi) = &self.pcd_roi {
-        //     points
-        //         .into_iter()
-        //         .filter(|point| roi.contains(&point.position))
-        //         .collect()
-        // } else {
-        //     points
-        // };
-
-        let assocs: Vec<_> = self
-            .otobrite_projector
-            .project(points)
-            .into_par_iter()
-            .filter(|(pcd_point, _img_point)| {
-                if let Some(roi) = &self.pcd_roi {
-                    roi.contains(&pcd_point.position)
-                } else {
-                    true
-                }
-            })
-            .map(|(pcd_point, img_point)| msg::Association {
-                pcd_point,
-                img_point,
-                object: None,
-            })
-            .collect();
-
-        self.cache.otobrite_assocs = Some(ARef::new(assocs));
-    }
-
-    /// Compute LiDAR points to Kneron image points associations.
-    fn update_kneron_assocs(&mut self) {
+    /// Compute LiDAR points to image points associations of a camera.
+    fn update_assocs(&mut self, index: usize) {
         let points = match &self.cache.points {
             Some(points) => points,
             None => return,
         };
+        let camera = &mut self.cameras[index];
 
         // Compute projected 2D points.
-        let pairs = self.kneron_projector.project(points);
+        let pairs = camera.projector.project(points);
 
         let pairs: Vec<_> = if let Some(roi) = &self.pcd_roi {
             pairs
@@ -386,7 +285,7 @@ impl State {
         };
 
         // Associate points with bboxes if bboxes are available.
-        let assocs: Vec<msg::Association> = match &self.cache.kneron_bboxes {
+        let assocs: Vec<msg::Association> = match &camera.bboxes {
             Some(bboxes) => pairs
                 .into_par_iter()
                 .map(|(pcd_point, img_point)| {
@@ -423,18 +322,155 @@ impl State {
                 .collect(),
         };
 
-        self.cache.kneron_assocs = Some(ARef::new(assocs));
+        camera.assocs = Some(ARef::new(assocs));
     }
 }
 
-/// The cache stores computed point cloud, image and detection  data.
+/// The projection parameters and the cached data of a camera.
+struct CameraState {
+    projector: PointProjector,
+    image_rotate_180: bool,
+    /// The scale from detection to image coordinates.
+    det_scale_hw: [f64; 2],
+    has_detections: bool,
+    image: Option<Mat>,
+    image_header: Option<Header>,
+    bboxes: Option<BBoxIndex>,
+    det_header: Option<Header>,
+    assocs: Option<msg::ArcAssocVec>,
+}
+
+impl CameraState {
+    fn new(config: &CameraConfig) -> Result<Self> {
+        let [image_h, image_w] = config.image_hw;
+        let projector = {
+            let camera_params = CameraParams::new(&config.intrinsics_file, &config.pose())?;
+
+            PointProjector {
+                height: image_h.get(),
+                width: image_w.get(),
+                camera_params,
+            }
+        };
+
+        let det_scale_hw = {
+            let [det_h, det_w] = config.det_hw();
+            [
+                image_h.get() as f64 / det_h.get() as f64,
+                image_w.get() as f64 / det_w.get() as f64,
+            ]
+        };
+
+        Ok(Self {
+            projector,
+            image_rotate_180: config.image_rotate_180,
+            det_scale_hw,
+            has_detections: config.det_topic.is_some(),
+            image: None,
+            image_header: None,
+            bboxes: None,
+            det_header: None,
+            assocs: None,
+        })
+    }
+
+    /// Processes a detection message and updates its state.
+    fn update_det(&mut self, det: Detection2DArray) {
+        let [scale_h, scale_w] = self.det_scale_hw;
+
+        let objects: Vec<_> = det
+            .detections
+            .par_iter()
+            .map(|det| {
+                let class_id = det
+                    .results
+                    .get(0)
+                    .map(|res| res.hypothesis.class_id.clone());
+                let BoundingBox2D {
+                    size_x,
+                    size_y,
+                    center: Pose2D { x: cx, y: cy, .. },
+                } = det.bbox;
+
+                // left-top x and y
+                let ltx = (cx - size_x / 2.0) * scale_w;
+                let lty = (cy - size_y / 2.0) * scale_h;
+
+                let width = size_x * scale_w;
+                let height = size_y * scale_h;
+
+                msg::Object {
+                    class_id,
+                    rect: Rect {
+                        x: ltx as i32,
+                        y: lty as i32,
+                        width: width as i32,
+                        height: height as i32,
+                    },
+                }
+            })
+            .collect();
+        let objects = ARef::new(objects);
+        // let index: RectRTree = objects.clone().flatten().collect();
+
+        self.bboxes = Some(BBoxIndex {
+            objects: objects.clone(),
+            // index,
+        });
+        self.det_header = Some(det.header);
+    }
+
+    /// Processes an image from the camera.
+    fn update_image(&mut self, image: Image) -> Result<()> {
+        self.check_image_size(image.height as usize, image.width as usize)?;
+        let mat = image_to_mat(&image)?;
+        self.set_image(mat, image.header)
+    }
+
+    /// Processes a compressed image from the camera.
+    fn update_compressed_image(&mut self, image: CompressedImage) -> Result<()> {
+        let mat = compressed_image_to_mat(&image)?;
+        self.check_image_size(mat.rows() as usize, mat.cols() as usize)?;
+        self.set_image(mat, image.header)
+    }
+
+    fn check_image_size(&self, image_h: usize, image_w: usize) -> Result<()> {
+        let expect_h = self.projector.height;
+        let expect_w = self.projector.width;
+
+        let ok = expect_h == image_h && expect_w == image_w;
+        ensure!(
+            ok,
+            "Expect {}x{} sized image, but received an image with size {}x{}",
+            expect_w,
+            expect_h,
+            image_w,
+            image_h
+        );
+        Ok(())
+    }
+
+    fn set_image(&mut self, mat: Mat, header: Header) -> Result<()> {
+        let mat = if self.image_rotate_180 {
+            let mut out = Mat::default();
+            opencv::core::rotate(&mat, &mut out, ROTATE_180)?;
+            out
+        } else {
+            mat
+        };
+
+        self.image = Some(mat);
+        self.image_header = Some(header);
+
+        Ok(())
+    }
+}
+
+/// The cache stores the computed point cloud.
 #[derive(Default)]
 struct Cache {
     points: Option<msg::ArcPointVec>,
-    otobrite_image: Option<Mat>,
-    kneron_bboxes: Option<BBoxIndex>,
-    otobrite_assocs: Option<msg::ArcAssocVec>,
-    kneron_assocs: Option<msg::ArcAssocVec>,
+    pcd_header: Option<Header>,
 }
 
 /// Contains a vec of bboxes and a spatial R-Tree of bboxes.
@@ -444,72 +480,180 @@ struct BBoxIndex {
 }
 
 /// Converts a ROS point cloud to a vec of points.
+///
+/// Fields are looked up by names. The `x`, `y` and `z` fields are
+/// required, and `intensity`, `ring` and `time` (or `t`, `timestamp`)
+/// are optional. Points with NaN or infinite coordinates are skipped.
 pub fn pcd_to_points(pcd: &PointCloud2) -> Result<Vec<msg::Point>> {
-    // Assert the point cloud has at least 4 fields. Otherwise return error.
-    let [fx, fy, fz, fi] = match pcd.fields.get(0..4) {
-        Some([f1, f2, f3, f4]) => [f1, f2, f3, f4],
-        Some(_) => unreachable!(),
-        None => {
-            bail!("Ignore a point cloud message with less then 3 fields");
-        }
+    let PointCloud2 {
+        height,
+        width,
+        ref fields,
+        is_bigendian,
+        point_step,
+        row_step,
+        ref data,
+        ..
+    } = *pcd;
+    let [height, width, point_step, row_step] =
+        [height, width, point_step, row_step].map(|val| val as usize);
+
+    // Locate fields by names.
+    let find_field = |names: &[&str]| {
+        fields
+            .iter()
+            .find(|field| names.contains(&field.name.as_str()))
+            .map(|field| FieldReader::new(field, point_step, is_bigendian))
+            .transpose()
     };
-
-    // Assert the fields are named x, y, z and intensity. Otherwise return error.
-    if !(fx.name == "x" && fy.name == "y" && fz.name == "z" && fi.name == "intensity") {
-        bail!("Ignore a point cloud message with incorrect field name");
-    }
-
-    // Assert each field is of f32 type and contains a single value. Otherwise, return error.
-    let check_field = |field: &PointField| {
-        let PointField {
-            datatype, count, ..
-        } = *field;
-
-        // reject non-f64 or non-single-value fields
-        if !(datatype == 7 && count == 1) {
-            bail!("Ignore a point cloud message with non-f64 or non-single-value values");
-        }
-
-        anyhow::Ok(())
+    let require_field = |name: &str| {
+        find_field(&[name])?
+            .ok_or_else(|| anyhow!("Ignore a point cloud message without the '{}' field", name))
     };
-
-    check_field(fx)?;
-    check_field(fy)?;
-    check_field(fz)?;
-    check_field(fi)?;
-
-    // Assert a point is 16 bytes (4 x f32 values). Otherwise, return error.
-    if pcd.point_step < 16 {
-        bail!("Ignore a point cloud message with incorrect point_step (expect 16)");
+    let x_field = require_field("x")?;
+    let y_field = require_field("y")?;
+    let z_field = require_field("z")?;
+    let intensity_field = find_field(&["intensity"])?;
+    let ring_field = find_field(&["ring"])?;
+    let time_field = find_field(&["time", "t", "timestamp"])?;
+
+    // Assert the data covers all points. Rows of organized clouds may
+    // be padded.
+    ensure!(
+        row_step >= width * point_step,
+        "Ignore a point cloud message with row_step {} less than width * point_step {}",
+        row_step,
+        width * point_step
+    );
+    if height > 0 && width > 0 {
+        let expect_len = (height - 1) * row_step + width * point_step;
+        ensure!(
+            data.len() >= expect_len,
+            "Ignore a point cloud message with {} data bytes (expect at least {})",
+            data.len(),
+            expect_len
+        );
     }
 
     // Transform the data byte to a vec of points.
-    let points: Vec<msg::Point> = pcd
-        .data
-        .chunks(pcd.point_step as usize)
-        .map(|point_bytes| {
-            let xbytes = &point_bytes[0..4];
-            let ybytes = &point_bytes[4..8];
-            let zbytes = &point_bytes[8..12];
-            let ibytes = &point_bytes[12..16];
-
-            let x = f32::from_le_bytes(xbytes.try_into().unwrap());
-            let y = f32::from_le_bytes(ybytes.try_into().unwrap());
-            let z = f32::from_le_bytes(zbytes.try_into().unwrap());
-            let position = na::Point3::new(x, y, z);
-            let intensity = f32::from_le_bytes(ibytes.try_into().unwrap());
-
-            msg::Point {
-                position,
-                intensity,
+    let points: Vec<msg::Point> = (0..height * width)
+        .into_par_iter()
+        .filter_map(|index| {
+            let offset = index / width * row_step + index % width * point_step;
+            let bytes = &data[offset..offset + point_step];
+
+            let x = x_field.read(bytes) as f32;
+            let y = y_field.read(bytes) as f32;
+            let z = z_field.read(bytes) as f32;
+            if !(x.is_finite() && y.is_finite() && z.is_finite()) {
+                return None;
             }
+
+            Some(msg::Point {
+                position: na::Point3::new(x, y, z),
+                intensity: intensity_field
+                    .as_ref()
+                    .map_or(0.0, |field| field.read(bytes) as f32),
+                ring: ring_field.as_ref().map(|field| field.read(bytes) as u16),
+                time: time_field.as_ref().map(|field| field.read(bytes)),
+            })
         })
         .collect();
 
     Ok(points)
 }
 
-/// Converts a ROS image to an OpenCV Mat.
+// The `datatype` values of `sensor_msgs/PointField`.
+const INT8: u8 = 1;
+const UINT8: u8 = 2;
+const INT16: u8 = 3;
+const UINT16: u8 = 4;
+const INT32: u8 = 5;
+const UINT32: u8 = 6;
+const FLOAT32: u8 = 7;
+const FLOAT64: u8 = 8;
+
+/// Reads the first value of a point field.
+struct FieldReader {
+    offset: usize,
+    datatype: u8,
+    is_bigendian: bool,
+}
+
+impl FieldReader {
+    fn new(field: &PointField, point_step: usize, is_bigendian: bool) -> Result<Self> {
+        let PointField {
+            ref name,
+            offset,
+            datatype,
+            count,
+        } = *field;
+
+        let size = match datatype {
+            INT8 | UINT8 => 1,
+            INT16 | UINT16 => 2,
+            INT32 | UINT32 | FLOAT32 => 4,
+            FLOAT64 => 8,
+            _ => bail!(
+                "Ignore a point cloud message with unknown datatype {} of field '{}'",
+                datatype,
+                name
+            ),
+        };
+        ensure!(
+            count >= 1,
+            "Ignore a point cloud message with no values in field '{}'",
+            name
+        );
+        ensure!(
+            offset as usize + size <= point_step,
+            "Ignore a point cloud message with field '{}' beyond point_step {}",
+            name,
+            point_step
+        );
+
+        Ok(Self {
+            offset: offset as usize,
+            datatype,
+            is_bigendian,
+        })
+    }
+
+    fn read(&self, point: &[u8]) -> f64 {
+        macro_rules! read {
+            ($ty:ty) => {{
+                const SIZE: usize = std::mem::size_of::<$ty>();
+                let bytes: [u8; SIZE] = point[self.offset..self.offset + SIZE].try_into().unwrap();
+                let val = if self.is_bigendian {
+                    <$ty>::from_be_bytes(bytes)
+                } else {
+                    <$ty>::from_le_bytes(bytes)
+                };
+                val as f64
+            }};
+        }
+
+        match self.datatype {
+            INT8 => read!(i8),
+            UINT8 => read!(u8),
+            INT16 => read!(i16),
+            UINT16 => read!(u16),
+            INT32 => read!(i32),
+            UINT32 => read!(u32),
+            FLOAT32 => read!(f32),
+            FLOAT64 => read!(f64),
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Converts a ROS image to a BGR Mat.
+///
+/// The encodings in `sensor_msgs/image_encodings` are accepted, except
+/// `nv24` and two-channel generic encodings that have no conversions
+/// to BGR. Values of 16-bit encodings are scaled down to 8 bits, and
+/// values of other non-8-bit encodings are normalized to the 8-bit
+/// range. Rows may be padded.
 pub fn image_to_mat(image: &Image) -> Result<Mat> {
     let Image {
         height,
@@ -520,70 +664,544 @@ pub fn image_to_mat(image: &Image) -> Result<Mat> {
         ref data,
         ..
     } = *image;
-
+    let [height, width, row_step] = [height, width, row_step].map(|val| val as usize);
     let is_bigendian = is_bigendian != 0;
-    ensure!(!is_bigendian);
-
-    let mat = match encoding.as_str() {
-        "BGR8" => {
-            let pixel_step = 3;
-            ensure!(row_step == width * pixel_step);
-            ensure!(data.len() == (row_step * height) as usize);
-
-            let mut mat = Mat::new_rows_cols_with_default(
-                height as i32,
-                width as i32,
-                CV_8UC3,
-                Scalar::all(0.0),
-            )?;
 
-            data.chunks_exact(3).enumerate().for_each(|(pidx, bytes)| {
-                let col = pidx % width as usize;
-                let row = pidx / width as usize;
-                let pixel: &mut Vec3b = mat.at_2d_mut(row as i32, col as i32).unwrap();
-                let bytes: [u8; 3] = bytes.try_into().unwrap();
-                *pixel = VecN(bytes);
-            });
+    let encoding = encoding.to_ascii_lowercase();
+    let ImageFormat {
+        depth,
+        channels,
+        code,
+        is_yuv420,
+    } = ImageFormat::parse(&encoding)
+        .ok_or_else(|| anyhow!("unsupported image format {}", encoding))?;
+
+    // YUV 4:2:0 images carry a half-height chroma plane below the luma
+    // plane.
+    let rows = if is_yuv420 {
+        ensure!(
+            height % 2 == 0 && width % 2 == 0,
+            "Expect even image size for {}, but received {}x{}",
+            encoding,
+            width,
+            height
+        );
+        height * 3 / 2
+    } else {
+        height
+    };
+    let row_len = width * channels * depth.size();
 
-            mat
+    ensure!(
+        row_step >= row_len,
+        "Expect row step at least {} for {} image of width {}, but received {}",
+        row_len,
+        encoding,
+        width,
+        row_step
+    );
+    if rows > 0 {
+        let expect_len = (rows - 1) * row_step + row_len;
+        ensure!(
+            data.len() >= expect_len,
+            "Expect at least {} bytes of image data, but received {}",
+            expect_len,
+            data.len()
+        );
+    }
+
+    // Remove row paddings.
+    let bytes: Vec<u8> = if row_step == row_len {
+        data[..rows * row_len].to_vec()
+    } else {
+        data.chunks(row_step)
+            .take(rows)
+            .flat_map(|row| &row[..row_len])
+            .copied()
+            .collect()
+    };
+
+    macro_rules! decode {
+        ($ty:ty) => {{
+            const SIZE: usize = std::mem::size_of::<$ty>();
+            let values: Vec<$ty> = bytes
+                .chunks_exact(SIZE)
+                .map(|bytes| {
+                    let bytes: [u8; SIZE] = bytes.try_into().unwrap();
+                    if is_bigendian {
+                        <$ty>::from_be_bytes(bytes)
+                    } else {
+                        <$ty>::from_le_bytes(bytes)
+                    }
+                })
+                .collect();
+            Mat::from_slice(&values)?
+                .reshape(channels as i32, rows as i32)?
+                .try_clone()?
+        }};
+    }
+
+    let mat = match depth {
+        Depth::U8 => Mat::from_slice(&bytes)?
+            .reshape(channels as i32, rows as i32)?
+            .try_clone()?,
+        Depth::I8 => decode!(i8),
+        Depth::U16 => decode!(u16),
+        Depth::I16 => decode!(i16),
+        Depth::I32 => decode!(i32),
+        Depth::F32 => decode!(f32),
+        Depth::F64 => decode!(f64),
+    };
+
+    // Convert values to 8 bits.
+    let mat = match depth {
+        Depth::U8 => mat,
+        Depth::U16 => {
+            let mut out = Mat::default();
+            mat.convert_to(&mut out, CV_8U, 1.0 / 256.0, 0.0)?;
+            out
         }
-        "RGB8" => {
-            let pixel_step = 3;
-            ensure!(row_step == width * pixel_step);
-            ensure!(data.len() == (row_step * height) as usize);
-
-            let mut mat = Mat::new_rows_cols_with_default(
-                height as i32,
-                width as i32,
-                CV_8UC3,
-                Scalar::all(0.0),
+        _ => {
+            let mut out = Mat::default();
+            opencv::core::normalize(
+                &mat,
+                &mut out,
+                0.0,   // alpha
+                255.0, // beta
+                NORM_MINMAX,
+                CV_8U,
+                &opencv::core::no_array(),
             )?;
+            out
+        }
+    };
 
-            data.chunks_exact(3).enumerate().for_each(|(pidx, bytes)| {
-                let col = pidx % width as usize;
-                let row = pidx / width as usize;
-                let pixel: &mut Vec3b = mat.at_2d_mut(row as i32, col as i32).unwrap();
-                let [r, g, b]: [u8; 3] = bytes.try_into().unwrap();
-                *pixel = VecN([b, g, r]);
-            });
+    // Convert colors to BGR.
+    let mat = match code {
+        Some(code) => {
+            let mut out = Mat::default();
+            imgproc::cvt_color(&mat, &mut out, code, 0)?;
+            out
+        }
+        None => mat,
+    };
 
-            mat
+    Ok(mat)
+}
+
+/// Decodes a JPEG or PNG image to a BGR Mat.
+pub fn compressed_image_to_mat(image: &CompressedImage) -> Result<Mat> {
+    let buf = Vector::<u8>::from_slice(&image.data);
+    let mat = imgcodecs::imdecode(&buf, IMREAD_COLOR)?;
+    ensure!(
+        mat.rows() > 0 && mat.cols() > 0,
+        "unable to decode the compressed image in format '{}'",
+        image.format
+    );
+    Ok(mat)
+}
+
+/// The memory layout of an image encoding.
+struct ImageFormat {
+    depth: Depth,
+    channels: usize,
+    /// The color conversion code to BGR.
+    code: Option<i32>,
+    /// Whether the image is a YUV 4:2:0 semi-planar image.
+    is_yuv420: bool,
+}
+
+impl ImageFormat {
+    fn parse(encoding: &str) -> Option<Self> {
+        let packed = |depth, channels, code| Self {
+            depth,
+            channels,
+            code,
+            is_yuv420: false,
+        };
+        let yuv420 = |code| Self {
+            depth: Depth::U8,
+            channels: 1,
+            code: Some(code),
+            is_yuv420: true,
+        };
+
+        let format = match encoding {
+            "bgr8" => packed(Depth::U8, 3, None),
+            "rgb8" => packed(Depth::U8, 3, Some(COLOR_RGB2BGR)),
+            "bgra8" => packed(Depth::U8, 4, Some(COLOR_BGRA2BGR)),
+            "rgba8" => packed(Depth::U8, 4, Some(COLOR_RGBA2BGR)),
+            "mono8" => packed(Depth::U8, 1, Some(COLOR_GRAY2BGR)),
+            "bgr16" => packed(Depth::U16, 3, None),
+            "rgb16" => packed(Depth::U16, 3, Some(COLOR_RGB2BGR)),
+            "bgra16" => packed(Depth::U16, 4, Some(COLOR_BGRA2BGR)),
+            "rgba16" => packed(Depth::U16, 4, Some(COLOR_RGBA2BGR)),
+            "mono16" => packed(Depth::U16, 1, Some(COLOR_GRAY2BGR)),
+            // The Bayer patterns of OpenCV are named by the second row.
+            "bayer_rggb8" => packed(Depth::U8, 1, Some(COLOR_BayerBG2BGR)),
+            "bayer_bggr8" => packed(Depth::U8, 1, Some(COLOR_BayerRG2BGR)),
+            "bayer_gbrg8" => packed(Depth::U8, 1, Some(COLOR_BayerGR2BGR)),
+            "bayer_grbg8" => packed(Depth::U8, 1, Some(COLOR_BayerGB2BGR)),
+            "bayer_rggb16" => packed(Depth::U16, 1, Some(COLOR_BayerBG2BGR)),
+            "bayer_bggr16" => packed(Depth::U16, 1, Some(COLOR_BayerRG2BGR)),
+            "bayer_gbrg16" => packed(Depth::U16, 1, Some(COLOR_BayerGR2BGR)),
+            "bayer_grbg16" => packed(Depth::U16, 1, Some(COLOR_BayerGB2BGR)),
+            "yuv422" | "uyvy" => packed(Depth::U8, 2, Some(COLOR_YUV2BGR_UYVY)),
+            "yuv422_yuy2" | "yuyv" => packed(Depth::U8, 2, Some(COLOR_YUV2BGR_YUYV)),
+            "nv12" => yuv420(COLOR_YUV2BGR_NV12),
+            "nv21" => yuv420(COLOR_YUV2BGR_NV21),
+            // Generic encodings such as "8uc3" and "32fc1". One-channel
+            // images are shown in gray, and others are assumed BGR(A).
+            _ => {
+                let (depth, channels) = encoding.split_once('c')?;
+                let depth = Depth::parse(depth)?;
+                let channels: usize = channels.parse().ok()?;
+                let code = match channels {
+                    1 => Some(COLOR_GRAY2BGR),
+                    3 => None,
+                    4 => Some(COLOR_BGRA2BGR),
+                    _ => return None,
+                };
+                packed(depth, channels, code)
+            }
+        };
+        Some(format)
+    }
+}
+
+/// The value type of an image encoding.
+#[derive(Clone, Copy)]
+enum Depth {
+    U8,
+    I8,
+    U16,
+    I16,
+    I32,
+    F32,
+    F64,
+}
+
+impl Depth {
+    fn parse(depth: &str) -> Option<Self> {
+        let depth = match depth {
+            "8u" => Self::U8,
+            "8s" => Self::I8,
+            "16u" => Self::U16,
+            "16s" => Self::I16,
+            "32s" => Self::I32,
+            "32f" => Self::F32,
+            "64f" => Self::F64,
+            _ => return None,
+        };
+        Some(depth)
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            Self::U8 | Self::I8 => 1,
+            Self::U16 | Self::I16 => 2,
+            Self::I32 | Self::F32 => 4,
+            Self::F64 => 8,
         }
-        "UYVY" => unsafe {
-            let pixel_step = 2;
-            ensure!(row_step == width * pixel_step);
-            ensure!(data.len() == (row_step * height) as usize);
+    }
+}
 
-            let mut mat = Mat::new_rows_cols(height as i32, width as i32, CV_8UC3)?;
-            let uyvy422_buf = data;
-            let bgr24_buf =
-                slice::from_raw_parts_mut(mat.data_mut(), (height * width * 3) as usize);
-            uyvy422_to_bgr24_chunk16_many(uyvy422_buf, bgr24_buf);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opencv::core::{Vec3b, CV_8UC3};
+
+    fn field(name: &str, offset: u32, datatype: u8) -> PointField {
+        PointField {
+            name: name.to_string(),
+            offset,
+            datatype,
+            count: 1,
+        }
+    }
 
-            mat
-        },
-        _ => bail!("unsupported image format {}", encoding),
-    };
+    fn positions(points: &[msg::Point]) -> Vec<[f32; 3]> {
+        points
+            .iter()
+            .map(|point| point.position.coords.into())
+            .collect()
+    }
 
-    Ok(mat)
+    /// A little-endian cloud of x, y, z and intensity in float32.
+    fn xyzi_cloud(points: &[[f32; 4]]) -> PointCloud2 {
+        PointCloud2 {
+            height: 1,
+            width: points.len() as u32,
+            fields: vec![
+                field("x", 0, FLOAT32),
+                field("y", 4, FLOAT32),
+                field("z", 8, FLOAT32),
+                field("intensity", 12, FLOAT32),
+            ],
+            is_bigendian: false,
+            point_step: 16,
+            row_step: points.len() as u32 * 16,
+            data: points
+                .iter()
+                .flatten()
+                .flat_map(|val| val.to_le_bytes())
+                .collect(),
+            is_dense: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn decode_xyzi_points() {
+        let pcd = xyzi_cloud(&[[1.0, 2.0, 3.0, 10.0], [4.0, 5.0, 6.0, 20.0]]);
+        let points = pcd_to_points(&pcd).unwrap();
+
+        assert_eq!(positions(&points), [[1.0, 2.0, 3.0], [4.0, 5.0, 6.0]]);
+        assert_eq!(points[1].intensity, 20.0);
+        assert_eq!(points[0].ring, None);
+        assert_eq!(points[0].time, None);
+    }
+
+    #[test]
+    fn decode_reordered_f64_fields() {
+        // z, x, y in float64 with ring and time fields.
+        let mut data = vec![];
+        data.extend(3.0f64.to_le_bytes());
+        data.extend(1.0f64.to_le_bytes());
+        data.extend(2.0f64.to_le_bytes());
+        data.extend(7u16.to_le_bytes());
+        data.extend(0.25f32.to_le_bytes());
+        data.extend([0; 2]); // padding
+        let pcd = PointCloud2 {
+            height: 1,
+            width: 1,
+            fields: vec![
+                field("z", 0, FLOAT64),
+                field("x", 8, FLOAT64),
+                field("y", 16, FLOAT64),
+                field("ring", 24, UINT16),
+                field("time", 26, FLOAT32),
+            ],
+            point_step: 32,
+            row_step: 32,
+            data,
+            ..Default::default()
+        };
+        let points = pcd_to_points(&pcd).unwrap();
+
+        assert_eq!(positions(&points), [[1.0, 2.0, 3.0]]);
+        assert_eq!(points[0].intensity, 0.0);
+        assert_eq!(points[0].ring, Some(7));
+        assert_eq!(points[0].time, Some(0.25));
+    }
+
+    #[test]
+    fn decode_big_endian_points() {
+        let mut data = vec![];
+        for val in [1.0f32, -2.0, 3.5] {
+            data.extend(val.to_be_bytes());
+        }
+        data.extend(300u16.to_be_bytes());
+        data.extend([0; 2]); // padding
+        let pcd = PointCloud2 {
+            height: 1,
+            width: 1,
+            fields: vec![
+                field("x", 0, FLOAT32),
+                field("y", 4, FLOAT32),
+                field("z", 8, FLOAT32),
+                field("intensity", 12, UINT16),
+            ],
+            is_bigendian: true,
+            point_step: 16,
+            row_step: 16,
+            data,
+            ..Default::default()
+        };
+        let points = pcd_to_points(&pcd).unwrap();
+
+        assert_eq!(positions(&points), [[1.0, -2.0, 3.5]]);
+        assert_eq!(points[0].intensity, 300.0);
+    }
+
+    #[test]
+    fn decode_organized_cloud_with_padded_rows() {
+        // A 2x2 cloud with 8 padding bytes after each row.
+        let mut pcd = xyzi_cloud(&[]);
+        pcd.height = 2;
+        pcd.width = 2;
+        pcd.row_step = 2 * 16 + 8;
+        pcd.is_dense = false;
+        for row in 0..2 {
+            for col in 0..2 {
+                let x = (row * 2 + col) as f32;
+                for val in [x, 0.0, 0.0, 0.0] {
+                    pcd.data.extend(val.to_le_bytes());
+                }
+            }
+            pcd.data.extend([0xff; 8]);
+        }
+        let points = pcd_to_points(&pcd).unwrap();
+
+        let xs: Vec<f32> = points.iter().map(|point| point.position.x).collect();
+        assert_eq!(xs, [0.0, 1.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn skip_nan_points() {
+        let mut pcd = xyzi_cloud(&[
+            [1.0, 1.0, 1.0, 0.0],
+            [f32::NAN, f32::NAN, f32::NAN, 0.0],
+            [2.0, f32::INFINITY, 2.0, 0.0],
+            [3.0, 3.0, 3.0, 0.0],
+        ]);
+        pcd.is_dense = false;
+        let points = pcd_to_points(&pcd).unwrap();
+
+        assert_eq!(positions(&points), [[1.0, 1.0, 1.0], [3.0, 3.0, 3.0]]);
+    }
+
+    #[test]
+    fn reject_invalid_clouds() {
+        let pcd = xyzi_cloud(&[[1.0, 2.0, 3.0, 0.0], [4.0, 5.0, 6.0, 0.0]]);
+
+        let mut missing_z = pcd.clone();
+        missing_z.fields.remove(2);
+        assert!(pcd_to_points(&missing_z).is_err());
+
+        let mut unknown_datatype = pcd.clone();
+        unknown_datatype.fields[3].datatype = 9;
+        assert!(pcd_to_points(&unknown_datatype).is_err());
+
+        let mut beyond_point_step = pcd.clone();
+        beyond_point_step.fields[3].datatype = FLOAT64;
+        assert!(pcd_to_points(&beyond_point_step).is_err());
+
+        let mut short_row_step = pcd.clone();
+        short_row_step.row_step = 16;
+        assert!(pcd_to_points(&short_row_step).is_err());
+
+        let mut truncated = pcd;
+        truncated.data.truncate(20);
+        assert!(pcd_to_points(&truncated).is_err());
+    }
+
+    fn image(encoding: &str, [height, width]: [u32; 2], step: u32, data: Vec<u8>) -> Image {
+        Image {
+            height,
+            width,
+            encoding: encoding.to_string(),
+            is_bigendian: 0,
+            step,
+            data,
+            ..Default::default()
+        }
+    }
+
+    fn bgr_at(mat: &Mat, row: i32, col: i32) -> [u8; 3] {
+        mat.at_2d::<Vec3b>(row, col).unwrap().0
+    }
+
+    #[test]
+    fn decode_bgr8_with_padded_rows() {
+        // 2x2 pixels with 2 padding bytes after each row.
+        let data = vec![
+            1, 2, 3, 4, 5, 6, 0, 0, //
+            7, 8, 9, 10, 11, 12, 0, 0,
+        ];
+        let mat = image_to_mat(&image("bgr8", [2, 2], 8, data)).unwrap();
+
+        assert_eq!(mat.typ(), CV_8UC3);
+        assert_eq!(bgr_at(&mat, 0, 1), [4, 5, 6]);
+        assert_eq!(bgr_at(&mat, 1, 0), [7, 8, 9]);
+        assert_eq!(bgr_at(&mat, 1, 1), [10, 11, 12]);
+    }
+
+    #[test]
+    fn decode_rgb8_case_insensitively() {
+        let data = vec![1, 2, 3, 4, 5, 6];
+        let mat = image_to_mat(&image("RGB8", [1, 2], 6, data)).unwrap();
+
+        assert_eq!(bgr_at(&mat, 0, 0), [3, 2, 1]);
+        assert_eq!(bgr_at(&mat, 0, 1), [6, 5, 4]);
+    }
+
+    #[test]
+    fn decode_mono16_in_both_byte_orders() {
+        let values = [0x1234u16, 0xff00];
+
+        let data = values.iter().flat_map(|val| val.to_le_bytes()).collect();
+        let mat = image_to_mat(&image("mono16", [1, 2], 4, data)).unwrap();
+        assert_eq!(bgr_at(&mat, 0, 0), [0x12; 3]);
+        assert_eq!(bgr_at(&mat, 0, 1), [0xff; 3]);
+
+        let data = values.iter().flat_map(|val| val.to_be_bytes()).collect();
+        let mut msg = image("mono16", [1, 2], 4, data);
+        msg.is_bigendian = 1;
+        let mat = image_to_mat(&msg).unwrap();
+        assert_eq!(bgr_at(&mat, 0, 0), [0x12; 3]);
+        assert_eq!(bgr_at(&mat, 0, 1), [0xff; 3]);
+    }
+
+    #[test]
+    fn decode_yuv422() {
+        // U Y0 V Y1 with black and white luma in the video range.
+        let data = vec![128, 16, 128, 235, 0, 0];
+        let mat = image_to_mat(&image("yuv422", [1, 2], 6, data)).unwrap();
+
+        assert_eq!(bgr_at(&mat, 0, 0), [0; 3]);
+        assert_eq!(bgr_at(&mat, 0, 1), [255; 3]);
+    }
+
+    #[test]
+    fn decode_nv12_with_padded_rows() {
+        // A 2x2 luma plane and an interleaved UV row, each row padded
+        // to 4 bytes.
+        let data = vec![
+            16, 235, 0, 0, //
+            235, 16, 0, 0, //
+            128, 128, 0, 0,
+        ];
+        let mat = image_to_mat(&image("nv12", [2, 2], 4, data)).unwrap();
+
+        assert_eq!(mat.rows(), 2);
+        assert_eq!(mat.cols(), 2);
+        assert_eq!(bgr_at(&mat, 0, 0), [0; 3]);
+        assert_eq!(bgr_at(&mat, 0, 1), [255; 3]);
+        assert_eq!(bgr_at(&mat, 1, 0), [255; 3]);
+        assert_eq!(bgr_at(&mat, 1, 1), [0; 3]);
+    }
+
+    #[test]
+    fn decode_bayer_patterns() {
+        // Fill the red sites of each pattern. The demosaiced image must
+        // be red inside.
+        for (encoding, red_site) in [
+            ("bayer_rggb8", [0, 0]),
+            ("bayer_bggr8", [1, 1]),
+            ("bayer_gbrg8", [1, 0]),
+            ("bayer_grbg8", [0, 1]),
+        ] {
+            let data = (0..6)
+                .flat_map(|row| (0..6).map(move |col| [row % 2, col % 2]))
+                .map(|site| if site == red_site { 255 } else { 0 })
+                .collect();
+            let mat = image_to_mat(&image(encoding, [6, 6], 6, data)).unwrap();
+
+            assert_eq!(bgr_at(&mat, 2, 2), [0, 0, 255], "{}", encoding);
+            assert_eq!(bgr_at(&mat, 3, 3), [0, 0, 255], "{}", encoding);
+        }
+    }
+
+    #[test]
+    fn reject_invalid_images() {
+        assert!(image_to_mat(&image("nv24", [2, 2], 2, vec![0; 12])).is_err());
+        assert!(image_to_mat(&image("8uc2", [1, 1], 2, vec![0; 2])).is_err());
+        // The step is shorter than a row.
+        assert!(image_to_mat(&image("bgr8", [1, 2], 3, vec![0; 6])).is_err());
+        // The data is shorter than the image.
+        assert!(image_to_mat(&image("bgr8", [2, 2], 8, vec![0; 13])).is_err());
+        // NV12 requires an even size.
+        assert!(image_to_mat(&image("nv12", [3, 2], 2, vec![0; 10])).is_err());
+    }
 }
@@ -1,8 +1,3 @@
-use crate::{
-    color_sampling::sample_rgb,
-    config::{Config, Roi3D},
-    message as msg,
-};
 use async_std::task::spawn_blocking;
 use futures::prelude::*;
 use kiss3d::{
@@ -14,6 +9,11 @@ use kiss3d::{
     post_processing::PostProcessingEffect,
     window::Window,
 };
+use newslab_fuse_demo::{
+    color_sampling::sample_rgb,
+    config::{Config, Roi3D},
+    message as msg,
+};
 use num_derive::FromPrimitive;
 use num_traits::FromPrimitive;
 use rayon::prelude::*;
@@ -108,8 +108,7 @@ impl State {
     // A callback method called when a message arrives.
     fn update_msg(&mut self, msg: msg::Kiss3dMessage) {
         let msg::Kiss3dMessage {
-            points,
-            kneron_assocs,
+            points, detections, ..
         } = msg;
 
         // Collect background points
@@ -130,25 +129,22 @@ impl State {
             (point, color)
         });
 
-        // Collect points that are inside at least one bbox
-        let object_points = kneron_assocs
-            .as_ref()
-            .map(|assocs: &msg::ArcAssocVec| {
-                assocs.par_iter().filter_map(|assoc: &msg::Association| {
-                    let point: &msg::Point = &assoc.pcd_point;
-                    let [r, g, b] = match assoc.object.as_deref() {
-                        Some(msg::Object {
-                            class_id: Some(ref class_id),
-                            ..
-                        }) => sample_rgb(class_id),
-                        _ => [0.5, 0.5, 0.5],
-                    };
-                    let color = na::Point3::new(r as f32, g as f32, b as f32);
-                    Some((point, color))
-                })
-            })
-            .into_par_iter()
-            .flatten();
+        // Collect points projected on cameras giving detections
+        let object_points = detections
+            .par_iter()
+            .flat_map(|detection: &msg::DetectionAssocs| detection.assocs.par_iter())
+            .map(|assoc: &msg::Association| {
+                let point: &msg::Point = &assoc.pcd_point;
+                let [r, g, b] = match assoc.object.as_deref() {
+                    Some(msg::Object {
+                        class_id: Some(ref class_id),
+                        ..
+                    }) => sample_rgb(class_id),
+                    _ => [0.5, 0.5, 0.5],
+                };
+                let color = na::Point3::new(r as f32, g as f32, b as f32);
+                (point, color)
+            });
 
         // Store points along with their colors
         self.points = background_points
@@ -0,0 +1,14 @@
+//! The LiDAR and camera fusing pipeline of the demo. The point cloud
+//! parsing and the point projection are also used by
+//! `vision_to_autoware_conv_node`.
+
+pub mod annotate;
+pub mod color_sampling;
+pub mod config;
+pub mod fuse;
+pub mod message;
+pub mod output;
+pub mod point_projection;
+// pub mod rect_rtree;
+pub mod sync;
+pub mod yaml_loader;
@@ -1,27 +1,19 @@
-mod color_sampling;
-mod config;
-mod fuse;
 mod kiss3d_gui;
-mod message;
 mod opencv_gui;
-mod point_projection;
-// mod rect_rtree;
-mod yaml_loader;
-// mod rate_meter;
 
-use crate::{config::Config, message as msg};
 use anyhow::Result;
 use async_std::task::spawn_blocking;
 use clap::Parser;
 use futures::{future, prelude::*};
+use newslab_fuse_demo::{config::Config, fuse, message as msg, output};
 use r2r::{
     log_info,
-    sensor_msgs::msg::{Image, PointCloud2},
+    sensor_msgs::msg::{CompressedImage, Image, PointCloud2},
     vision_msgs::msg::Detection2DArray,
     Context, Node, QosProfile,
 };
-use serde_loader::Json5Path;
-use std::{path::PathBuf, time::Duration};
+use sensor_diagnostics::{spawn_diagnostics, SensorMonitor};
+use std::{path::PathBuf, sync::Arc, time::Duration};
 
 /// The type defines the program arguments.
 #[derive(Parser)]
@@ -36,12 +28,12 @@ async fn main() -> Result<()> {
     let opts = Opts::parse();
 
     // Load the configuration file.
-    let config: Config = Json5Path::open_and_take(&opts.config)?;
+    let config = Config::open(&opts.config)?;
     let Config {
         namespace,
         pcd_topic,
-        otobrite_img_topic,
-        kneron_det_topic,
+        cameras,
+        diagnostics,
         ..
     } = &config;
 
@@ -49,6 +41,13 @@ async fn main() -> Result<()> {
     let ctx = Context::create()?;
     let mut node = Node::create(ctx, env!("CARGO_PKG_NAME"), namespace)?;
 
+    // Publish diagnostics of input topics and the fusing worker.
+    let new_monitor = |name: &str, hardware_id: &str| {
+        let name = format!("{}: {}", env!("CARGO_PKG_NAME"), name);
+        Arc::new(SensorMonitor::new(&name, hardware_id, diagnostics))
+    };
+    let mut monitors = vec![];
+
     // Create ROS subscriptions
     log_info!(
         env!("CARGO_PKG_NAME"),
@@ -56,37 +55,80 @@ async fn main() -> Result<()> {
         pcd_topic
     );
     let pcd_sub = node.subscribe::<PointCloud2>(pcd_topic, QosProfile::default())?;
+    let pcd_monitor = new_monitor("point cloud", pcd_topic);
+    monitors.push(pcd_monitor.clone());
+
+    let mut input_streams = vec![pcd_sub
+        .inspect(move |_| pcd_monitor.bump())
+        .map(msg::InputMessage::PointCloud2)
+        .boxed()];
+
+    for (index, camera) in cameras.iter().enumerate() {
+        if let Some(topic) = &camera.image_topic {
+            log_info!(
+                env!("CARGO_PKG_NAME"),
+                "Subscribe {} camera image from {}",
+                camera.name,
+                topic
+            );
+            let monitor = new_monitor(&format!("{} image", camera.name), topic);
+            monitors.push(monitor.clone());
+
+            let stream = if camera.image_compressed {
+                node.subscribe::<CompressedImage>(topic, QosProfile::default())?
+                    .inspect(move |_| monitor.bump())
+                    .map(move |image| msg::InputMessage::CompressedImage {
+                        camera: index,
+                        image,
+                    })
+                    .boxed()
+            } else {
+                node.subscribe::<Image>(topic, QosProfile::default())?
+                    .inspect(move |_| monitor.bump())
+                    .map(move |image| msg::InputMessage::Image {
+                        camera: index,
+                        image,
+                    })
+                    .boxed()
+            };
+            input_streams.push(stream);
+        }
 
-    log_info!(
-        env!("CARGO_PKG_NAME"),
-        "Subscribe Otobrite camera image from {}",
-        otobrite_img_topic
-    );
-    let otobrite_img_sub = node.subscribe::<Image>(otobrite_img_topic, QosProfile::default())?;
+        if let Some(topic) = &camera.det_topic {
+            log_info!(
+                env!("CARGO_PKG_NAME"),
+                "Subscribe {} camera detection from {}",
+                camera.name,
+                topic
+            );
+            let sub = node.subscribe::<Detection2DArray>(topic, QosProfile::default())?;
+            let monitor = new_monitor(&format!("{} detection", camera.name), topic);
+            monitors.push(monitor.clone());
+
+            let stream = sub
+                .inspect(move |_| monitor.bump())
+                .map(move |det| msg::InputMessage::BBox { camera: index, det })
+                .boxed();
+            input_streams.push(stream);
+        }
+    }
 
-    log_info!(
-        env!("CARGO_PKG_NAME"),
-        "Subscribe Kneron camera detection from {}",
-        kneron_det_topic
-    );
-    let kneron_det_sub =
-        node.subscribe::<Detection2DArray>(kneron_det_topic, QosProfile::default())?;
+    let fuse_monitor = new_monitor("fusion", "");
+    monitors.push(fuse_monitor.clone());
+    spawn_diagnostics(&mut node, diagnostics, monitors)?;
 
     // Merge subscription streams into one stream using `select` operation
-    let input_stream = {
-        let pcd_stream = pcd_sub.map(msg::InputMessage::PointCloud2).boxed();
-        let kneron_det_stream = kneron_det_sub.map(msg::InputMessage::BBox).boxed();
-        let otobrite_img_stream = otobrite_img_sub
-            .map(msg::InputMessage::OtobriteImage)
-            .boxed();
-        futures::stream::select_all([pcd_stream, kneron_det_stream, otobrite_img_stream])
-    };
+    let input_stream = futures::stream::select_all(input_streams);
+
+    // Create publishers of the fusion results
+    let publishers = output::Publishers::new(&mut node, &config)?;
+    let enable_output = !publishers.is_empty();
 
     // Start image/pcd fusing worker
-    let fuse_stream = fuse::start(input_stream, &config)?;
+    let fuse_stream = fuse::start(input_stream, &config, fuse_monitor)?;
 
-    // Split fuse worker output into two parts
-    let (split_future, opencv_rx, kiss3d_rx) = split(fuse_stream.boxed());
+    // Split fuse worker output into three parts
+    let (split_future, opencv_rx, kiss3d_rx, output_rx) = split(fuse_stream.boxed(), enable_output);
 
     // Start OpenCV GUI
     let opencv_future = opencv_gui::start(&config, opencv_rx.into_stream());
@@ -94,6 +136,9 @@ async fn main() -> Result<()> {
     // Start Kiss3d GUI
     let kiss3d_future = kiss3d_gui::start(&config, kiss3d_rx.into_stream());
 
+    // Start publishing fusion results
+    let output_future = output::start(&config, publishers, output_rx.into_stream());
+
     // Create a future to spin the ROS node
     let spin_future = spawn_blocking(move || loop {
         node.spin_once(Duration::from_millis(100));
@@ -101,38 +146,55 @@ async fn main() -> Result<()> {
 
     // Join all futures
     let join1 = future::join3(split_future, kiss3d_future, spin_future);
-    let join2 = future::try_join(join1.map(|_| anyhow::Ok(())), opencv_future);
+    let join2 = future::try_join3(join1.map(|_| anyhow::Ok(())), opencv_future, output_future);
     join2.await?;
 
     Ok(())
 }
 
-/// Splits a stream and forward the messages to two respective channels.
+/// Splits a stream and forward the messages to three respective channels.
 ///
 /// # Returns
-/// It returns a tuple (future, opencv_rx, kiss3d_rx).
+/// It returns a tuple (future, opencv_rx, kiss3d_rx, output_rx).
 /// - `future` is polled to forward the messages in the stream to the senders.
 /// - `opencv_rx` is receiver of the channel that collecting OpenCV messages.
 /// - `kiss3d_rx` is receiver of the channel that collecting Kiss3d messages.
+/// - `output_rx` is receiver of the channel that collecting copies of
+///   all messages for ROS outputs if `enable_output` is true.
+///   Copies are dropped if the channel is full.
 fn split(
     mut stream: impl Stream<Item = msg::FuseMessage> + Unpin + Send,
+    enable_output: bool,
 ) -> (
     impl Future<Output = ()> + Send,
-    flume::Receiver<msg::OpencvMessage>,
+    flume::Receiver<msg::CameraMessage>,
     flume::Receiver<msg::Kiss3dMessage>,
+    flume::Receiver<msg::FuseMessage>,
 ) {
-    // Create two channels
+    // Create three channels
     let (opencv_tx, opencv_rx) = flume::bounded(2);
     let (kiss3d_tx, kiss3d_rx) = flume::bounded(2);
+    let (output_tx, output_rx) = flume::bounded(2);
+    let output_tx = enable_output.then_some(output_tx);
 
     // Create a future that forwards stream messages to respective channels.
     let future = async move {
         while let Some(in_msg) = stream.next().await {
             use msg::FuseMessage as M;
 
+            if let Some(output_tx) = &output_tx {
+                let copy: M = match &in_msg {
+                    M::Camera(msg) => msg.clone().into(),
+                    M::Kiss3d(msg) => msg.clone().into(),
+                };
+
+                if let Err(flume::TrySendError::Disconnected(_)) = output_tx.try_send(copy) {
+                    break;
+                }
+            }
+
             let ok: bool = match in_msg {
-                M::Otobrite(msg) => opencv_tx.send_async(msg.into()).await.is_ok(),
-                M::Kneron(msg) => opencv_tx.send_async(msg.into()).await.is_ok(),
+                M::Camera(msg) => opencv_tx.send_async(msg).await.is_ok(),
                 M::Kiss3d(msg) => kiss3d_tx.send_async(msg).await.is_ok(),
             };
 
@@ -142,5 +204,5 @@ fn split(
         }
     };
 
-    (future, opencv_rx, kiss3d_rx)
+    (future, opencv_rx, kiss3d_rx, output_rx)
 }
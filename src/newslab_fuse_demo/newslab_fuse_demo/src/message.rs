@@ -5,7 +5,8 @@ use opencv::{
 };
 use ownref::ArcRefA as ARef;
 use r2r::{
-    sensor_msgs::msg::{Image, PointCloud2},
+    sensor_msgs::msg::{CompressedImage, Image, PointCloud2},
+    std_msgs::msg::Header,
     vision_msgs::msg::Detection2DArray,
 };
 
@@ -17,20 +18,33 @@ pub type ArcObjVec = ARef<'static, Vec<Object>>;
 pub type ArcObj = ARef<'static, Vec<Object>, Object>;
 pub type ArcAssocVec = ARef<'static, Vec<Association>>;
 
-/// An input message that can be a point cloud from LiDAR, an image
-/// from the Otobrite camera or an image from Kneron camera.
+/// An input message that can be a point cloud from LiDAR, or an image
+/// or detections from a camera.
 #[derive(Debug)]
 pub enum InputMessage {
     PointCloud2(PointCloud2),
-    OtobriteImage(Image),
-    BBox(Detection2DArray),
+    /// An image from the camera at the index of the `cameras` config.
+    Image {
+        camera: usize,
+        image: Image,
+    },
+    /// A JPEG or PNG image from the camera at the index of the
+    /// `cameras` config.
+    CompressedImage {
+        camera: usize,
+        image: CompressedImage,
+    },
+    /// Detections from the camera at the index of the `cameras` config.
+    BBox {
+        camera: usize,
+        det: Detection2DArray,
+    },
 }
 
 /// A message produced by the pcd/image fusing algorithm.
 #[derive(Debug)]
 pub enum FuseMessage {
-    Otobrite(OtobriteMessage),
-    Kneron(KneronMessage),
+    Camera(CameraMessage),
     Kiss3d(Kiss3dMessage),
 }
 
@@ -40,64 +54,58 @@ impl From<Kiss3dMessage> for FuseMessage {
     }
 }
 
-impl From<KneronMessage> for FuseMessage {
-    fn from(v: KneronMessage) -> Self {
-        Self::Kneron(v)
+impl From<CameraMessage> for FuseMessage {
+    fn from(v: CameraMessage) -> Self {
+        Self::Camera(v)
     }
 }
 
-impl From<OtobriteMessage> for FuseMessage {
-    fn from(v: OtobriteMessage) -> Self {
-        Self::Otobrite(v)
-    }
-}
-
-/// A message containing an Otobrite image and projected LiDAR points.
-#[derive(Debug)]
-pub struct OtobriteMessage {
-    pub image: Option<Mat>,
-    pub assocs: Option<ArcAssocVec>,
-}
-
-/// A message containing bboxes from the Kneron camera and projected
+/// A message containing the image and bboxes of a camera and projected
 /// LiDAR points.
-#[derive(Debug)]
-pub struct KneronMessage {
+#[derive(Debug, Clone)]
+pub struct CameraMessage {
+    /// The index of the `cameras` config.
+    pub camera: usize,
+    pub image: Option<Mat>,
+    pub image_header: Option<Header>,
     pub objects: Option<ArcObjVec>,
+    pub det_header: Option<Header>,
     pub assocs: Option<ArcAssocVec>,
+    pub pcd_header: Option<Header>,
+    /// Set if the message is sent for a new point cloud rather than
+    /// for an image or detection update.
+    pub new_pcd: bool,
 }
 
 /// A message that is sent to Kiss3d GUI.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Kiss3dMessage {
     pub points: ArcPointVec,
-    pub kneron_assocs: Option<ArcAssocVec>,
+    pub pcd_header: Option<Header>,
+    /// The projected points of cameras giving detections.
+    pub detections: Vec<DetectionAssocs>,
 }
 
-/// A message that is sent to OpenCV GUI.
-#[derive(Debug)]
-pub enum OpencvMessage {
-    Otobrite(OtobriteMessage),
-    Kneron(KneronMessage),
-}
-
-impl From<KneronMessage> for OpencvMessage {
-    fn from(v: KneronMessage) -> Self {
-        Self::Kneron(v)
-    }
-}
-
-impl From<OtobriteMessage> for OpencvMessage {
-    fn from(v: OtobriteMessage) -> Self {
-        Self::Otobrite(v)
-    }
+/// The bboxes of a camera and projected LiDAR points.
+#[derive(Debug, Clone)]
+pub struct DetectionAssocs {
+    /// The index of the `cameras` config.
+    pub camera: usize,
+    pub objects: Option<ArcObjVec>,
+    pub assocs: ArcAssocVec,
 }
 
 /// A point with a 3D position and an intensity.
 #[derive(Debug)]
 pub struct Point {
     pub position: na::Point3<f32>,
+    /// It is zero if the point cloud has no intensity field.
     pub intensity: f32,
+    /// The laser ring number if the point cloud has a ring field.
+    pub ring: Option<u16>,
+    /// The per-point time in the unit of the point cloud, if it has a
+    /// time field.
+    pub time: Option<f64>,
 }
 
 /// Contains a 3D point with an associated 2D point and an associated bbox.
@@ -1,52 +1,30 @@
-use crate::{color_sampling::sample_rgb, config::Config, message as msg};
 use anyhow::Result;
 use async_std::task::spawn_blocking;
 use futures::prelude::*;
-use nalgebra as na;
+use newslab_fuse_demo::{
+    annotate::{self, PointPainter},
+    config::{CameraConfig, Config},
+    message as msg,
+};
 use opencv::{
-    core::{add_weighted, Point2f, Point2i, Rect, Scalar, Size, CV_32FC3},
+    core::{add_weighted, Rect, Size},
     highgui,
-    imgproc::{self, INTER_LINEAR, LINE_8},
+    imgproc::{self, INTER_LINEAR},
     prelude::*,
 };
-use palette::{Hsv, IntoColor, RgbHue, Srgb};
-use rayon::prelude::*;
-use std::{
-    collections::HashMap,
-    num::NonZeroUsize,
-    ops::RangeInclusive,
-    time::{Duration, Instant},
-};
+use std::time::{Duration, Instant};
 
 const INTERVAL: Duration = Duration::from_millis(34);
 
 pub async fn start(
     config: &Config,
-    stream: impl Stream<Item = msg::OpencvMessage> + Unpin + Send,
+    stream: impl Stream<Item = msg::CameraMessage> + Unpin + Send,
 ) -> Result<()> {
-    let Config {
-        otobrite_image_hw,
-        kneron_image_hw,
-        otobrite_raw_present_size,
-        otobrite_fused_present_size,
-        kneron_det_present_size,
-        kneron_fused_present_size,
-        otobrite_distance_range,
-        kneron_distance_range,
-        otobrite_image_roi_tlbr,
-        kneron_image_roi_tlbr,
-        otobrite_hue_range,
-        ..
-    } = *config;
-
-    let otobrite_distance_range = {
-        let [min, max] = otobrite_distance_range;
-        min..=max
-    };
-    let kneron_distance_range = {
-        let [min, max] = kneron_distance_range;
-        min..=max
-    };
+    let cameras: Vec<_> = config
+        .cameras
+        .iter()
+        .map(CameraState::new)
+        .collect::<Result<_>>()?;
 
     let (tx, rx) = flume::bounded(2);
 
@@ -54,45 +32,7 @@ pub async fn start(
     let handle_future = spawn_blocking(move || {
         use flume::RecvTimeoutError as E;
 
-        let mut state = {
-            let convert_hw = |[h, w]: [NonZeroUsize; 2]| [h.get(), w.get()];
-            let tlbr_to_rect = |[t, l, b, r]: [usize; 4]| -> Rect {
-                let width = r - l;
-                let height = b - t;
-                Rect {
-                    x: l as i32,
-                    y: t as i32,
-                    width: width as i32,
-                    height: height as i32,
-                }
-            };
-
-            let otobrite_image_hw = convert_hw(otobrite_image_hw);
-            let kneron_image_hw = convert_hw(kneron_image_hw);
-            let otobrite_image_roi = tlbr_to_rect(otobrite_image_roi_tlbr);
-            let kneron_image_roi = tlbr_to_rect(kneron_image_roi_tlbr);
-
-            let kneron_image = make_zero_mat(kneron_image_hw);
-            let otobrite_image = make_zero_mat(otobrite_image_hw);
-
-            State {
-                kneron_det_image: kneron_image.clone(),
-                kneron_fused_image: kneron_image,
-                kneron_det_present_size,
-                kneron_fused_present_size,
-                otobrite_raw_image: otobrite_image.clone(),
-                otobrite_fused_image: otobrite_image,
-                otobrite_image_hw,
-                otobrite_raw_present_size,
-                otobrite_fused_present_size,
-                kneron_image_hw,
-                otobrite_distance_range,
-                kneron_distance_range,
-                otobrite_image_roi,
-                kneron_image_roi,
-                otobrite_hue_range,
-            }
-        };
+        let mut state = State { cameras };
         let mut until = Instant::now() + INTERVAL;
 
         loop {
@@ -123,367 +63,155 @@ pub async fn start(
 }
 
 struct State {
-    kneron_det_image: Mat,
-    kneron_fused_image: Mat,
-    kneron_det_present_size: usize,
-    kneron_fused_present_size: usize,
-    otobrite_raw_image: Mat,
-    otobrite_fused_image: Mat,
-    otobrite_image_hw: [usize; 2],
-    otobrite_raw_present_size: usize,
-    otobrite_fused_present_size: usize,
-    kneron_image_hw: [usize; 2],
-    otobrite_distance_range: RangeInclusive<f32>,
-    kneron_distance_range: RangeInclusive<f32>,
-    otobrite_image_roi: Rect,
-    kneron_image_roi: Rect,
-    otobrite_hue_range: [f32; 2],
+    cameras: Vec<CameraState>,
 }
 
 impl State {
     fn step(&mut self) -> Result<()> {
-        highgui::imshow("Otobrite Image", &self.otobrite_raw_image)?;
-        highgui::imshow("Kneron Detection", &self.kneron_det_image)?;
-        highgui::imshow("Kneron Camera + Point Cloud", &self.kneron_fused_image)?;
-        highgui::imshow("Otobrite Camera + Point Cloud", &self.otobrite_fused_image)?;
+        for camera in &self.cameras {
+            highgui::imshow(&camera.raw_title, &camera.raw_image)?;
+            highgui::imshow(&camera.fused_title, &camera.fused_image)?;
+        }
         let _key = highgui::wait_key(1)?;
 
         Ok(())
     }
 
-    fn update(&mut self, msg: msg::OpencvMessage) -> Result<()> {
-        use msg::OpencvMessage as M;
-
-        match msg {
-            M::Otobrite(msg) => self.update_otobrite(msg)?,
-            M::Kneron(msg) => self.update_kneron(msg)?,
-        }
-
-        Ok(())
+    fn update(&mut self, msg: msg::CameraMessage) -> Result<()> {
+        let camera = &mut self.cameras[msg.camera];
+        camera.update(msg)
     }
+}
 
-    fn update_otobrite(&mut self, msg: msg::OtobriteMessage) -> Result<()> {
-        let msg::OtobriteMessage { image, assocs } = msg;
-
-        let orig_image: Mat = image.unwrap_or_else(|| make_zero_mat(self.otobrite_image_hw));
-        let otobrite_raw_image = {
-            let canvas: Mat = orig_image.clone();
-
-            // Crop
-            let canvas = Mat::roi(&canvas, self.otobrite_image_roi)?;
+/// The windows of a camera.
+struct CameraState {
+    /// Shows the image, or the bboxes if the camera gives no images.
+    raw_title: String,
+    raw_image: Mat,
+    raw_present_size: usize,
+    /// Shows the bboxes and projected points over the image.
+    fused_title: String,
+    fused_image: Mat,
+    fused_present_size: usize,
+    image_hw: [usize; 2],
+    image_roi: Rect,
+    painter: PointPainter,
+}
 
-            // Scale image
-            let canvas = {
-                let target_size = self.otobrite_raw_present_size as f64;
-                let fx = target_size / canvas.cols() as f64;
-                let fy = target_size / canvas.rows() as f64;
-                let scale = fx.min(fy);
+impl CameraState {
+    fn new(config: &CameraConfig) -> Result<Self> {
+        let [h, w] = config.image_hw;
+        let image_hw = [h.get(), w.get()];
+        let image_roi = {
+            let [t, l, b, r] = config.image_roi_tlbr;
+            Rect {
+                x: l as i32,
+                y: t as i32,
+                width: (r - l) as i32,
+                height: (b - t) as i32,
+            }
+        };
+        let raw_title = if config.image_topic.is_some() {
+            format!("{} Image", config.name)
+        } else {
+            format!("{} Detection", config.name)
+        };
+        let image = annotate::make_canvas(image_hw)?;
+
+        Ok(Self {
+            raw_title,
+            raw_image: image.clone(),
+            raw_present_size: config.raw_present_size,
+            fused_title: format!("{} Camera + Point Cloud", config.name),
+            fused_image: image,
+            fused_present_size: config.fused_present_size,
+            image_hw,
+            image_roi,
+            painter: PointPainter::new(config),
+        })
+    }
 
-                let mut out = Mat::default();
-                imgproc::resize(
-                    &canvas,
-                    &mut out,
-                    Size::default(),
-                    scale,
-                    scale,
-                    INTER_LINEAR,
-                )?;
-                out
-            };
+    fn update(&mut self, msg: msg::CameraMessage) -> Result<()> {
+        let msg::CameraMessage {
+            image,
+            objects,
+            assocs,
+            ..
+        } = msg;
+        let objects: &[msg::Object] = match &objects {
+            Some(objects) => objects,
+            None => &[],
+        };
 
-            // h-flip
-            let canvas = {
-                let mut output = Mat::default();
-                opencv::core::flip(&canvas, &mut output, 1)?;
-                output
+        let raw_image = {
+            let canvas = match &image {
+                Some(image) => image.clone(),
+                None => {
+                    let mut canvas = annotate::make_canvas(self.image_hw)?;
+                    annotate::draw_objects(&mut canvas, objects, 3, false)?;
+                    canvas
+                }
             };
-
-            canvas
+            self.present(&canvas, self.raw_present_size)?
         };
-        let otobrite_fused_image = {
-            let canvas: Mat = orig_image;
 
-            // Change opacity
-            let mut canvas = {
-                let mut output = Mat::default();
-                add_weighted(&canvas, 0.5, &canvas, 0.0, 0.0, &mut output, -1)?;
-                output
+        let fused_image = {
+            let mut canvas = match &image {
+                // Change opacity
+                Some(image) => {
+                    let mut output = Mat::default();
+                    add_weighted(image, 0.5, image, 0.0, 0.0, &mut output, -1)?;
+                    output
+                }
+                None => annotate::make_canvas(self.image_hw)?,
             };
 
-            // Draw points
-            if let Some(assocs) = assocs {
-                let pixels: HashMap<_, _> = assocs
-                    .par_iter()
-                    .filter_map(|assoc| {
-                        let distance =
-                            na::distance(&na::Point3::origin(), &assoc.pcd_point.position);
-                        self.otobrite_distance_range
-                            .contains(&distance)
-                            .then_some((assoc, distance))
-                    })
-                    .map(|(assoc, distance)| {
-                        let color = {
-                            let dist_min = *self.otobrite_distance_range.start();
-                            let dist_max = *self.otobrite_distance_range.end();
-                            let [hue_min, hue_max] = self.otobrite_hue_range;
-                            let deg = distance.clamp(dist_min, dist_max) / (dist_max - dist_min)
-                                * (hue_max - hue_min)
-                                + hue_min;
-                            let hue = RgbHue::from_degrees(deg);
-                            let hsv = Hsv::new(hue, 1.0, 1.0);
-                            let rgb: Srgb = hsv.into_color();
-                            let (r, g, b) = rgb.into_components();
-                            Scalar::new(b as f64 * 255.0, g as f64 * 255.0, r as f64 * 255.0, 0.0)
-                        };
-
-                        let center = {
-                            let Point2f { x, y } = assoc.img_point;
-                            Point2i::new(x.round() as i32, y.round() as i32)
-                        };
-
-                        let xy = [center.x, center.y];
-                        (xy, (center, color))
-                    })
-                    .collect();
-
-                pixels.into_iter().for_each(|(_, (center, color))| {
-                    imgproc::circle(
-                        &mut canvas,
-                        center,
-                        1, // radius
-                        color,
-                        1, // thickness
-                        LINE_8,
-                        0, // shift
-                    )
-                    .unwrap();
-                });
+            annotate::draw_objects(&mut canvas, objects, 1, false)?;
+            if let Some(assocs) = &assocs {
+                self.painter.draw_points(&mut canvas, assocs)?;
             }
 
-            // Crop
-            let canvas = Mat::roi(&canvas, self.otobrite_image_roi)?;
-
-            // Scale image
-            let canvas = {
-                let target_size = self.otobrite_fused_present_size as f64;
-                let fx = target_size / canvas.cols() as f64;
-                let fy = target_size / canvas.rows() as f64;
-                let scale = fx.min(fy);
-
-                let mut out = Mat::default();
-                imgproc::resize(
-                    &canvas,
-                    &mut out,
-                    Size::default(),
-                    scale,
-                    scale,
-                    INTER_LINEAR,
-                )?;
-                out
-            };
-
-            // h-flip
-            let canvas = {
-                let mut output = Mat::default();
-                opencv::core::flip(&canvas, &mut output, 1)?;
-                output
-            };
-
-            canvas
+            self.present(&canvas, self.fused_present_size)?
         };
 
-        self.otobrite_raw_image = otobrite_raw_image;
-        self.otobrite_fused_image = otobrite_fused_image;
+        self.raw_image = raw_image;
+        self.fused_image = fused_image;
 
         Ok(())
     }
 
-    fn update_kneron(&mut self, msg: msg::KneronMessage) -> Result<()> {
-        let msg::KneronMessage { assocs, objects } = msg;
-        let orig_image: Mat = make_zero_mat(self.kneron_image_hw);
-
-        let kneron_detection_image = {
-            let mut canvas = orig_image.clone();
-
-            // Draw rectangles
-            if let Some(objects) = &objects {
-                objects.iter().for_each(|object: &msg::Object| {
-                    // Sample color using the pointer value of ArcRefC
-                    let [r, g, b] = match &object.class_id {
-                        Some(class_id) => sample_rgb(class_id),
-                        None => [1.0, 1.0, 1.0],
-                    };
-                    let color = Scalar::new(b, g, r, 0.0);
-
-                    imgproc::rectangle(
-                        &mut canvas,
-                        object.rect,
-                        color,
-                        3, // thickness
-                        LINE_8,
-                        0, // shift
-                    )
-                    .unwrap();
-                });
-            }
-
-            // Crop
-            let canvas = Mat::roi(&canvas, self.kneron_image_roi)?;
-
-            // Scale image
-            let canvas = {
-                let target_size = self.kneron_det_present_size as f64;
-                let fx = target_size / canvas.cols() as f64;
-                let fy = target_size / canvas.rows() as f64;
-                let scale = fx.min(fy);
-
-                let mut out = Mat::default();
-                imgproc::resize(
-                    &canvas,
-                    &mut out,
-                    Size::default(),
-                    scale,
-                    scale,
-                    INTER_LINEAR,
-                )?;
-                out
-            };
-
-            // h-flip
-            let canvas = {
-                let mut output = Mat::default();
-                opencv::core::flip(&canvas, &mut output, 1)?;
-                output
-            };
-
-            canvas
+    /// Crops the image to the ROI, scales it to the present size and
+    /// flips it horizontally.
+    fn present(&self, canvas: &Mat, present_size: usize) -> Result<Mat> {
+        // Crop
+        let canvas = Mat::roi(canvas, self.image_roi)?;
+
+        // Scale image
+        let canvas = {
+            let target_size = present_size as f64;
+            let fx = target_size / canvas.cols() as f64;
+            let fy = target_size / canvas.rows() as f64;
+            let scale = fx.min(fy);
+
+            let mut out = Mat::default();
+            imgproc::resize(
+                &canvas,
+                &mut out,
+                Size::default(),
+                scale,
+                scale,
+                INTER_LINEAR,
+            )?;
+            out
         };
 
-        let kneron_fused_image = {
-            let mut canvas = orig_image;
-
-            // Draw rectangles
-            if let Some(objects) = &objects {
-                objects.iter().for_each(|object: &msg::Object| {
-                    // Sample color using the pointer value of ArcRefC
-                    let [r, g, b] = match &object.class_id {
-                        Some(class_id) => sample_rgb(class_id),
-                        None => [1.0, 1.0, 1.0],
-                    };
-                    let color = Scalar::new(b, g, r, 0.0);
-                    let Rect {
-                        x,
-                        y,
-                        height,
-                        width,
-                    } = object.rect;
-                    let new_rect = Rect {
-                        x,
-                        y: y - 100,
-                        height,
-                        width,
-                    };
-
-                    imgproc::rectangle(
-                        &mut canvas,
-                        new_rect,
-                        color,
-                        1, // thickness
-                        LINE_8,
-                        0, // shift
-                    )
-                    .unwrap();
-                });
-            }
-
-            // Draw points
-            if let Some(assocs) = assocs {
-                let pixels: HashMap<_, _> = assocs
-                    .par_iter()
-                    .filter_map(|assoc| {
-                        let distance =
-                            na::distance(&na::Point3::origin(), &assoc.pcd_point.position);
-                        self.kneron_distance_range
-                            .contains(&distance)
-                            .then_some((assoc, distance))
-                    })
-                    .map(|(assoc, _distance)| {
-                        let color = {
-                            let [r, g, b] = match assoc.object.as_deref() {
-                                Some(msg::Object {
-                                    class_id: Some(ref class_id),
-                                    ..
-                                }) => sample_rgb(class_id),
-                                _ => [0.5, 0.5, 0.5],
-                            };
-                            Scalar::new(b, g, r, 0.0)
-                        };
-                        let center = {
-                            let Point2f { x, y } = assoc.img_point;
-                            Point2i::new(x.round() as i32, y.round() as i32)
-                        };
-
-                        let xy = [center.x, center.y];
-                        (xy, (center, color))
-                    })
-                    .collect();
-
-                pixels.into_iter().for_each(|(_, (center, color))| {
-                    imgproc::circle(
-                        &mut canvas,
-                        center,
-                        1, // radius
-                        color,
-                        1, // thickness
-                        LINE_8,
-                        0, // shift
-                    )
-                    .unwrap();
-                });
-            }
-
-            // Crop
-            let canvas = Mat::roi(&canvas, self.kneron_image_roi)?;
-
-            // Scale image
-            let canvas = {
-                let target_size = self.kneron_fused_present_size as f64;
-                let fx = target_size / canvas.cols() as f64;
-                let fy = target_size / canvas.rows() as f64;
-                let scale = fx.min(fy);
-
-                let mut out = Mat::default();
-                imgproc::resize(
-                    &canvas,
-                    &mut out,
-                    Size::default(),
-                    scale,
-                    scale,
-                    INTER_LINEAR,
-                )?;
-                out
-            };
-
-            // h-flip
-            let canvas = {
-                let mut output = Mat::default();
-                opencv::core::flip(&canvas, &mut output, 1)?;
-                output
-            };
-
-            canvas
+        // h-flip
+        let canvas = {
+            let mut output = Mat::default();
+            opencv::core::flip(&canvas, &mut output, 1)?;
+            output
         };
 
-        self.kneron_det_image = kneron_detection_image;
-        self.kneron_fused_image = kneron_fused_image;
-
-        Ok(())
+        Ok(canvas)
     }
 }
-
-fn make_zero_mat([h, w]: [usize; 2]) -> Mat {
-    Mat::zeros(h as i32, w as i32, CV_32FC3)
-        .unwrap()
-        .to_mat()
-        .unwrap()
-}
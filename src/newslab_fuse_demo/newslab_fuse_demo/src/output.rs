@@ -0,0 +1,502 @@
+//! Publishes the fusion results as ROS topics so that other nodes and
+//! RViz can consume them.
+
+use crate::{
+    annotate::{self, class_rgb, PointPainter},
+    config::{Config, Roi3D},
+    message as msg,
+};
+use anyhow::{ensure, Result};
+use async_std::task::spawn_blocking;
+use futures::prelude::*;
+use nalgebra as na;
+use opencv::{
+    core::{Point2i, Vec3b, CV_8UC3},
+    prelude::*,
+};
+use r2r::{
+    geometry_msgs::msg::{Point, Pose, Quaternion, Vector3},
+    log_warn,
+    sensor_msgs::msg::{Image, PointCloud2, PointField},
+    std_msgs::msg::{ColorRGBA, Header},
+    visualization_msgs::msg::{Marker, MarkerArray},
+    Node, Publisher, QosProfile,
+};
+use std::{collections::HashMap, ops::RangeInclusive, ptr};
+
+// The `datatype` values of `sensor_msgs/PointField`.
+const INT32: u8 = 5;
+const UINT32: u8 = 6;
+const FLOAT32: u8 = 7;
+
+// The `type` and `action` values of `visualization_msgs/Marker`.
+const CUBE: i32 = 1;
+const LINE_LIST: i32 = 5;
+const TEXT_VIEW_FACING: i32 = 9;
+const DELETEALL: i32 = 3;
+
+/// The publishers of the enabled outputs.
+pub struct Publishers {
+    cameras: Vec<CameraPublishers>,
+    markers: Option<Publisher<MarkerArray>>,
+}
+
+struct CameraPublishers {
+    colored_pcd: Option<Publisher<PointCloud2>>,
+    labeled_pcd: Option<Publisher<PointCloud2>>,
+    image: Option<Publisher<Image>>,
+}
+
+impl Publishers {
+    pub fn new(node: &mut Node, config: &Config) -> Result<Self> {
+        let cameras = config
+            .cameras
+            .iter()
+            .map(|camera| {
+                let outputs = &camera.outputs;
+                let colored_pcd = outputs
+                    .colored_pcd_topic
+                    .as_ref()
+                    .map(|topic| node.create_publisher::<PointCloud2>(topic, QosProfile::default()))
+                    .transpose()?;
+                let labeled_pcd = outputs
+                    .labeled_pcd_topic
+                    .as_ref()
+                    .map(|topic| node.create_publisher::<PointCloud2>(topic, QosProfile::default()))
+                    .transpose()?;
+                let image = outputs
+                    .image_topic
+                    .as_ref()
+                    .map(|topic| node.create_publisher::<Image>(topic, QosProfile::default()))
+                    .transpose()?;
+
+                anyhow::Ok(CameraPublishers {
+                    colored_pcd,
+                    labeled_pcd,
+                    image,
+                })
+            })
+            .collect::<Result<_>>()?;
+        let markers = config
+            .outputs
+            .marker_topic
+            .as_ref()
+            .map(|topic| node.create_publisher::<MarkerArray>(topic, QosProfile::default()))
+            .transpose()?;
+
+        Ok(Self { cameras, markers })
+    }
+
+    /// Returns true if no output is enabled.
+    pub fn is_empty(&self) -> bool {
+        let no_camera_output = self.cameras.iter().all(|camera| {
+            camera.colored_pcd.is_none() && camera.labeled_pcd.is_none() && camera.image.is_none()
+        });
+        no_camera_output && self.markers.is_none()
+    }
+}
+
+/// Publishes the fusion results in the stream until it ends.
+pub async fn start(
+    config: &Config,
+    publishers: Publishers,
+    stream: impl Stream<Item = msg::FuseMessage> + Unpin + Send,
+) -> Result<()> {
+    let state = State::new(config, publishers);
+    let (tx, rx) = flume::bounded(2);
+
+    let forward_future = stream.map(Ok).forward(tx.into_sink()).map(|_result| ());
+    let handle_future = spawn_blocking(move || {
+        while let Ok(msg) = rx.recv() {
+            // Skip the message on failure rather than stopping the node.
+            if let Err(err) = state.publish(msg) {
+                log_warn!(
+                    env!("CARGO_PKG_NAME"),
+                    "Unable to publish fusion results: {:#}",
+                    err
+                );
+            }
+        }
+        anyhow::Ok(())
+    });
+
+    let ((), result) = futures::join!(forward_future, handle_future);
+    result
+}
+
+struct State {
+    publishers: Publishers,
+    cameras: Vec<CameraState>,
+    pcd_roi: Option<Roi3D>,
+    /// Maps class names to `label` values.
+    labels: HashMap<String, u32>,
+}
+
+struct CameraState {
+    name: String,
+    image_hw: [usize; 2],
+    distance_range: RangeInclusive<f32>,
+    painter: PointPainter,
+}
+
+impl State {
+    fn new(config: &Config, publishers: Publishers) -> Self {
+        let cameras = config
+            .cameras
+            .iter()
+            .map(|camera| {
+                let [h, w] = camera.image_hw;
+                let [min, max] = camera.distance_range;
+
+                CameraState {
+                    name: camera.name.clone(),
+                    image_hw: [h.get(), w.get()],
+                    distance_range: min..=max,
+                    painter: PointPainter::new(camera),
+                }
+            })
+            .collect();
+        let labels = config
+            .outputs
+            .labels
+            .iter()
+            .enumerate()
+            .map(|(index, name)| (name.clone(), index as u32 + 1))
+            .collect();
+
+        Self {
+            publishers,
+            cameras,
+            pcd_roi: config.pcd_roi.to_roi(),
+            labels,
+        }
+    }
+
+    fn publish(&self, msg: msg::FuseMessage) -> Result<()> {
+        use msg::FuseMessage as M;
+
+        match msg {
+            M::Camera(msg) => self.publish_camera(msg),
+            M::Kiss3d(msg) => self.publish_markers(msg),
+        }
+    }
+
+    fn publish_camera(&self, msg: msg::CameraMessage) -> Result<()> {
+        let msg::CameraMessage {
+            camera: index,
+            image,
+            image_header,
+            objects,
+            det_header,
+            assocs,
+            pcd_header,
+            new_pcd,
+        } = msg;
+        let publishers = &self.publishers.cameras[index];
+        let camera = &self.cameras[index];
+        let objects: &[msg::Object] = match &objects {
+            Some(objects) => objects,
+            None => &[],
+        };
+        let assocs: &[msg::Association] = match &assocs {
+            Some(assocs) => assocs,
+            None => &[],
+        };
+
+        // Point clouds are published once per LiDAR scan, while the
+        // image is published on every camera update.
+        let pcd_header = pcd_header.filter(|_| new_pcd);
+
+        // Color points by the pixels they are projected to.
+        if let (Some(publisher), Some(image), Some(pcd_header)) =
+            (&publishers.colored_pcd, &image, &pcd_header)
+        {
+            let mut data = Vec::with_capacity(assocs.len() * 16);
+
+            for assoc in assocs {
+                let Point2i { x, y } = annotate::to_pixel(&assoc.img_point);
+                if !((0..image.cols()).contains(&x) && (0..image.rows()).contains(&y)) {
+                    continue;
+                }
+                let [b, g, r] = image.at_2d::<Vec3b>(y, x)?.0;
+                let position = &assoc.pcd_point.position;
+
+                data.extend(position.coords.iter().flat_map(|val| val.to_le_bytes()));
+                // The PCL convention packs 0x00RRGGBB in a float32.
+                data.extend([b, g, r, 0]);
+            }
+
+            let fields = [
+                ("x", FLOAT32),
+                ("y", FLOAT32),
+                ("z", FLOAT32),
+                ("rgb", FLOAT32),
+            ];
+            publisher.publish(&make_pcd(pcd_header.clone(), &fields, data))?;
+        }
+
+        // Label points by the bboxes they fall in.
+        if let (Some(publisher), Some(pcd_header)) = (&publishers.labeled_pcd, &pcd_header) {
+            let mut data = Vec::with_capacity(assocs.len() * 24);
+
+            for assoc in assocs {
+                let object = object_index(objects, assoc).map_or(-1, |index| index as i32);
+                let label = assoc
+                    .object
+                    .as_ref()
+                    .and_then(|object| self.labels.get(object.class_id.as_ref()?))
+                    .copied()
+                    .unwrap_or(0);
+                let msg::Point {
+                    position,
+                    intensity,
+                    ..
+                } = &*assoc.pcd_point;
+
+                data.extend(position.coords.iter().flat_map(|val| val.to_le_bytes()));
+                data.extend(intensity.to_le_bytes());
+                data.extend(object.to_le_bytes());
+                data.extend(label.to_le_bytes());
+            }
+
+            let fields = [
+                ("x", FLOAT32),
+                ("y", FLOAT32),
+                ("z", FLOAT32),
+                ("intensity", FLOAT32),
+                ("object", INT32),
+                ("label", UINT32),
+            ];
+            publisher.publish(&make_pcd(pcd_header.clone(), &fields, data))?;
+        }
+
+        // Draw bboxes and points on the image, or on a black canvas
+        // without images.
+        if let Some(publisher) = &publishers.image {
+            let header = match image_header.or(det_header) {
+                Some(header) => header,
+                None => return Ok(()),
+            };
+            let mut canvas = match image {
+                Some(image) => image,
+                None => annotate::make_canvas(camera.image_hw)?,
+            };
+
+            annotate::draw_objects(&mut canvas, objects, 2, true)?;
+            camera.painter.draw_points(&mut canvas, assocs)?;
+            publisher.publish(&mat_to_image(header, &canvas)?)?;
+        }
+
+        Ok(())
+    }
+
+    fn publish_markers(&self, msg: msg::Kiss3dMessage) -> Result<()> {
+        let msg::Kiss3dMessage {
+            pcd_header,
+            detections,
+            ..
+        } = msg;
+        let (publisher, pcd_header) = match (&self.publishers.markers, pcd_header) {
+            (Some(publisher), Some(pcd_header)) => (publisher, pcd_header),
+            _ => return Ok(()),
+        };
+
+        // Clear the boxes of the last message.
+        let mut markers = vec![Marker {
+            header: pcd_header.clone(),
+            action: DELETEALL,
+            ..Default::default()
+        }];
+
+        if let Some(roi) = &self.pcd_roi {
+            let points = roi
+                .box_segments()
+                .into_iter()
+                .flatten()
+                .map(|point| to_point(&point))
+                .collect();
+
+            markers.push(Marker {
+                header: pcd_header.clone(),
+                ns: "roi".to_string(),
+                type_: LINE_LIST,
+                pose: to_pose(&na::Point3::origin()),
+                scale: Vector3 {
+                    x: 0.02,
+                    y: 0.0,
+                    z: 0.0,
+                },
+                color: to_color([1.0, 1.0, 1.0], 1.0),
+                points,
+                ..Default::default()
+            });
+        }
+
+        for detection in &detections {
+            let msg::DetectionAssocs {
+                camera: index,
+                objects,
+                assocs,
+            } = detection;
+            let camera = &self.cameras[*index];
+            let objects: &[msg::Object] = match objects {
+                Some(objects) => objects,
+                None => continue,
+            };
+
+            // Compute the extent of the points in each bbox.
+            let mut extents: Vec<Option<(na::Point3<f32>, na::Point3<f32>)>> =
+                vec![None; objects.len()];
+
+            for assoc in assocs.iter() {
+                let index = match object_index(objects, assoc) {
+                    Some(index) => index,
+                    None => continue,
+                };
+                let position = &assoc.pcd_point.position;
+                let distance = na::distance(&na::Point3::origin(), position);
+                if !camera.distance_range.contains(&distance) {
+                    continue;
+                }
+
+                extents[index] = Some(match extents[index] {
+                    Some((min, max)) => (min.inf(position), max.sup(position)),
+                    None => (*position, *position),
+                });
+            }
+
+            for (index, (object, extent)) in objects.iter().zip(extents).enumerate() {
+                let (min, max) = match extent {
+                    Some(extent) => extent,
+                    None => continue,
+                };
+                let center = na::center(&min, &max);
+                let size = max - min;
+                let rgb = class_rgb(object);
+
+                markers.push(Marker {
+                    header: pcd_header.clone(),
+                    ns: format!("{}/objects", camera.name),
+                    id: index as i32,
+                    type_: CUBE,
+                    pose: to_pose(&center),
+                    scale: Vector3 {
+                        x: size.x.max(0.05) as f64,
+                        y: size.y.max(0.05) as f64,
+                        z: size.z.max(0.05) as f64,
+                    },
+                    color: to_color(rgb, 0.5),
+                    ..Default::default()
+                });
+
+                if let Some(class_id) = &object.class_id {
+                    let position = center + na::Vector3::new(0.0, 0.0, size.z / 2.0 + 0.2);
+
+                    markers.push(Marker {
+                        header: pcd_header.clone(),
+                        ns: format!("{}/labels", camera.name),
+                        id: index as i32,
+                        type_: TEXT_VIEW_FACING,
+                        pose: to_pose(&position),
+                        scale: Vector3 {
+                            x: 0.0,
+                            y: 0.0,
+                            z: 0.3,
+                        },
+                        color: to_color(rgb, 1.0),
+                        text: class_id.clone(),
+                        ..Default::default()
+                    });
+                }
+            }
+        }
+
+        publisher.publish(&MarkerArray { markers })?;
+        Ok(())
+    }
+}
+
+/// The index of the bbox that the point falls in.
+fn object_index(objects: &[msg::Object], assoc: &msg::Association) -> Option<usize> {
+    let object = assoc.object.as_deref()?;
+    objects.iter().position(|other| ptr::eq(other, object))
+}
+
+/// Builds an unorganized little-endian point cloud. Every field is 4
+/// bytes in size.
+fn make_pcd(header: Header, fields: &[(&str, u8)], data: Vec<u8>) -> PointCloud2 {
+    let point_step = fields.len() as u32 * 4;
+    let width = data.len() as u32 / point_step;
+    let fields = fields
+        .iter()
+        .zip((0..).step_by(4))
+        .map(|(&(name, datatype), offset)| PointField {
+            name: name.to_string(),
+            offset,
+            datatype,
+            count: 1,
+        })
+        .collect();
+
+    PointCloud2 {
+        header,
+        height: 1,
+        width,
+        fields,
+        is_bigendian: false,
+        point_step,
+        row_step: point_step * width,
+        data,
+        is_dense: true,
+    }
+}
+
+/// Converts a BGR Mat to a ROS image.
+fn mat_to_image(header: Header, mat: &Mat) -> Result<Image> {
+    ensure!(mat.typ() == CV_8UC3, "Expect a CV_8UC3 Mat");
+
+    let data = if mat.is_continuous() {
+        mat.data_bytes()?.to_vec()
+    } else {
+        mat.try_clone()?.data_bytes()?.to_vec()
+    };
+
+    Ok(Image {
+        header,
+        height: mat.rows() as u32,
+        width: mat.cols() as u32,
+        encoding: "bgr8".to_string(),
+        is_bigendian: 0,
+        step: mat.cols() as u32 * 3,
+        data,
+    })
+}
+
+fn to_color([r, g, b]: [f64; 3], a: f32) -> ColorRGBA {
+    ColorRGBA {
+        r: r as f32,
+        g: g as f32,
+        b: b as f32,
+        a,
+    }
+}
+
+fn to_point(point: &na::Point3<f32>) -> Point {
+    Point {
+        x: point.x as f64,
+        y: point.y as f64,
+        z: point.z as f64,
+    }
+}
+
+fn to_pose(position: &na::Point3<f32>) -> Pose {
+    Pose {
+        position: to_point(position),
+        orientation: Quaternion {
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+            w: 1.0,
+        },
+    }
+}
@@ -0,0 +1,464 @@
+//! Pairs point clouds with camera messages by header stamps in the
+//! manner of the ApproximateTime policy of ROS message_filters.
+//!
+//! Each point cloud is held until every camera input has a message at
+//! or after its stamp, or until a message newer than the window
+//! arrives. The nearest camera messages within the tolerance are then
+//! emitted before the point cloud, so that the point cloud is fused
+//! with them.
+//!
+//! Point clouds are the reference clock. Camera messages stamped more
+//! than the window ahead of the newest point cloud are dropped, so that
+//! a camera on a skewed clock cannot time out every point cloud.
+
+use crate::{
+    config::{CameraConfig, SyncConfig},
+    message as msg,
+};
+use r2r::builtin_interfaces::msg::Time;
+use std::collections::VecDeque;
+
+/// The messages released by a [Synchronizer].
+#[derive(Debug, Default)]
+pub struct SyncOutput {
+    /// The messages in the order to be processed.
+    pub messages: Vec<msg::InputMessage>,
+    /// The number of messages dropped without a pair.
+    pub unmatched: usize,
+    /// The number of messages dropped for arriving too late, or for
+    /// being stamped too far ahead of the point clouds.
+    pub late: usize,
+}
+
+pub struct Synchronizer {
+    window: f64,
+    tolerance: f64,
+    pcds: VecDeque<(f64, msg::InputMessage)>,
+    /// The buffers of camera inputs.
+    inputs: Vec<(InputKey, VecDeque<(f64, msg::InputMessage)>)>,
+    /// The newest stamp among point clouds.
+    latest_pcd: Option<f64>,
+    /// The stamp of the last released point cloud.
+    last_released: Option<f64>,
+}
+
+impl Synchronizer {
+    pub fn new(config: &SyncConfig, cameras: &[CameraConfig]) -> Self {
+        let keys = cameras.iter().enumerate().flat_map(|(index, camera)| {
+            let image = camera.image_topic.as_ref().map(|_| InputKey::Image(index));
+            let bbox = camera.det_topic.as_ref().map(|_| InputKey::BBox(index));
+            image.into_iter().chain(bbox)
+        });
+        Self::with_inputs(config, keys)
+    }
+
+    fn with_inputs(config: &SyncConfig, keys: impl IntoIterator<Item = InputKey>) -> Self {
+        let inputs = keys.into_iter().map(|key| (key, VecDeque::new())).collect();
+
+        Self {
+            window: config.window_secs,
+            tolerance: config.tolerance_secs,
+            pcds: VecDeque::new(),
+            inputs,
+            latest_pcd: None,
+            last_released: None,
+        }
+    }
+
+    /// Buffers an input message and releases the point clouds that are
+    /// settled, along with their paired camera messages.
+    pub fn push(&mut self, in_msg: msg::InputMessage) -> SyncOutput {
+        use msg::InputMessage as M;
+
+        let mut output = SyncOutput::default();
+        let (stamp, key) = match &in_msg {
+            M::PointCloud2(pcd) => (to_secs(&pcd.header.stamp), None),
+            M::Image { camera, image } => {
+                (to_secs(&image.header.stamp), Some(InputKey::Image(*camera)))
+            }
+            M::CompressedImage { camera, image } => {
+                (to_secs(&image.header.stamp), Some(InputKey::Image(*camera)))
+            }
+            M::BBox { camera, det } => (to_secs(&det.header.stamp), Some(InputKey::BBox(*camera))),
+        };
+        let is_pcd = key.is_none();
+
+        // Point clouds cannot be released out of order, and camera
+        // messages cannot be paired with released point clouds.
+        if let Some(last_released) = self.last_released {
+            let is_late = if is_pcd {
+                stamp <= last_released
+            } else {
+                stamp < last_released - self.tolerance
+            };
+            if is_late {
+                output.late += 1;
+                return output;
+            }
+        }
+
+        // Camera messages far ahead of the point clouds are on a
+        // different clock or wrongly stamped.
+        if let (false, Some(latest_pcd)) = (is_pcd, self.latest_pcd) {
+            if stamp > latest_pcd + self.window {
+                output.late += 1;
+                return output;
+            }
+        }
+
+        let buffer = match key {
+            None => &mut self.pcds,
+            Some(key) => match self.inputs.iter_mut().find(|(other, _)| *other == key) {
+                Some((_, buffer)) => buffer,
+                None => {
+                    // The input is not configured.
+                    output.unmatched += 1;
+                    return output;
+                }
+            },
+        };
+        let index = buffer.partition_point(|(other, _)| *other <= stamp);
+        buffer.insert(index, (stamp, in_msg));
+
+        if is_pcd {
+            let latest_pcd = self.latest_pcd.map_or(stamp, |latest| latest.max(stamp));
+            self.latest_pcd = Some(latest_pcd);
+
+            // Drop camera messages received before the first point
+            // cloud that are too far ahead of it.
+            for (_, buffer) in &mut self.inputs {
+                while buffer
+                    .back()
+                    .map_or(false, |(other, _)| *other > latest_pcd + self.window)
+                {
+                    buffer.pop_back();
+                    output.late += 1;
+                }
+            }
+        }
+
+        // The newest stamp among the point clouds and the buffered
+        // camera messages.
+        let latest = self
+            .inputs
+            .iter()
+            .filter_map(|(_, buffer)| buffer.back().map(|(other, _)| *other))
+            .chain(self.latest_pcd)
+            .fold(stamp, f64::max);
+
+        // Release settled point clouds.
+        while let Some(&(pcd_stamp, _)) = self.pcds.front() {
+            let timed_out = latest >= pcd_stamp + self.window;
+            let complete = self.inputs.iter().all(|(_, buffer)| {
+                buffer
+                    .back()
+                    .map_or(false, |(other, _)| *other >= pcd_stamp)
+            });
+            if !(timed_out || complete) {
+                break;
+            }
+
+            let (_, pcd) = self.pcds.pop_front().unwrap();
+            let paired: Vec<_> = self
+                .inputs
+                .iter_mut()
+                .filter_map(|(_, buffer)| {
+                    take_nearest(buffer, pcd_stamp, self.tolerance, &mut output)
+                })
+                .collect();
+            if paired.is_empty() {
+                output.unmatched += 1;
+            }
+
+            output.messages.extend(paired);
+            output.messages.push(pcd);
+            self.last_released = Some(pcd_stamp);
+        }
+
+        // Drop camera messages that no point cloud can be paired with.
+        let min_stamp = match self.pcds.front() {
+            Some(&(pcd_stamp, _)) => pcd_stamp - self.tolerance,
+            None => {
+                let min_stamp = latest - self.window;
+                match self.last_released {
+                    Some(last_released) => min_stamp.max(last_released - self.tolerance),
+                    None => min_stamp,
+                }
+            }
+        };
+        for (_, buffer) in &mut self.inputs {
+            while buffer
+                .front()
+                .map_or(false, |(other, _)| *other < min_stamp)
+            {
+                buffer.pop_front();
+                output.unmatched += 1;
+            }
+        }
+
+        output
+    }
+}
+
+/// Identifies a camera input by the camera index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InputKey {
+    Image(usize),
+    BBox(usize),
+}
+
+/// Removes and returns the message nearest to the stamp within the
+/// tolerance. Older messages are dropped as unmatched.
+fn take_nearest(
+    buffer: &mut VecDeque<(f64, msg::InputMessage)>,
+    stamp: f64,
+    tolerance: f64,
+    output: &mut SyncOutput,
+) -> Option<msg::InputMessage> {
+    let (index, diff) = buffer
+        .iter()
+        .enumerate()
+        .map(|(index, (other, _))| (index, (other - stamp).abs()))
+        .min_by(|(_, lhs), (_, rhs)| lhs.total_cmp(rhs))?;
+    if diff > tolerance {
+        return None;
+    }
+
+    output.unmatched += index;
+    buffer.drain(..index);
+    buffer.pop_front().map(|(_, in_msg)| in_msg)
+}
+
+fn to_secs(stamp: &Time) -> f64 {
+    stamp.sec as f64 + stamp.nanosec as f64 / 1e9
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use r2r::{
+        sensor_msgs::msg::{Image, PointCloud2},
+        std_msgs::msg::Header,
+        vision_msgs::msg::Detection2DArray,
+    };
+
+    /// A camera giving images and a camera giving detections.
+    fn synchronizer() -> Synchronizer {
+        let config = SyncConfig {
+            window_secs: 0.3,
+            tolerance_secs: 0.05,
+        };
+        Synchronizer::with_inputs(&config, [InputKey::Image(0), InputKey::BBox(1)])
+    }
+
+    fn header(millis: u32) -> Header {
+        Header {
+            stamp: Time {
+                sec: (millis / 1000) as i32,
+                nanosec: millis % 1000 * 1_000_000,
+            },
+            ..Default::default()
+        }
+    }
+
+    fn pcd(millis: u32) -> msg::InputMessage {
+        msg::InputMessage::PointCloud2(PointCloud2 {
+            header: header(millis),
+            ..Default::default()
+        })
+    }
+
+    fn image(millis: u32) -> msg::InputMessage {
+        msg::InputMessage::Image {
+            camera: 0,
+            image: Image {
+                header: header(millis),
+                ..Default::default()
+            },
+        }
+    }
+
+    fn bbox(millis: u32) -> msg::InputMessage {
+        msg::InputMessage::BBox {
+            camera: 1,
+            det: Detection2DArray {
+                header: header(millis),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Describes released messages by kinds and stamps in milliseconds.
+    fn released(output: &SyncOutput) -> Vec<(&'static str, u32)> {
+        use msg::InputMessage as M;
+
+        output
+            .messages
+            .iter()
+            .map(|in_msg| {
+                let (kind, stamp) = match in_msg {
+                    M::PointCloud2(pcd) => ("pcd", &pcd.header.stamp),
+                    M::Image { image, .. } => ("image", &image.header.stamp),
+                    M::CompressedImage { image, .. } => ("image", &image.header.stamp),
+                    M::BBox { det, .. } => ("bbox", &det.header.stamp),
+                };
+                (kind, (to_secs(stamp) * 1000.0).round() as u32)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn release_when_complete() {
+        let mut sync = synchronizer();
+
+        assert!(released(&sync.push(pcd(1000))).is_empty());
+        assert!(released(&sync.push(image(1010))).is_empty());
+        // Detections before the point cloud do not complete it.
+        assert!(released(&sync.push(bbox(990))).is_empty());
+
+        let output = sync.push(bbox(1020));
+        assert_eq!(
+            released(&output),
+            [("image", 1010), ("bbox", 990), ("pcd", 1000)]
+        );
+        assert_eq!(output.unmatched, 0);
+        assert_eq!(output.late, 0);
+    }
+
+    #[test]
+    fn release_on_window_timeout() {
+        let mut sync = synchronizer();
+
+        sync.push(pcd(1000));
+        assert!(released(&sync.push(image(1000))).is_empty());
+
+        // No detections arrive. A message beyond the window releases
+        // the point cloud with the image only.
+        let output = sync.push(image(1300));
+        assert_eq!(released(&output), [("image", 1000), ("pcd", 1000)]);
+        assert_eq!(output.unmatched, 0);
+    }
+
+    #[test]
+    fn count_unpaired_point_cloud() {
+        let mut sync = synchronizer();
+
+        sync.push(pcd(1000));
+        let output = sync.push(pcd(1400));
+        assert_eq!(released(&output), [("pcd", 1000)]);
+        assert_eq!(output.unmatched, 1);
+    }
+
+    #[test]
+    fn skip_messages_beyond_tolerance() {
+        let mut sync = synchronizer();
+
+        sync.push(pcd(1000));
+        sync.push(image(1100));
+        let output = sync.push(bbox(1000));
+        assert_eq!(released(&output), [("bbox", 1000), ("pcd", 1000)]);
+        assert_eq!(output.unmatched, 0);
+
+        // The image is kept for later point clouds.
+        sync.push(pcd(1100));
+        let output = sync.push(bbox(1100));
+        assert_eq!(
+            released(&output),
+            [("image", 1100), ("bbox", 1100), ("pcd", 1100)]
+        );
+    }
+
+    #[test]
+    fn drop_late_messages() {
+        let mut sync = synchronizer();
+
+        sync.push(pcd(1000));
+        sync.push(image(1000));
+        sync.push(bbox(1000));
+
+        // Point clouds at or before the released one are late.
+        assert_eq!(sync.push(pcd(900)).late, 1);
+        assert_eq!(sync.push(pcd(1000)).late, 1);
+
+        // Camera messages are late beyond the tolerance.
+        assert_eq!(sync.push(image(900)).late, 1);
+        let output = sync.push(image(960));
+        assert_eq!(output.late, 0);
+        assert!(output.messages.is_empty());
+    }
+
+    #[test]
+    fn drain_older_messages_when_pairing() {
+        let mut sync = synchronizer();
+
+        sync.push(image(970));
+        sync.push(image(980));
+        sync.push(image(1000));
+        sync.push(bbox(1000));
+
+        let output = sync.push(pcd(1000));
+        assert_eq!(
+            released(&output),
+            [("image", 1000), ("bbox", 1000), ("pcd", 1000)]
+        );
+        assert_eq!(output.unmatched, 2);
+    }
+
+    #[test]
+    fn prune_stale_messages() {
+        let mut sync = synchronizer();
+
+        assert_eq!(sync.push(image(1000)).unmatched, 0);
+        // Without point clouds, messages older than the window are
+        // dropped.
+        let output = sync.push(image(1500));
+        assert!(output.messages.is_empty());
+        assert_eq!(output.unmatched, 1);
+    }
+
+    #[test]
+    fn drop_future_stamped_messages() {
+        let mut sync = synchronizer();
+
+        // Received before any point cloud, then dropped by the first
+        // point cloud.
+        sync.push(image(9000));
+        let output = sync.push(pcd(1000));
+        assert!(output.messages.is_empty());
+        assert_eq!(output.late, 1);
+
+        sync.push(image(1000));
+        let output = sync.push(bbox(1000));
+        assert_eq!(
+            released(&output),
+            [("image", 1000), ("bbox", 1000), ("pcd", 1000)]
+        );
+
+        // Received after point clouds.
+        let output = sync.push(image(9000));
+        assert!(output.messages.is_empty());
+        assert_eq!(output.late, 1);
+
+        // Normal pairing goes on without timeouts.
+        assert!(released(&sync.push(pcd(1100))).is_empty());
+        assert!(released(&sync.push(image(1100))).is_empty());
+        let output = sync.push(bbox(1100));
+        assert_eq!(
+            released(&output),
+            [("image", 1100), ("bbox", 1100), ("pcd", 1100)]
+        );
+        assert_eq!(output.unmatched, 0);
+        assert_eq!(output.late, 0);
+    }
+
+    #[test]
+    fn count_unconfigured_inputs() {
+        let mut sync = synchronizer();
+
+        let output = sync.push(msg::InputMessage::BBox {
+            camera: 5,
+            det: Detection2DArray::default(),
+        });
+        assert_eq!(output.unmatched, 1);
+    }
+}
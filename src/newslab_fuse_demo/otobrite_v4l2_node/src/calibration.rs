@@ -0,0 +1,104 @@
+//! Loads camera calibration files and converts them to
+//! `sensor_msgs/CameraInfo` messages.
+
+use anyhow::{ensure, Context, Result};
+use r2r::sensor_msgs::msg::{CameraInfo, RegionOfInterest};
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+
+/// The calibration file format written by MRPT camera-calib and by
+/// the ROS camera_calibration package.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Calibration {
+    #[serde(default)]
+    pub camera_name: String,
+    pub image_width: u32,
+    pub image_height: u32,
+    pub distortion_model: String,
+    pub distortion_coefficients: Matrix,
+    pub camera_matrix: Matrix,
+    pub rectification_matrix: Matrix,
+    pub projection_matrix: Matrix,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Matrix {
+    pub rows: usize,
+    pub cols: usize,
+    pub data: Vec<f64>,
+}
+
+impl Matrix {
+    fn check(&self, name: &str, rows: usize, cols: usize) -> Result<()> {
+        ensure!(
+            self.rows == rows && self.cols == cols && self.data.len() == rows * cols,
+            "{} must be a {}x{} matrix, but get {}x{} with {} values",
+            name,
+            rows,
+            cols,
+            self.rows,
+            self.cols,
+            self.data.len()
+        );
+        Ok(())
+    }
+}
+
+impl Calibration {
+    /// Loads a calibration YAML file.
+    pub fn load<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("unable to read calibration file {}", path.display()))?;
+        let calib: Self = serde_yaml::from_str(&text)
+            .with_context(|| format!("unable to parse calibration file {}", path.display()))?;
+
+        calib.camera_matrix.check("camera_matrix", 3, 3)?;
+        calib
+            .rectification_matrix
+            .check("rectification_matrix", 3, 3)?;
+        calib.projection_matrix.check("projection_matrix", 3, 4)?;
+        ensure!(
+            calib.distortion_coefficients.data.len()
+                == calib.distortion_coefficients.rows * calib.distortion_coefficients.cols,
+            "distortion_coefficients has a wrong number of values"
+        );
+
+        Ok(calib)
+    }
+
+    /// Creates a `CameraInfo` message with an empty header.
+    pub fn to_camera_info(&self) -> CameraInfo {
+        let coefs = &self.distortion_coefficients.data;
+
+        // MRPT pads plumb_bob coefficients with zeros to 8 values,
+        // while ROS expects exactly 5 values. A non-zero padding
+        // indicates the rational polynomial model.
+        let (distortion_model, d) = if self.distortion_model == "plumb_bob" && coefs.len() > 5 {
+            if coefs[5..].iter().all(|&coef| coef == 0.0) {
+                ("plumb_bob".to_string(), coefs[..5].to_vec())
+            } else {
+                ("rational_polynomial".to_string(), coefs.clone())
+            }
+        } else {
+            (self.distortion_model.clone(), coefs.clone())
+        };
+
+        CameraInfo {
+            height: self.image_height,
+            width: self.image_width,
+            distortion_model,
+            d,
+            k: self.camera_matrix.data.clone(),
+            r: self.rectification_matrix.data.clone(),
+            p: self.projection_matrix.data.clone(),
+            binning_x: 0,
+            binning_y: 0,
+            roi: RegionOfInterest::default(),
+            ..Default::default()
+        }
+    }
+}
@@ -0,0 +1,132 @@
+//! Maps V4L2 buffer timestamps to the wall clock.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// An offset change larger than it is taken as a wall clock step.
+const STEP_NANOS: i128 = 1_000_000;
+
+/// Tracks the offset from the monotonic clock, which V4L2 drivers
+/// stamp buffers with, to the wall clock.
+///
+/// The offset is sampled on every frame to follow wall clock
+/// adjustments. Small changes are smoothed to hide the jitter between
+/// the two clock readings, while a clock step is taken immediately.
+#[derive(Debug, Clone, Default)]
+pub struct BufferClock {
+    offset_nanos: Option<i128>,
+}
+
+impl BufferClock {
+    /// Converts a buffer timestamp in microseconds to the wall clock.
+    pub fn wall_time(&mut self, timestamp_micros: u64) -> SystemTime {
+        let sample = nanos_since_epoch(SystemTime::now()) - monotonic_nanos();
+        let offset = match self.offset_nanos {
+            Some(offset) if (sample - offset).abs() < STEP_NANOS => offset + (sample - offset) / 16,
+            _ => sample,
+        };
+        self.offset_nanos = Some(offset);
+
+        let nanos = timestamp_micros as i128 * 1000 + offset;
+        UNIX_EPOCH + Duration::from_nanos(nanos.max(0) as u64)
+    }
+}
+
+/// Numbers frames by buffer timestamps and detects dropped frames.
+///
+/// rscam does not expose the `sequence` field of V4L2 buffers, so this
+/// heuristic replaces it. The gap between two buffer timestamps is
+/// divided by the configured frame interval and rounded, so jitter
+/// below half an interval is not counted as drops. If the driver runs
+/// at a different interval than the configured one, the drop counts
+/// are wrong.
+#[derive(Debug, Clone, Default)]
+pub struct FrameSequence {
+    last_timestamp: Option<u64>,
+    next: u64,
+}
+
+impl FrameSequence {
+    /// Returns the sequence number of a frame and the number of frames
+    /// dropped right before it.
+    pub fn update(&mut self, timestamp_micros: u64, period: Duration) -> (u64, u64) {
+        let period_micros = period.as_micros() as f64;
+        let dropped = match self.last_timestamp {
+            Some(last) if timestamp_micros > last && period_micros > 0.0 => {
+                let gap = (timestamp_micros - last) as f64 / period_micros;
+                (gap.round() as u64).saturating_sub(1)
+            }
+            _ => 0,
+        };
+        self.last_timestamp = Some(timestamp_micros);
+
+        let sequence = self.next + dropped;
+        self.next = sequence + 1;
+        (sequence, dropped)
+    }
+
+    /// Forgets the last timestamp after the device is reopened.
+    pub fn reset(&mut self) {
+        self.last_timestamp = None;
+    }
+}
+
+fn monotonic_nanos() -> i128 {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: `ts` is a valid timespec to write to.
+    unsafe {
+        libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts);
+    }
+    ts.tv_sec as i128 * 1_000_000_000 + ts.tv_nsec as i128
+}
+
+fn nanos_since_epoch(time: SystemTime) -> i128 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(dur) => dur.as_nanos() as i128,
+        Err(err) => -(err.duration().as_nanos() as i128),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PERIOD: Duration = Duration::from_millis(33);
+
+    #[test]
+    fn ignore_jitter_without_drops() {
+        let mut sequence = FrameSequence::default();
+        let jitters: [i64; 8] = [0, 8_000, -6_000, 7_000, -5_000, 9_000, -7_000, 0];
+
+        for (index, jitter) in jitters.into_iter().enumerate() {
+            let timestamp = 1_000_000 + index as i64 * 33_000 + jitter;
+            let (seq, dropped) = sequence.update(timestamp as u64, PERIOD);
+            assert_eq!((seq, dropped), (index as u64, 0), "frame {}", index);
+        }
+    }
+
+    #[test]
+    fn count_dropped_frames() {
+        let mut sequence = FrameSequence::default();
+
+        assert_eq!(sequence.update(1_000_000, PERIOD), (0, 0));
+        assert_eq!(sequence.update(1_033_000, PERIOD), (1, 0));
+        // Two frames are missing.
+        assert_eq!(sequence.update(1_132_000, PERIOD), (4, 2));
+        assert_eq!(sequence.update(1_165_000, PERIOD), (5, 0));
+    }
+
+    #[test]
+    fn keep_counting_after_reset() {
+        let mut sequence = FrameSequence::default();
+
+        sequence.update(1_000_000, PERIOD);
+        sequence.update(1_033_000, PERIOD);
+        // The device is reopened and timestamps restart.
+        sequence.reset();
+        assert_eq!(sequence.update(5_000_000, PERIOD), (2, 0));
+        assert_eq!(sequence.update(5_033_000, PERIOD), (3, 0));
+    }
+}
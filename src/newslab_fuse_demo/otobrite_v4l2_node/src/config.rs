@@ -1,11 +1,268 @@
+use crate::controls::ControlValue;
+use anyhow::{ensure, Result};
+use sensor_diagnostics::DiagnosticsConfig;
 use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, path::PathBuf, time::Duration};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
     pub namespace: String,
     pub topic: String,
+    /// The TF frame id of published images.
+    pub frame_id: String,
+    #[serde(default)]
+    pub clock: ClockSource,
+    /// Where frames come from. The V4L2 device is used by default.
+    #[serde(default)]
+    pub source: Source,
+    /// The device path. A stable udev link such as
+    /// `/dev/v4l/by-path/...` is resolved again on every reconnect.
     pub video_device: String,
-    pub format: String,
+    /// If it is set, the device with the serial number is looked up in
+    /// `/dev/v4l/by-id` instead of using `video_device`.
+    #[serde(default)]
+    pub device_serial: Option<String>,
+    #[serde(default)]
+    pub reconnect: ReconnectConfig,
+    /// The V4L2 controls set on the camera, keyed by the names listed
+    /// by `v4l2-ctl --list-ctrls`. Each control is also exposed as the
+    /// ROS parameter `controls.<name>`.
+    #[serde(default)]
+    pub controls: BTreeMap<String, ControlValue>,
+    /// The pixel format captured from the camera.
+    pub format: PixelFormat,
+    /// Convert frames to this encoding on capture. If it is not set,
+    /// frames are published in the native format of the camera.
+    #[serde(default)]
+    pub encoding: Option<Encoding>,
     pub resolution: (u32, u32),
+    /// The frame interval in seconds as a fraction.
     pub interval: (u32, u32),
+    /// The MRPT or ROS calibration YAML file, relative to the config
+    /// file. If it is set, the calibration is published on
+    /// `<topic>/camera_info`.
+    #[serde(default)]
+    pub camera_info_file: Option<PathBuf>,
+    /// Publish JPEG images on `<topic>/compressed`.
+    #[serde(default)]
+    pub compressed: Option<CompressedConfig>,
+    /// Publish downscaled images on `<topic>/preview`.
+    #[serde(default)]
+    pub preview: Option<PreviewConfig>,
+    #[serde(default)]
+    pub diagnostics: DiagnosticsConfig,
+}
+
+impl Config {
+    /// The frame interval. It fails if `interval` is not positive.
+    pub fn period(&self) -> Result<Duration> {
+        let (num, den) = self.interval;
+        ensure!(
+            num > 0 && den > 0,
+            "the frame interval {}/{} must be positive",
+            num,
+            den
+        );
+        Ok(Duration::from_secs_f64(num as f64 / den as f64))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompressedConfig {
+    /// The JPEG quality from 0 to 100.
+    #[serde(default = "default_jpeg_quality")]
+    pub quality: u8,
+    /// The maximum publishing rate in Hz. Every frame is published if
+    /// it is not set.
+    #[serde(default)]
+    pub max_rate: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PreviewConfig {
+    /// The image width and height are divided by it.
+    pub downscale: u32,
+    /// The maximum publishing rate in Hz. Every frame is published if
+    /// it is not set.
+    #[serde(default)]
+    pub max_rate: Option<f64>,
+}
+
+fn default_jpeg_quality() -> u8 {
+    80
+}
+
+/// The clock that image timestamps are taken from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClockSource {
+    /// The buffer timestamp from the driver, shifted to the wall
+    /// clock.
+    #[default]
+    Buffer,
+    /// The time when the frame is received.
+    Receive,
+}
+
+/// A source of frames.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Source {
+    /// The V4L2 device.
+    #[default]
+    V4l2,
+    /// JPEG or PNG files in the directory, played in name order in a
+    /// loop. Each file is decoded as a `MJPG` frame.
+    ImageDir { dir: PathBuf },
+    /// A file of raw frames in `format`, stored back to back and played
+    /// in a loop.
+    RawFile { path: PathBuf },
+    /// Moving color bars in `format`.
+    Pattern,
+}
+
+impl Source {
+    /// The pixel format of the frames from the source.
+    pub fn pixel_format(&self, format: PixelFormat) -> PixelFormat {
+        match self {
+            Self::ImageDir { .. } => PixelFormat::Mjpeg,
+            Self::V4l2 | Self::RawFile { .. } | Self::Pattern => format,
+        }
+    }
+}
+
+/// The backoff policy to reopen a failed device.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReconnectConfig {
+    /// The delay in seconds before the first attempt.
+    pub initial_backoff_secs: f64,
+    /// The delay is doubled on each failed attempt up to this many
+    /// seconds.
+    pub max_backoff_secs: f64,
+    /// The node exits after this many consecutive failed attempts.
+    /// It retries forever if it is not set.
+    pub max_attempts: Option<u32>,
+}
+
+impl ReconnectConfig {
+    /// Checks that the backoff delays are valid.
+    pub fn check(&self) -> Result<()> {
+        let Self {
+            initial_backoff_secs: initial,
+            max_backoff_secs: max,
+            ..
+        } = *self;
+        ensure!(
+            initial.is_finite() && initial >= 0.0,
+            "reconnect.initial_backoff_secs must be a non-negative number"
+        );
+        ensure!(
+            max.is_finite() && max >= 0.0,
+            "reconnect.max_backoff_secs must be a non-negative number"
+        );
+        ensure!(
+            initial <= max,
+            "reconnect.initial_backoff_secs must not exceed reconnect.max_backoff_secs"
+        );
+        Ok(())
+    }
+
+    /// The delay before the n-th consecutive attempt, counting from
+    /// zero.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let secs = self.initial_backoff_secs * 2f64.powi(attempt.min(64) as i32);
+        Duration::from_secs_f64(secs.min(self.max_backoff_secs))
+    }
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        Self {
+            initial_backoff_secs: 0.5,
+            max_backoff_secs: 10.0,
+            max_attempts: None,
+        }
+    }
+}
+
+/// The V4L2 pixel formats supported by the node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PixelFormat {
+    #[serde(rename = "UYVY")]
+    Uyvy,
+    #[serde(rename = "YUYV")]
+    Yuyv,
+    #[serde(rename = "NV12")]
+    Nv12,
+    #[serde(rename = "GREY")]
+    Grey,
+    #[serde(rename = "MJPG", alias = "MJPEG")]
+    Mjpeg,
+    #[serde(rename = "RGB3", alias = "RGB8")]
+    Rgb8,
+}
+
+impl PixelFormat {
+    /// The V4L2 FourCC code.
+    pub fn fourcc(&self) -> &'static [u8] {
+        match self {
+            Self::Uyvy => b"UYVY",
+            Self::Yuyv => b"YUYV",
+            Self::Nv12 => b"NV12",
+            Self::Grey => b"GREY",
+            Self::Mjpeg => b"MJPG",
+            Self::Rgb8 => b"RGB3",
+        }
+    }
+
+    /// The `sensor_msgs/Image` encoding of unconverted frames, or
+    /// `None` if ROS has no encoding for the format.
+    pub fn native_encoding(&self) -> Option<&'static str> {
+        Some(match self {
+            Self::Uyvy => "yuv422",
+            Self::Yuyv => "yuv422_yuy2",
+            Self::Grey => "mono8",
+            Self::Rgb8 => "rgb8",
+            Self::Nv12 | Self::Mjpeg => return None,
+        })
+    }
+
+    /// The byte length of a frame, or `None` for compressed formats.
+    pub fn frame_len(&self, width: u32, height: u32) -> Option<usize> {
+        let pixels = width as usize * height as usize;
+        Some(match self {
+            Self::Uyvy | Self::Yuyv => pixels * 2,
+            Self::Nv12 => pixels * 3 / 2,
+            Self::Grey => pixels,
+            Self::Rgb8 => pixels * 3,
+            Self::Mjpeg => return None,
+        })
+    }
+}
+
+/// The `sensor_msgs/Image` encodings that frames can be converted to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    Bgr8,
+    Rgb8,
+    Mono8,
+}
+
+impl Encoding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Bgr8 => "bgr8",
+            Self::Rgb8 => "rgb8",
+            Self::Mono8 => "mono8",
+        }
+    }
+
+    pub fn channels(&self) -> u32 {
+        match self {
+            Self::Bgr8 | Self::Rgb8 => 3,
+            Self::Mono8 => 1,
+        }
+    }
 }
@@ -0,0 +1,219 @@
+//! Applies V4L2 controls, such as exposure and gain, from the config
+//! and from ROS parameters.
+
+use anyhow::{bail, ensure, Context, Result};
+use futures::{executor::block_on, prelude::*};
+use r2r::{Node, ParameterValue};
+use rscam::{Camera, Control, CtrlData};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    io,
+    sync::{
+        mpsc::{self, Receiver},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+/// The prefix of ROS parameters that set controls.
+pub const PARAM_PREFIX: &str = "controls.";
+
+/// The value of a V4L2 control. Menu controls take the menu index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ControlValue {
+    Bool(bool),
+    Int(i64),
+}
+
+impl ControlValue {
+    pub fn from_parameter(value: &ParameterValue) -> Option<Self> {
+        match *value {
+            ParameterValue::Bool(value) => Some(Self::Bool(value)),
+            ParameterValue::Integer(value) => Some(Self::Int(value)),
+            _ => None,
+        }
+    }
+
+    pub fn to_parameter(self) -> ParameterValue {
+        match self {
+            Self::Bool(value) => ParameterValue::Bool(value),
+            Self::Int(value) => ParameterValue::Integer(value),
+        }
+    }
+}
+
+/// The controls reported by a device, keyed by their names.
+pub struct DeviceControls {
+    controls: HashMap<String, Control>,
+}
+
+impl DeviceControls {
+    pub fn query(camera: &Camera) -> Result<Self> {
+        let controls = camera
+            .controls()
+            .map(|control| {
+                let control = control?;
+                Ok((control_key(&control.name), control))
+            })
+            .collect::<io::Result<_>>()
+            .context("unable to list camera controls")?;
+        Ok(Self { controls })
+    }
+
+    /// Checks the value against the range reported by the device and
+    /// sets the control.
+    pub fn set(&self, camera: &Camera, name: &str, value: ControlValue) -> Result<()> {
+        let control = self
+            .controls
+            .get(name)
+            .with_context(|| format!("the camera has no control {}", name))?;
+        let id = control.id;
+
+        let result = match (&control.data, value) {
+            (
+                &CtrlData::Integer {
+                    minimum,
+                    maximum,
+                    step,
+                    ..
+                },
+                ControlValue::Int(value),
+            ) => {
+                check_range(value, minimum.into(), maximum.into(), step.into())?;
+                camera.set_control(id, &(value as i32))
+            }
+            (
+                &CtrlData::Integer64 {
+                    minimum,
+                    maximum,
+                    step,
+                    ..
+                },
+                ControlValue::Int(value),
+            ) => {
+                check_range(value, minimum, maximum, step)?;
+                camera.set_control(id, &value)
+            }
+            (CtrlData::Boolean { .. }, ControlValue::Bool(value)) => camera.set_control(id, &value),
+            (CtrlData::Boolean { .. }, ControlValue::Int(value @ (0 | 1))) => {
+                camera.set_control(id, &(value == 1))
+            }
+            (CtrlData::Menu { items, .. }, ControlValue::Int(value)) => {
+                ensure!(
+                    items.iter().any(|item| i64::from(item.index) == value),
+                    "{} is not a menu index",
+                    value
+                );
+                camera.set_control(id, &(value as i32))
+            }
+            (CtrlData::IntegerMenu { items, .. }, ControlValue::Int(value)) => {
+                ensure!(
+                    items.iter().any(|item| i64::from(item.index) == value),
+                    "{} is not a menu index",
+                    value
+                );
+                camera.set_control(id, &(value as i32))
+            }
+            (_, value) => bail!("control {} cannot be set to {:?}", name, value),
+        };
+        result.with_context(|| format!("unable to set control {}", name))?;
+
+        Ok(())
+    }
+}
+
+/// Receives control changes from ROS parameters named
+/// `controls.<name>`.
+pub struct ControlParams {
+    rx: Receiver<(String, ParameterValue)>,
+    params: Arc<Mutex<HashMap<String, ParameterValue>>>,
+}
+
+impl ControlParams {
+    /// Declares a parameter for each control and watches parameter
+    /// changes. Values given on the command line take precedence over
+    /// the config. It returns the initial control values.
+    pub fn declare(
+        node: &mut Node,
+        controls: &BTreeMap<String, ControlValue>,
+    ) -> Result<(Self, BTreeMap<String, ControlValue>)> {
+        let params = node.params.clone();
+        let initial: BTreeMap<_, _> = {
+            let mut params = params.lock().unwrap();
+            controls
+                .iter()
+                .map(|(name, &value)| {
+                    let param = params
+                        .entry(format!("{}{}", PARAM_PREFIX, name))
+                        .or_insert_with(|| value.to_parameter());
+                    let value = ControlValue::from_parameter(param).unwrap_or(value);
+                    (name.clone(), value)
+                })
+                .collect()
+        };
+
+        let (handler, events) = node.make_parameter_handler()?;
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || block_on(handler));
+        thread::spawn(move || {
+            block_on(events.for_each(|(name, value)| {
+                if let Some(name) = name.strip_prefix(PARAM_PREFIX) {
+                    let _ = tx.send((name.to_string(), value));
+                }
+                future::ready(())
+            }))
+        });
+
+        Ok((Self { rx, params }, initial))
+    }
+
+    /// Takes a pending change.
+    pub fn try_recv(&self) -> Option<(String, ParameterValue)> {
+        self.rx.try_recv().ok()
+    }
+
+    /// Restores a parameter after a change is rejected.
+    pub fn restore(&self, name: &str, value: Option<ControlValue>) {
+        let key = format!("{}{}", PARAM_PREFIX, name);
+        let mut params = self.params.lock().unwrap();
+        match value {
+            Some(value) => {
+                params.insert(key, value.to_parameter());
+            }
+            None => {
+                params.remove(&key);
+            }
+        }
+    }
+}
+
+fn check_range(value: i64, minimum: i64, maximum: i64, step: i64) -> Result<()> {
+    ensure!(
+        (minimum..=maximum).contains(&value),
+        "{} is out of range [{}, {}]",
+        value,
+        minimum,
+        maximum
+    );
+    ensure!(
+        step <= 1 || (value - minimum) % step == 0,
+        "{} is not {} plus a multiple of {}",
+        value,
+        minimum,
+        step
+    );
+    Ok(())
+}
+
+/// Converts a control name to the key used by `v4l2-ctl`, e.g.
+/// "White Balance Temperature, Auto" to
+/// "white_balance_temperature_auto".
+fn control_key(name: &str) -> String {
+    name.split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_ascii_lowercase())
+        .collect::<Vec<_>>()
+        .join("_")
+}
@@ -0,0 +1,108 @@
+//! Converts captured V4L2 frames to `sensor_msgs/Image` encodings.
+
+use crate::config::{Encoding, PixelFormat};
+use anyhow::{ensure, Result};
+use opencv::{
+    core::Mat,
+    imgcodecs::{self, IMREAD_COLOR, IMREAD_GRAYSCALE},
+    imgproc::{self, *},
+    prelude::*,
+};
+
+/// The image data in a `sensor_msgs/Image` encoding.
+#[derive(Debug, Clone)]
+pub struct Converted {
+    pub encoding: &'static str,
+    pub step: u32,
+    pub data: Vec<u8>,
+}
+
+/// Converts a frame to the encoding. If the encoding is `None`, the
+/// frame is kept in the native format.
+pub fn convert(
+    frame: &[u8],
+    format: PixelFormat,
+    encoding: Option<Encoding>,
+    (width, height): (u32, u32),
+) -> Result<Converted> {
+    let encoding = match encoding {
+        Some(encoding) => encoding,
+        None => {
+            let encoding = format.native_encoding().unwrap();
+            return Ok(Converted {
+                encoding,
+                step: frame.len() as u32 / height,
+                data: frame.to_vec(),
+            });
+        }
+    };
+    let step = encoding.channels() * width;
+
+    let mat = if format == PixelFormat::Mjpeg {
+        let flags = match encoding {
+            Encoding::Mono8 => IMREAD_GRAYSCALE,
+            Encoding::Bgr8 | Encoding::Rgb8 => IMREAD_COLOR,
+        };
+        let mat = imgcodecs::imdecode(&Mat::from_slice(frame)?, flags)?;
+        ensure!(
+            mat.cols() == width as i32 && mat.rows() == height as i32,
+            "the decoded JPEG image has size {}x{}",
+            mat.cols(),
+            mat.rows()
+        );
+
+        if encoding == Encoding::Rgb8 {
+            convert_color(&mat, COLOR_BGR2RGB)?
+        } else {
+            mat
+        }
+    } else {
+        let code = match (format, encoding) {
+            (PixelFormat::Uyvy, Encoding::Bgr8) => COLOR_YUV2BGR_UYVY,
+            (PixelFormat::Uyvy, Encoding::Rgb8) => COLOR_YUV2RGB_UYVY,
+            (PixelFormat::Uyvy, Encoding::Mono8) => COLOR_YUV2GRAY_UYVY,
+            (PixelFormat::Yuyv, Encoding::Bgr8) => COLOR_YUV2BGR_YUYV,
+            (PixelFormat::Yuyv, Encoding::Rgb8) => COLOR_YUV2RGB_YUYV,
+            (PixelFormat::Yuyv, Encoding::Mono8) => COLOR_YUV2GRAY_YUYV,
+            (PixelFormat::Nv12, Encoding::Bgr8) => COLOR_YUV2BGR_NV12,
+            (PixelFormat::Nv12, Encoding::Rgb8) => COLOR_YUV2RGB_NV12,
+            (PixelFormat::Nv12, Encoding::Mono8) => COLOR_YUV2GRAY_NV12,
+            (PixelFormat::Grey, Encoding::Bgr8) => COLOR_GRAY2BGR,
+            (PixelFormat::Grey, Encoding::Rgb8) => COLOR_GRAY2RGB,
+            (PixelFormat::Rgb8, Encoding::Bgr8) => COLOR_RGB2BGR,
+            (PixelFormat::Rgb8, Encoding::Mono8) => COLOR_RGB2GRAY,
+            (PixelFormat::Grey, Encoding::Mono8) | (PixelFormat::Rgb8, Encoding::Rgb8) => {
+                return Ok(Converted {
+                    encoding: encoding.as_str(),
+                    step,
+                    data: frame.to_vec(),
+                });
+            }
+            (PixelFormat::Mjpeg, _) => unreachable!(),
+        };
+
+        // NV12 stores a full-size Y plane followed by a half-size
+        // interleaved UV plane.
+        let (channels, rows) = match format {
+            PixelFormat::Uyvy | PixelFormat::Yuyv => (2, height),
+            PixelFormat::Nv12 => (1, height * 3 / 2),
+            PixelFormat::Grey => (1, height),
+            PixelFormat::Rgb8 => (3, height),
+            PixelFormat::Mjpeg => unreachable!(),
+        };
+        let src = Mat::from_slice(frame)?.reshape(channels, rows as i32)?;
+        convert_color(&src, code)?
+    };
+
+    Ok(Converted {
+        encoding: encoding.as_str(),
+        step,
+        data: mat.data_bytes()?.to_vec(),
+    })
+}
+
+fn convert_color(src: &Mat, code: i32) -> Result<Mat> {
+    let mut dst = Mat::default();
+    imgproc::cvt_color(src, &mut dst, code, 0)?;
+    Ok(dst)
+}
@@ -0,0 +1,171 @@
+//! Publishes JPEG and downscaled images derived from captured images.
+
+use crate::config::{CompressedConfig, Config, PreviewConfig};
+use anyhow::{bail, ensure, Result};
+use opencv::{
+    core::{Mat, Size, Vector},
+    imgcodecs::{self, IMWRITE_JPEG_QUALITY},
+    imgproc::{self, *},
+    prelude::*,
+};
+use r2r::{
+    sensor_msgs::msg::{CompressedImage, Image},
+    Node, Publisher, QosProfile,
+};
+use std::time::{Duration, Instant};
+
+/// The publishers of `<topic>/compressed` and `<topic>/preview`.
+pub struct DerivedPublishers {
+    compressed: Option<(Publisher<CompressedImage>, CompressedConfig, RateLimiter)>,
+    preview: Option<(Publisher<Image>, PreviewConfig, RateLimiter)>,
+}
+
+impl DerivedPublishers {
+    pub fn new(node: &mut Node, config: &Config) -> Result<Self> {
+        let compressed = match &config.compressed {
+            Some(compressed) => {
+                ensure!(
+                    compressed.quality <= 100,
+                    "JPEG quality must be within 0..=100"
+                );
+                if let Some(rate) = compressed.max_rate {
+                    ensure!(
+                        rate.is_finite() && rate > 0.0,
+                        "compressed.max_rate must be a positive number"
+                    );
+                }
+                let topic = format!("{}/compressed", config.topic);
+                let publisher = node.create_publisher(&topic, QosProfile::default())?;
+                let limiter = RateLimiter::new(compressed.max_rate);
+                Some((publisher, compressed.clone(), limiter))
+            }
+            None => None,
+        };
+        let preview = match &config.preview {
+            Some(preview) => {
+                ensure!(preview.downscale >= 1, "downscale must be at least 1");
+                if let Some(rate) = preview.max_rate {
+                    ensure!(
+                        rate.is_finite() && rate > 0.0,
+                        "preview.max_rate must be a positive number"
+                    );
+                }
+                let topic = format!("{}/preview", config.topic);
+                let publisher = node.create_publisher(&topic, QosProfile::default())?;
+                let limiter = RateLimiter::new(preview.max_rate);
+                Some((publisher, preview.clone(), limiter))
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            compressed,
+            preview,
+        })
+    }
+
+    /// Publishes the images derived from an image if they are due.
+    pub fn publish(&mut self, image: &Image) -> Result<()> {
+        let now = Instant::now();
+        let compressed_due = self
+            .compressed
+            .as_mut()
+            .map_or(false, |(_, _, limiter)| limiter.ready(now));
+        let preview_due = self
+            .preview
+            .as_mut()
+            .map_or(false, |(_, _, limiter)| limiter.ready(now));
+        if !compressed_due && !preview_due {
+            return Ok(());
+        }
+
+        let (mat, encoding) = image_to_mat(image)?;
+
+        if let (true, Some((publisher, config, _))) = (compressed_due, &self.compressed) {
+            let mut buf = Vector::new();
+            let params = Vector::from_slice(&[IMWRITE_JPEG_QUALITY, config.quality as i32]);
+            imgcodecs::imencode(".jpg", &mat, &mut buf, &params)?;
+
+            publisher.publish(&CompressedImage {
+                header: image.header.clone(),
+                format: format!("{}; jpeg compressed {}", encoding, encoding),
+                data: buf.to_vec(),
+            })?;
+        }
+
+        if let (true, Some((publisher, config, _))) = (preview_due, &self.preview) {
+            let size = Size::new(
+                (image.width / config.downscale) as i32,
+                (image.height / config.downscale) as i32,
+            );
+            let mut small = Mat::default();
+            imgproc::resize(&mat, &mut small, size, 0.0, 0.0, INTER_AREA)?;
+
+            publisher.publish(&Image {
+                header: image.header.clone(),
+                height: size.height as u32,
+                width: size.width as u32,
+                encoding: encoding.to_string(),
+                is_bigendian: image.is_bigendian,
+                step: (size.width * small.channels()) as u32,
+                data: small.data_bytes()?.to_vec(),
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Throttles publishing to a maximum rate.
+struct RateLimiter {
+    period: Option<Duration>,
+    last: Option<Instant>,
+}
+
+impl RateLimiter {
+    fn new(max_rate: Option<f64>) -> Self {
+        Self {
+            period: max_rate.map(|rate| Duration::from_secs_f64(1.0 / rate)),
+            last: None,
+        }
+    }
+
+    /// Returns true and restarts the period if the period has passed.
+    fn ready(&mut self, now: Instant) -> bool {
+        let ready = match (self.period, self.last) {
+            (Some(period), Some(last)) => now.duration_since(last) >= period,
+            _ => true,
+        };
+        if ready {
+            self.last = Some(now);
+        }
+        ready
+    }
+}
+
+/// Converts an image to a BGR or grayscale Mat, and returns it with
+/// its encoding.
+fn image_to_mat(image: &Image) -> Result<(Mat, &'static str)> {
+    let rows = image.height as i32;
+    let src = Mat::from_slice(&image.data)?;
+
+    let (code, channels, encoding) = match image.encoding.as_str() {
+        "bgr8" => (None, 3, "bgr8"),
+        "mono8" => (None, 1, "mono8"),
+        "rgb8" => (Some(COLOR_RGB2BGR), 3, "bgr8"),
+        "yuv422" => (Some(COLOR_YUV2BGR_UYVY), 2, "bgr8"),
+        "yuv422_yuy2" => (Some(COLOR_YUV2BGR_YUYV), 2, "bgr8"),
+        encoding => bail!("unsupported encoding {}", encoding),
+    };
+    let src = src.reshape(channels, rows)?;
+
+    let mat = match code {
+        Some(code) => {
+            let mut dst = Mat::default();
+            imgproc::cvt_color(&src, &mut dst, code, 0)?;
+            dst
+        }
+        None => src.try_clone()?,
+    };
+    Ok((mat, encoding))
+}
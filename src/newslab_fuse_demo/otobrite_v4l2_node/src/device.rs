@@ -0,0 +1,38 @@
+//! Locates the V4L2 device to capture from.
+
+use anyhow::{bail, Context, Result};
+use std::{fs, path::PathBuf};
+
+/// The directory of udev links named after device serial numbers.
+const BY_ID_DIR: &str = "/dev/v4l/by-id";
+
+/// Finds the device node. Symlinks are resolved so that a re-plugged
+/// device is found under its new node.
+pub fn find_device(video_device: &str, serial: Option<&str>) -> Result<PathBuf> {
+    let link = match serial {
+        Some(serial) => find_by_serial(serial)?,
+        None => PathBuf::from(video_device),
+    };
+    fs::canonicalize(&link)
+        .with_context(|| format!("unable to find video device {}", link.display()))
+}
+
+/// Finds the capture node of the device with the serial number. udev
+/// names the links as `<bus>-<vendor>_<model>_<serial>-video-index<N>`,
+/// where index 0 is the capture node.
+fn find_by_serial(serial: &str) -> Result<PathBuf> {
+    let entries =
+        fs::read_dir(BY_ID_DIR).with_context(|| format!("unable to list {}", BY_ID_DIR))?;
+
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+
+        if name.contains(&format!("_{}-", serial)) && name.ends_with("-video-index0") {
+            return Ok(entry.path());
+        }
+    }
+
+    bail!("no video device with serial number {} is found", serial)
+}
@@ -1,22 +1,42 @@
+mod calibration;
+mod clock;
 mod config;
+mod controls;
+mod convert;
+mod derived;
+mod device;
+mod virtual_camera;
 
-use anyhow::{bail, ensure, Result};
+use anyhow::{bail, ensure, Context as _, Result};
+use calibration::Calibration;
 use clap::Parser;
-use config::Config;
+use clock::{BufferClock, FrameSequence};
+use config::{ClockSource, Config, PixelFormat, Source};
+use controls::{ControlParams, ControlValue, DeviceControls};
+use convert::convert;
+use derived::DerivedPublishers;
+use device::find_device;
 use r2r::{
-    builtin_interfaces::msg::Time, sensor_msgs::msg::Image, std_msgs::msg::Header, Context, Node,
-    QosProfile,
+    builtin_interfaces::msg::Time,
+    log_info, log_warn,
+    sensor_msgs::msg::{CameraInfo, Image},
+    std_msgs::msg::Header,
+    Context, Node, QosProfile,
 };
 use rscam::Camera;
+use sensor_diagnostics::{spawn_diagnostics, SensorMonitor};
 use std::{
+    collections::BTreeMap,
     fs,
+    path::Path,
     sync::{
         mpsc::{sync_channel, SyncSender},
         Arc,
     },
-    thread::spawn,
+    thread::{self, spawn},
     time::{Duration, SystemTime},
 };
+use virtual_camera::VirtualCamera;
 
 #[derive(Debug, Clone, Parser)]
 struct Opts {
@@ -26,28 +46,129 @@ struct Opts {
 
 fn main() -> Result<()> {
     let opts = Opts::parse();
-    let config: Config = {
+    let mut config: Config = {
         let text = fs::read_to_string(&opts.config)?;
         json5::from_str(&text)?
     };
+
+    // Resolve relative paths against the directory of the config file.
+    {
+        let config_dir = Path::new(&opts.config).parent().unwrap_or(Path::new(""));
+        if let Some(path) = &mut config.camera_info_file {
+            *path = config_dir.join(&*path);
+        }
+        if let Source::ImageDir { dir: path } | Source::RawFile { path } = &mut config.source {
+            *path = config_dir.join(&*path);
+        }
+    }
     let config = Arc::new(config);
+    let period = config.period()?;
+    config.reconnect.check()?;
+
+    let format = config.source.pixel_format(config.format);
+    if config.encoding.is_none() && format.native_encoding().is_none() {
+        bail!(
+            "{:?} frames have no ROS encoding; set \"encoding\" to convert them",
+            format
+        );
+    }
+
+    // Load the calibration file.
+    let camera_info = config
+        .camera_info_file
+        .as_ref()
+        .map(|path| -> Result<_> {
+            let calib = Calibration::load(path)?;
+            ensure!(
+                (calib.image_width, calib.image_height) == config.resolution,
+                "the calibration is made for {}x{} images, but the resolution is {}x{}",
+                calib.image_width,
+                calib.image_height,
+                config.resolution.0,
+                config.resolution.1
+            );
+            Ok(calib.to_camera_info())
+        })
+        .transpose()?;
 
     // Start a ROS node
     let ctx = Context::create()?;
     let mut node = Node::create(ctx, env!("CARGO_PKG_NAME"), &config.namespace)?;
-    // Create a ROS publisher.
+    // Create ROS publishers.
     let publisher = node.create_publisher::<Image>(&config.topic, QosProfile::default())?;
+    let camera_info_publisher = camera_info
+        .is_some()
+        .then(|| {
+            let topic = format!("{}/camera_info", config.topic);
+            node.create_publisher::<CameraInfo>(&topic, QosProfile::default())
+        })
+        .transpose()?;
+    let mut derived_publishers = DerivedPublishers::new(&mut node, &config)?;
+
+    // Publish diagnostics.
+    let monitor = Arc::new(SensorMonitor::new(
+        concat!(env!("CARGO_PKG_NAME"), ": camera"),
+        &hardware_id(&config),
+        &config.diagnostics,
+    ));
+    spawn_diagnostics(&mut node, &config.diagnostics, vec![monitor.clone()])?;
+
+    // Expose camera controls as ROS parameters.
+    let (control_params, controls) = ControlParams::declare(&mut node, &config.controls)?;
 
     // Create a channel
     let (tx, rx) = sync_channel(8);
 
     let capture_handle = spawn({
         let config = config.clone();
-        move || run_camera_capture(&config, tx)
+        move || {
+            let result = match config.source {
+                Source::V4l2 => {
+                    run_camera_capture(&config, period, &monitor, &control_params, controls, tx)
+                }
+                _ => run_virtual_capture(&config, period, &monitor, tx),
+            };
+            monitor.set_connected(false);
+            result
+        }
     });
     let publish_handle = spawn(move || {
+        // Publish failures are logged, and the thread keeps receiving
+        // so that the capture thread is not blocked.
         while let Ok(image) = rx.recv() {
-            publisher.publish(&image)?;
+            if let Err(err) = publisher.publish(&image) {
+                log_warn!(
+                    env!("CARGO_PKG_NAME"),
+                    "Unable to publish an image: {:#}",
+                    err
+                );
+            }
+
+            // Publish the calibration with the same header.
+            if let (Some(camera_info), Some(camera_info_publisher)) =
+                (&camera_info, &camera_info_publisher)
+            {
+                let camera_info = CameraInfo {
+                    header: image.header.clone(),
+                    ..camera_info.clone()
+                };
+                if let Err(err) = camera_info_publisher.publish(&camera_info) {
+                    log_warn!(
+                        env!("CARGO_PKG_NAME"),
+                        "Unable to publish the camera info: {:#}",
+                        err
+                    );
+                }
+            }
+
+            // Publish JPEG and preview images.
+            if let Err(err) = derived_publishers.publish(&image) {
+                log_warn!(
+                    env!("CARGO_PKG_NAME"),
+                    "Unable to publish derived images: {:#}",
+                    err
+                );
+            }
         }
         anyhow::Ok(())
     });
@@ -62,78 +183,280 @@ fn main() -> Result<()> {
     spin_handle.join().unwrap();
 }
 
-fn run_camera_capture(config: &Config, tx: SyncSender<Image>) -> Result<()> {
+/// Captures frames until the receiver is dropped. The device is
+/// reopened with exponential backoff whenever it fails.
+fn run_camera_capture(
+    config: &Config,
+    period: Duration,
+    monitor: &SensorMonitor,
+    control_params: &ControlParams,
+    controls: BTreeMap<String, ControlValue>,
+    tx: SyncSender<Image>,
+) -> Result<()> {
+    let mut state = CaptureState {
+        controls,
+        ..Default::default()
+    };
+
+    loop {
+        let result = capture_device(config, period, monitor, control_params, &tx, &mut state);
+        monitor.set_connected(false);
+
+        let err = match result {
+            Ok(()) => return Ok(()),
+            Err(err) => err,
+        };
+        let attempts = state.failed_attempts;
+        if let Some(max_attempts) = config.reconnect.max_attempts {
+            if attempts >= max_attempts {
+                return Err(err.context(format!("give up after {} attempts", attempts)));
+            }
+        }
+
+        let backoff = config.reconnect.backoff(attempts);
+        log_warn!(
+            env!("CARGO_PKG_NAME"),
+            "Camera failed: {:#}. Reopen it in {:.1}s",
+            err,
+            backoff.as_secs_f64()
+        );
+        state.failed_attempts += 1;
+        state.recovering = true;
+        thread::sleep(backoff);
+    }
+}
+
+/// The capture state kept across reconnects.
+#[derive(Debug, Default)]
+struct CaptureState {
+    clock: BufferClock,
+    sequence: FrameSequence,
+    /// The number of consecutive failures without a captured frame.
+    failed_attempts: u32,
+    /// Set if the device is being reopened after a failure.
+    recovering: bool,
+    /// The control values applied whenever the device is opened.
+    controls: BTreeMap<String, ControlValue>,
+}
+
+/// Opens the device and captures frames from it. It returns `Ok` if
+/// the receiver is dropped, or an error if the device fails.
+fn capture_device(
+    config: &Config,
+    period: Duration,
+    monitor: &SensorMonitor,
+    control_params: &ControlParams,
+    tx: &SyncSender<Image>,
+    state: &mut CaptureState,
+) -> Result<()> {
     let Config {
         resolution,
         interval,
-        ref format,
+        format,
         ..
     } = *config;
-    let (width, height) = resolution;
-
-    let pixel_step = match format.as_str() {
-        "UYVY" => 2,
-        "RGB8" => 3,
-        _ => bail!("unsupported format {}", format),
-    };
 
     // Start the camera
-    let mut camera = Camera::new(&config.video_device)?;
+    let device = find_device(&config.video_device, config.device_serial.as_deref())?;
+    let mut camera = Camera::new(&device.to_string_lossy())
+        .with_context(|| format!("unable to open {}", device.display()))?;
     camera.start(&rscam::Config {
         interval,
         resolution,
-        format: format.as_bytes(),
+        format: format.fourcc(),
         ..Default::default()
     })?;
+    monitor.set_connected(true);
+    state.sequence.reset();
+
+    // Apply controls.
+    let device_controls = DeviceControls::query(&camera)?;
+    for (name, &value) in &state.controls {
+        if let Err(err) = device_controls.set(&camera, name, value) {
+            log_warn!(env!("CARGO_PKG_NAME"), "{:#}", err);
+        }
+    }
 
-    let mut frame_id_iter = 0..;
+    if state.recovering {
+        state.recovering = false;
+        monitor.count_reconnect();
+        log_info!(
+            env!("CARGO_PKG_NAME"),
+            "Camera is reconnected at {}",
+            device.display()
+        );
+    }
 
     loop {
+        apply_control_params(&camera, &device_controls, control_params, state);
+
         let frame = camera.capture()?;
+        let receive_time = SystemTime::now();
+        state.failed_attempts = 0;
 
-        let sys_time = SystemTime::now();
-        let unix_time = sys_time.duration_since(SystemTime::UNIX_EPOCH).unwrap();
+        // Count the frames dropped by the driver.
+        let (sequence, dropped) = state.sequence.update(frame.timestamp, period);
+        if dropped > 0 {
+            log_warn!(
+                env!("CARGO_PKG_NAME"),
+                "Camera dropped {} frames before frame {}",
+                dropped,
+                sequence
+            );
+            (0..dropped).for_each(|_| monitor.count_dropped());
+        }
 
-        let row_step = pixel_step * width;
-        let num_bytes = row_step * height;
-        let bytes: &[u8] = &*frame;
-        let is_bigendian = if cfg!(target_endian = "big") { 1 } else { 0 };
+        let problem = if frame.resolution != config.resolution {
+            Some("resolution mismatches")
+        } else if frame.format != format.fourcc() {
+            Some("format mismatches")
+        } else {
+            None
+        };
+        if let Some(problem) = problem {
+            log_warn!(env!("CARGO_PKG_NAME"), "Drop a frame: {}", problem);
+            monitor.count_invalid();
+            continue;
+        }
 
-        ensure!(
-            frame.resolution == config.resolution,
-            "resolution mismatches"
-        );
-        ensure!(
-            frame.format == config.format.as_bytes(),
-            "format mismatches"
-        );
-        ensure!(
-            bytes.len() == num_bytes as usize,
-            "byte array length mismatches"
-        );
+        let stamp = match config.clock {
+            ClockSource::Buffer if frame.timestamp != 0 => state.clock.wall_time(frame.timestamp),
+            _ => receive_time,
+        };
+        let image = match frame_to_image(config, format, monitor, &frame, stamp) {
+            Some(image) => image,
+            None => continue,
+        };
+        let ok = tx.send(image).is_ok();
+        if !ok {
+            return Ok(());
+        }
+    }
+}
 
-        let frame_id = frame_id_iter.next().unwrap();
-        let image = Image {
-            header: Header {
-                stamp: Time {
-                    sec: unix_time.as_secs() as i32,
-                    nanosec: unix_time.subsec_nanos(),
-                },
-                frame_id: frame_id.to_string(),
-            },
-            height,
-            width,
-            encoding: format.clone(),
-            is_bigendian,
-            step: row_step,
-            data: bytes.to_vec(),
+/// Publishes frames from a virtual camera until the receiver is
+/// dropped.
+fn run_virtual_capture(
+    config: &Config,
+    period: Duration,
+    monitor: &SensorMonitor,
+    tx: SyncSender<Image>,
+) -> Result<()> {
+    let mut camera = VirtualCamera::open(config, period)?;
+    let format = config.source.pixel_format(config.format);
+    monitor.set_connected(true);
+
+    loop {
+        let frame = camera.capture()?;
+        let stamp = SystemTime::now();
+        let image = match frame_to_image(config, format, monitor, &frame, stamp) {
+            Some(image) => image,
+            None => continue,
         };
 
         let ok = tx.send(image).is_ok();
         if !ok {
-            break;
+            return Ok(());
         }
     }
+}
 
-    Ok(())
+/// Converts a captured frame to an image message. Invalid frames are
+/// logged, counted and dropped.
+fn frame_to_image(
+    config: &Config,
+    format: PixelFormat,
+    monitor: &SensorMonitor,
+    bytes: &[u8],
+    stamp: SystemTime,
+) -> Option<Image> {
+    let (width, height) = config.resolution;
+
+    let unix_time = stamp.duration_since(SystemTime::UNIX_EPOCH).unwrap();
+    let is_bigendian = if cfg!(target_endian = "big") { 1 } else { 0 };
+
+    let len_ok = format
+        .frame_len(width, height)
+        .map_or(!bytes.is_empty(), |len| bytes.len() == len);
+    if !len_ok {
+        log_warn!(
+            env!("CARGO_PKG_NAME"),
+            "Drop a frame: byte array length mismatches"
+        );
+        monitor.count_invalid();
+        return None;
+    }
+
+    let converted = match convert(bytes, format, config.encoding, config.resolution) {
+        Ok(converted) => converted,
+        Err(err) => {
+            log_warn!(env!("CARGO_PKG_NAME"), "Drop a frame: {:#}", err);
+            monitor.count_invalid();
+            return None;
+        }
+    };
+    monitor.bump();
+
+    Some(Image {
+        header: Header {
+            stamp: Time {
+                sec: unix_time.as_secs() as i32,
+                nanosec: unix_time.subsec_nanos(),
+            },
+            frame_id: config.frame_id.clone(),
+        },
+        height,
+        width,
+        encoding: converted.encoding.to_string(),
+        is_bigendian,
+        step: converted.step,
+        data: converted.data,
+    })
+}
+
+/// Applies control changes from ROS parameters. A rejected change is
+/// reverted to the last applied value.
+fn apply_control_params(
+    camera: &Camera,
+    device_controls: &DeviceControls,
+    control_params: &ControlParams,
+    state: &mut CaptureState,
+) {
+    while let Some((name, param)) = control_params.try_recv() {
+        let result = ControlValue::from_parameter(&param)
+            .with_context(|| format!("{:?} is neither a bool nor an integer", param))
+            .and_then(|value| {
+                device_controls.set(camera, &name, value)?;
+                Ok(value)
+            });
+
+        match result {
+            Ok(value) => {
+                log_info!(
+                    env!("CARGO_PKG_NAME"),
+                    "Set control {} to {:?}",
+                    name,
+                    value
+                );
+                state.controls.insert(name, value);
+            }
+            Err(err) => {
+                log_warn!(env!("CARGO_PKG_NAME"), "Reject control {}: {:#}", name, err);
+                control_params.restore(&name, state.controls.get(&name).copied());
+            }
+        }
+    }
+}
+
+/// Describes the frame source in diagnostics.
+fn hardware_id(config: &Config) -> String {
+    match &config.source {
+        Source::V4l2 => config
+            .device_serial
+            .clone()
+            .unwrap_or_else(|| config.video_device.clone()),
+        Source::ImageDir { dir } => dir.display().to_string(),
+        Source::RawFile { path } => path.display().to_string(),
+        Source::Pattern => "pattern".to_string(),
+    }
 }
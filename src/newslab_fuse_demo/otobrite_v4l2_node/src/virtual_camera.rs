@@ -0,0 +1,221 @@
+//! Virtual cameras that produce frames without a V4L2 device.
+
+use crate::config::{Config, PixelFormat, Source};
+use anyhow::{bail, ensure, Context, Result};
+use std::{
+    fs::{self, File},
+    io::{self, prelude::*, SeekFrom},
+    path::PathBuf,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// The image file extensions read by the image directory source.
+const IMAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png"];
+
+/// The color bars of the test pattern in RGB.
+const COLOR_BARS: [[u8; 3]; 8] = [
+    [255, 255, 255],
+    [255, 255, 0],
+    [0, 255, 255],
+    [0, 255, 0],
+    [255, 0, 255],
+    [255, 0, 0],
+    [0, 0, 255],
+    [0, 0, 0],
+];
+
+/// Produces frames at the configured interval.
+pub struct VirtualCamera {
+    kind: Kind,
+    period: Duration,
+    deadline: Option<Instant>,
+}
+
+enum Kind {
+    ImageDir {
+        files: Vec<PathBuf>,
+        index: usize,
+    },
+    RawFile {
+        file: File,
+        frame_len: usize,
+    },
+    Pattern {
+        format: PixelFormat,
+        resolution: (u32, u32),
+        index: usize,
+    },
+}
+
+impl VirtualCamera {
+    pub fn open(config: &Config, period: Duration) -> Result<Self> {
+        let Config {
+            format,
+            resolution: (width, height),
+            ..
+        } = *config;
+
+        let kind = match &config.source {
+            Source::V4l2 => bail!("the V4L2 device is not a virtual camera"),
+            Source::ImageDir { dir } => {
+                let mut files: Vec<_> = fs::read_dir(dir)
+                    .with_context(|| format!("unable to list {}", dir.display()))?
+                    .map(|entry| Ok(entry?.path()))
+                    .collect::<io::Result<_>>()?;
+                files.retain(|path| {
+                    let ext = path.extension().and_then(|ext| ext.to_str());
+                    ext.map_or(false, |ext| {
+                        IMAGE_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str())
+                    })
+                });
+                files.sort();
+                ensure!(!files.is_empty(), "no image files in {}", dir.display());
+
+                Kind::ImageDir { files, index: 0 }
+            }
+            Source::RawFile { path } => {
+                let frame_len = format
+                    .frame_len(width, height)
+                    .with_context(|| format!("raw {:?} frames are not supported", format))?;
+                let file = File::open(path)
+                    .with_context(|| format!("unable to open {}", path.display()))?;
+                let file_len = file.metadata()?.len();
+                ensure!(
+                    file_len > 0 && file_len % frame_len as u64 == 0,
+                    "the size of {} is not a multiple of the frame size {}",
+                    path.display(),
+                    frame_len
+                );
+
+                Kind::RawFile { file, frame_len }
+            }
+            Source::Pattern => {
+                ensure!(
+                    format.frame_len(width, height).is_some(),
+                    "the test pattern cannot be generated in {:?}",
+                    format
+                );
+                ensure!(
+                    width % 2 == 0 && height % 2 == 0,
+                    "the test pattern needs an even resolution"
+                );
+
+                Kind::Pattern {
+                    format,
+                    resolution: (width, height),
+                    index: 0,
+                }
+            }
+        };
+
+        Ok(Self {
+            kind,
+            period,
+            deadline: None,
+        })
+    }
+
+    /// Waits for the next frame interval and returns the frame.
+    pub fn capture(&mut self) -> Result<Vec<u8>> {
+        // Keep the pace. Skip the missed intervals if it falls behind.
+        let now = Instant::now();
+        let deadline = match self.deadline {
+            Some(deadline) if deadline > now => {
+                thread::sleep(deadline - now);
+                deadline
+            }
+            _ => now,
+        };
+        self.deadline = Some(deadline + self.period);
+
+        let frame = match &mut self.kind {
+            Kind::ImageDir { files, index } => {
+                let path = &files[*index];
+                *index = (*index + 1) % files.len();
+                fs::read(path).with_context(|| format!("unable to read {}", path.display()))?
+            }
+            Kind::RawFile { file, frame_len } => {
+                if file.stream_position()? + *frame_len as u64 > file.metadata()?.len() {
+                    file.seek(SeekFrom::Start(0))?;
+                }
+                let mut frame = vec![0; *frame_len];
+                file.read_exact(&mut frame)?;
+                frame
+            }
+            Kind::Pattern {
+                format,
+                resolution,
+                index,
+            } => {
+                let frame = color_bars(*format, *resolution, *index);
+                *index += 1;
+                frame
+            }
+        };
+
+        Ok(frame)
+    }
+}
+
+/// Draws color bars that move by 8 pixels per frame.
+fn color_bars(format: PixelFormat, (width, height): (u32, u32), index: usize) -> Vec<u8> {
+    let (width, height) = (width as usize, height as usize);
+    let shift = index * 8;
+    let rgb_at = |x: usize| COLOR_BARS[(x + shift) % width * COLOR_BARS.len() / width];
+    let yuv_at = |x: usize| rgb_to_yuv(rgb_at(x));
+
+    // All rows are the same.
+    let (row, rows): (Vec<u8>, usize) = match format {
+        PixelFormat::Grey => ((0..width).map(|x| yuv_at(x)[0]).collect(), height),
+        PixelFormat::Rgb8 => ((0..width).flat_map(rgb_at).collect(), height),
+        PixelFormat::Uyvy => (
+            (0..width)
+                .step_by(2)
+                .flat_map(|x| {
+                    let [y0, u, v] = yuv_at(x);
+                    let [y1, _, _] = yuv_at(x + 1);
+                    [u, y0, v, y1]
+                })
+                .collect(),
+            height,
+        ),
+        PixelFormat::Yuyv => (
+            (0..width)
+                .step_by(2)
+                .flat_map(|x| {
+                    let [y0, u, v] = yuv_at(x);
+                    let [y1, _, _] = yuv_at(x + 1);
+                    [y0, u, y1, v]
+                })
+                .collect(),
+            height,
+        ),
+        PixelFormat::Nv12 => {
+            let y_row: Vec<u8> = (0..width).map(|x| yuv_at(x)[0]).collect();
+            let uv_row: Vec<u8> = (0..width)
+                .step_by(2)
+                .flat_map(|x| {
+                    let [_, u, v] = yuv_at(x);
+                    [u, v]
+                })
+                .collect();
+
+            let mut frame = y_row.repeat(height);
+            frame.extend(uv_row.repeat(height / 2));
+            return frame;
+        }
+        PixelFormat::Mjpeg => unreachable!(),
+    };
+
+    row.repeat(rows)
+}
+
+/// Converts a color to BT.601 limited range YUV.
+fn rgb_to_yuv([r, g, b]: [u8; 3]) -> [u8; 3] {
+    let [r, g, b] = [r as f32, g as f32, b as f32];
+    let y = 16.0 + 0.257 * r + 0.504 * g + 0.098 * b;
+    let u = 128.0 - 0.148 * r - 0.291 * g + 0.439 * b;
+    let v = 128.0 + 0.439 * r - 0.368 * g - 0.071 * b;
+    [y.round() as u8, u.round() as u8, v.round() as u8]
+}
@@ -0,0 +1,155 @@
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize};
+use std::time::Duration;
+
+/// The thresholds that decide the level of sensor statuses.
+#[derive(Debug, Clone, Serialize)]
+pub struct DiagnosticsConfig {
+    /// The topic that diagnostics are published on.
+    pub topic: String,
+    /// The period in seconds to publish diagnostics.
+    pub period_secs: f64,
+    /// A warning is raised if the message rate in Hz drops below it.
+    pub warn_rate: f64,
+    /// An error is raised if the message rate in Hz drops below it.
+    pub error_rate: f64,
+    /// A warning is raised if no message arrives for this many
+    /// seconds.
+    pub warn_stale_secs: f64,
+    /// An error is raised if no message arrives for this many
+    /// seconds.
+    pub error_stale_secs: f64,
+}
+
+impl DiagnosticsConfig {
+    pub fn period(&self) -> Duration {
+        Duration::from_secs_f64(self.period_secs)
+    }
+}
+
+impl Default for DiagnosticsConfig {
+    fn default() -> Self {
+        Self {
+            topic: "/diagnostics".to_string(),
+            period_secs: 1.0,
+            warn_rate: 5.0,
+            error_rate: 1.0,
+            warn_stale_secs: 1.0,
+            error_stale_secs: 5.0,
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for DiagnosticsConfig {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let UncheckedDiagnosticsConfig {
+            topic,
+            period_secs,
+            warn_rate,
+            error_rate,
+            warn_stale_secs,
+            error_stale_secs,
+        } = UncheckedDiagnosticsConfig::deserialize(deserializer)?;
+        let defaults = Self::default();
+        let config = Self {
+            topic: topic.unwrap_or(defaults.topic),
+            period_secs: period_secs.unwrap_or(defaults.period_secs),
+            warn_rate: warn_rate.unwrap_or(defaults.warn_rate),
+            error_rate: error_rate.unwrap_or(defaults.error_rate),
+            warn_stale_secs: warn_stale_secs.unwrap_or(defaults.warn_stale_secs),
+            error_stale_secs: error_stale_secs.unwrap_or(defaults.error_stale_secs),
+        };
+
+        if !(config.period_secs.is_finite() && config.period_secs > 0.0) {
+            return Err(D::Error::custom(format!(
+                "period_secs must be a positive number, but get {}",
+                config.period_secs
+            )));
+        }
+
+        for (name, value) in [
+            ("warn_rate", config.warn_rate),
+            ("error_rate", config.error_rate),
+            ("warn_stale_secs", config.warn_stale_secs),
+            ("error_stale_secs", config.error_stale_secs),
+        ] {
+            if !(value.is_finite() && value >= 0.0) {
+                return Err(D::Error::custom(format!(
+                    "{} must be a non-negative number, but get {}",
+                    name, value
+                )));
+            }
+        }
+
+        if config.error_rate > config.warn_rate {
+            return Err(D::Error::custom(format!(
+                "error_rate {} must not exceed warn_rate {}",
+                config.error_rate, config.warn_rate
+            )));
+        }
+        if config.warn_stale_secs > config.error_stale_secs {
+            return Err(D::Error::custom(format!(
+                "warn_stale_secs {} must not exceed error_stale_secs {}",
+                config.warn_stale_secs, config.error_stale_secs
+            )));
+        }
+
+        Ok(config)
+    }
+}
+
+#[derive(Deserialize)]
+struct UncheckedDiagnosticsConfig {
+    #[serde(default)]
+    topic: Option<String>,
+    #[serde(default)]
+    period_secs: Option<f64>,
+    #[serde(default)]
+    warn_rate: Option<f64>,
+    #[serde(default)]
+    error_rate: Option<f64>,
+    #[serde(default)]
+    warn_stale_secs: Option<f64>,
+    #[serde(default)]
+    error_stale_secs: Option<f64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fill_missing_fields_with_defaults() {
+        let config: DiagnosticsConfig = json5::from_str("{ warn_rate: 8.0 }").unwrap();
+        assert_eq!(config.warn_rate, 8.0);
+        assert_eq!(config.error_rate, 1.0);
+        assert_eq!(config.period(), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn reject_invalid_period() {
+        for text in [
+            "{ period_secs: 0 }",
+            "{ period_secs: -1 }",
+            "{ period_secs: NaN }",
+        ] {
+            let result: Result<DiagnosticsConfig, _> = json5::from_str(text);
+            assert!(result.is_err(), "{} is accepted", text);
+        }
+    }
+
+    #[test]
+    fn reject_inverted_thresholds() {
+        for text in [
+            "{ warn_rate: 1.0, error_rate: 5.0 }",
+            "{ warn_stale_secs: 5.0, error_stale_secs: 1.0 }",
+            "{ error_rate: -1.0 }",
+            "{ warn_stale_secs: Infinity }",
+        ] {
+            let result: Result<DiagnosticsConfig, _> = json5::from_str(text);
+            assert!(result.is_err(), "{} is accepted", text);
+        }
+    }
+}
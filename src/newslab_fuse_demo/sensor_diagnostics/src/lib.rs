@@ -0,0 +1,14 @@
+//! Health reporting of sensor nodes in `diagnostic_msgs/DiagnosticArray`
+//! messages.
+
+mod config;
+pub use config::*;
+
+mod monitor;
+pub use monitor::*;
+
+mod publisher;
+pub use publisher::*;
+
+mod rate_meter;
+pub use rate_meter::*;
@@ -0,0 +1,284 @@
+use crate::{config::DiagnosticsConfig, rate_meter::RateMeter};
+use r2r::diagnostic_msgs::msg::{DiagnosticStatus, KeyValue};
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering::*},
+        Mutex,
+    },
+    time::Instant,
+};
+
+/// The level of a sensor status, in the same values as
+/// `diagnostic_msgs/DiagnosticStatus`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(u8)]
+pub enum Level {
+    Ok = 0,
+    Warn = 1,
+    Error = 2,
+}
+
+/// Collects the health of a sensor stream, which is shared among
+/// the threads that receive and process the stream.
+#[derive(Debug)]
+pub struct SensorMonitor {
+    name: String,
+    hardware_id: String,
+    config: DiagnosticsConfig,
+    created: Instant,
+    rate: RateMeter,
+    last_message: Mutex<Option<Instant>>,
+    connected: Mutex<Option<bool>>,
+    received: AtomicUsize,
+    dropped: AtomicUsize,
+    invalid: AtomicUsize,
+    decode_errors: AtomicUsize,
+    reconnects: AtomicUsize,
+    unmatched: AtomicUsize,
+    late: AtomicUsize,
+}
+
+impl SensorMonitor {
+    pub fn new(name: &str, hardware_id: &str, config: &DiagnosticsConfig) -> Self {
+        Self {
+            name: name.to_string(),
+            hardware_id: hardware_id.to_string(),
+            config: config.clone(),
+            created: Instant::now(),
+            rate: RateMeter::new(),
+            last_message: Mutex::new(None),
+            connected: Mutex::new(None),
+            received: AtomicUsize::new(0),
+            dropped: AtomicUsize::new(0),
+            invalid: AtomicUsize::new(0),
+            decode_errors: AtomicUsize::new(0),
+            reconnects: AtomicUsize::new(0),
+            unmatched: AtomicUsize::new(0),
+            late: AtomicUsize::new(0),
+        }
+    }
+
+    /// Records a received message.
+    pub fn bump(&self) {
+        self.rate.bump();
+        self.received.fetch_add(1, SeqCst);
+        *self.last_message.lock().unwrap() = Some(Instant::now());
+    }
+
+    /// Sets the connection state. The state is not reported until it
+    /// is set once.
+    pub fn set_connected(&self, connected: bool) {
+        *self.connected.lock().unwrap() = Some(connected);
+    }
+
+    /// Records a message that is dropped before it is processed.
+    pub fn count_dropped(&self) {
+        self.dropped.fetch_add(1, SeqCst);
+    }
+
+    /// Records a message that is received but rejected.
+    pub fn count_invalid(&self) {
+        self.invalid.fetch_add(1, SeqCst);
+    }
+
+    /// Records a message that fails to decode.
+    pub fn count_decode_error(&self) {
+        self.decode_errors.fetch_add(1, SeqCst);
+    }
+
+    /// Records a recovery from a lost connection.
+    pub fn count_reconnect(&self) {
+        self.reconnects.fetch_add(1, SeqCst);
+    }
+
+    /// Records a message that is not paired with the other streams.
+    pub fn count_unmatched(&self) {
+        self.unmatched.fetch_add(1, SeqCst);
+    }
+
+    /// Records a message that arrives after its pairing is done.
+    pub fn count_late(&self) {
+        self.late.fetch_add(1, SeqCst);
+    }
+
+    /// Summarizes the health since the last report.
+    pub fn report(&self) -> DiagnosticStatus {
+        let DiagnosticsConfig {
+            warn_rate,
+            error_rate,
+            warn_stale_secs,
+            error_stale_secs,
+            ..
+        } = self.config;
+        let rate = self.rate.take_rate();
+        let last_message = *self.last_message.lock().unwrap();
+        let connected = *self.connected.lock().unwrap();
+
+        let mut problems = vec![];
+
+        if connected == Some(false) {
+            problems.push((Level::Error, "disconnected".to_string()));
+        }
+
+        match last_message {
+            Some(last_message) => {
+                let age = last_message.elapsed().as_secs_f64();
+                if age >= error_stale_secs {
+                    problems.push((Level::Error, format!("no message for {:.1}s", age)));
+                } else if age >= warn_stale_secs {
+                    problems.push((Level::Warn, format!("no message for {:.1}s", age)));
+                }
+
+                if rate < error_rate {
+                    problems.push((Level::Error, format!("rate {:.1}Hz is too low", rate)));
+                } else if rate < warn_rate {
+                    problems.push((Level::Warn, format!("rate {:.1}Hz is low", rate)));
+                }
+            }
+            None => {
+                let level = if self.created.elapsed().as_secs_f64() >= error_stale_secs {
+                    Level::Error
+                } else {
+                    Level::Warn
+                };
+                problems.push((level, "no message received".to_string()));
+            }
+        }
+
+        let level = problems
+            .iter()
+            .map(|(level, _)| *level)
+            .max()
+            .unwrap_or(Level::Ok);
+        let message = if problems.is_empty() {
+            "OK".to_string()
+        } else {
+            let texts: Vec<_> = problems.into_iter().map(|(_, text)| text).collect();
+            texts.join(", ")
+        };
+
+        let age = match last_message {
+            Some(last_message) => format!("{:.3}", last_message.elapsed().as_secs_f64()),
+            None => "inf".to_string(),
+        };
+        let mut values = vec![
+            key_value("rate_hz", format!("{:.2}", rate)),
+            key_value("last_message_age_secs", age),
+            key_value("received", self.received.load(SeqCst)),
+            key_value("dropped", self.dropped.load(SeqCst)),
+            key_value("invalid", self.invalid.load(SeqCst)),
+            key_value("decode_errors", self.decode_errors.load(SeqCst)),
+            key_value("reconnects", self.reconnects.load(SeqCst)),
+            key_value("unmatched", self.unmatched.load(SeqCst)),
+            key_value("late", self.late.load(SeqCst)),
+        ];
+        if let Some(connected) = connected {
+            values.push(key_value("connected", connected));
+        }
+
+        DiagnosticStatus {
+            level: level as u8,
+            name: self.name.clone(),
+            message,
+            hardware_id: self.hardware_id.clone(),
+            values,
+        }
+    }
+}
+
+fn key_value(key: &str, value: impl ToString) -> KeyValue {
+    KeyValue {
+        key: key.to_string(),
+        value: value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates a monitor that has received a message right now.
+    fn monitor(config: DiagnosticsConfig) -> SensorMonitor {
+        let monitor = SensorMonitor::new("camera", "test", &config);
+        monitor.bump();
+        monitor
+    }
+
+    fn level(monitor: &SensorMonitor) -> u8 {
+        monitor.report().level
+    }
+
+    #[test]
+    fn report_ok_on_fresh_messages() {
+        let monitor = monitor(DiagnosticsConfig::default());
+        let status = monitor.report();
+        assert_eq!(status.level, Level::Ok as u8);
+        assert_eq!(status.message, "OK");
+    }
+
+    #[test]
+    fn report_low_rates() {
+        // Any measured rate is below the thresholds.
+        let warn = monitor(DiagnosticsConfig {
+            warn_rate: 1e12,
+            error_rate: 0.0,
+            ..Default::default()
+        });
+        assert_eq!(level(&warn), Level::Warn as u8);
+
+        let error = monitor(DiagnosticsConfig {
+            warn_rate: 1e12,
+            error_rate: 1e12,
+            ..Default::default()
+        });
+        assert_eq!(level(&error), Level::Error as u8);
+    }
+
+    #[test]
+    fn report_stale_messages() {
+        // Any message age reaches the thresholds.
+        let warn = monitor(DiagnosticsConfig {
+            warn_stale_secs: 0.0,
+            error_stale_secs: 60.0,
+            warn_rate: 0.0,
+            error_rate: 0.0,
+            ..Default::default()
+        });
+        assert_eq!(level(&warn), Level::Warn as u8);
+
+        let error = monitor(DiagnosticsConfig {
+            warn_stale_secs: 0.0,
+            error_stale_secs: 0.0,
+            warn_rate: 0.0,
+            error_rate: 0.0,
+            ..Default::default()
+        });
+        assert_eq!(level(&error), Level::Error as u8);
+    }
+
+    #[test]
+    fn report_missing_messages() {
+        let config = DiagnosticsConfig::default();
+        let monitor = SensorMonitor::new("camera", "test", &config);
+        assert_eq!(level(&monitor), Level::Warn as u8);
+
+        let config = DiagnosticsConfig {
+            warn_stale_secs: 0.0,
+            error_stale_secs: 0.0,
+            ..Default::default()
+        };
+        let monitor = SensorMonitor::new("camera", "test", &config);
+        assert_eq!(level(&monitor), Level::Error as u8);
+    }
+
+    #[test]
+    fn report_disconnection() {
+        let monitor = monitor(DiagnosticsConfig::default());
+        monitor.set_connected(false);
+        assert_eq!(level(&monitor), Level::Error as u8);
+
+        monitor.bump();
+        monitor.set_connected(true);
+        assert_eq!(level(&monitor), Level::Ok as u8);
+    }
+}
@@ -0,0 +1,49 @@
+use crate::{config::DiagnosticsConfig, monitor::SensorMonitor};
+use anyhow::Result;
+use r2r::{
+    builtin_interfaces::msg::Time, diagnostic_msgs::msg::DiagnosticArray, log_warn,
+    std_msgs::msg::Header, Node, QosProfile,
+};
+use std::{
+    sync::Arc,
+    thread::{self, JoinHandle},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Spawns a thread that periodically publishes the reports of the
+/// monitors in a `DiagnosticArray`. Publish failures are logged and
+/// the thread keeps running.
+pub fn spawn_diagnostics(
+    node: &mut Node,
+    config: &DiagnosticsConfig,
+    monitors: Vec<Arc<SensorMonitor>>,
+) -> Result<JoinHandle<()>> {
+    let publisher =
+        node.create_publisher::<DiagnosticArray>(&config.topic, QosProfile::default())?;
+    let period = config.period();
+
+    let handle = thread::spawn(move || loop {
+        thread::sleep(period);
+
+        let systime = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let msg = DiagnosticArray {
+            header: Header {
+                stamp: Time {
+                    sec: systime.as_secs() as i32,
+                    nanosec: systime.subsec_nanos(),
+                },
+                frame_id: "".to_string(),
+            },
+            status: monitors.iter().map(|monitor| monitor.report()).collect(),
+        };
+        if let Err(err) = publisher.publish(&msg) {
+            log_warn!(
+                env!("CARGO_PKG_NAME"),
+                "Unable to publish diagnostics: {:#}",
+                err
+            );
+        }
+    });
+
+    Ok(handle)
+}
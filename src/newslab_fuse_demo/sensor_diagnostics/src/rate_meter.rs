@@ -0,0 +1,51 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering::*},
+        Mutex,
+    },
+    time::Instant,
+};
+
+/// Counts events and measures the event rate between two reads.
+#[derive(Debug)]
+pub struct RateMeter {
+    count: AtomicUsize,
+    since: Mutex<Instant>,
+}
+
+impl RateMeter {
+    pub fn new() -> Self {
+        Self {
+            count: AtomicUsize::new(0),
+            since: Mutex::new(Instant::now()),
+        }
+    }
+
+    pub fn bump(&self) {
+        self.count.fetch_add(1, SeqCst);
+    }
+
+    /// Returns the events per second since the last call, and resets
+    /// the counter.
+    pub fn take_rate(&self) -> f64 {
+        let now = Instant::now();
+        let since = {
+            let mut since = self.since.lock().unwrap();
+            std::mem::replace(&mut *since, now)
+        };
+        let count = self.count.swap(0, SeqCst);
+
+        let secs = now.duration_since(since).as_secs_f64();
+        if secs > 0.0 {
+            count as f64 / secs
+        } else {
+            0.0
+        }
+    }
+}
+
+impl Default for RateMeter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
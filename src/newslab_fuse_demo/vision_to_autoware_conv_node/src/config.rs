@@ -0,0 +1,128 @@
+use crate::labels::LabelMap;
+use nalgebra as na;
+use newslab_fuse_demo::{config::MrptCalibration, yaml_loader::YamlPath};
+use serde::Deserialize;
+use serde_loader::Json5Path;
+use std::num::NonZeroUsize;
+
+/// The type defines the configuration file format.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Config {
+    /// Locate detections in 3D by LiDAR points. Detections are
+    /// converted in pixel units if it is not set.
+    #[serde(default)]
+    pub lidar: Option<LidarConfig>,
+    /// The JSON5 file that maps detector classes to Autoware labels.
+    /// Class ids are parsed as label numbers if it is not set.
+    #[serde(default)]
+    pub label_map_file: Option<Json5Path<LabelMap>>,
+    /// The tracker parameters used if `--tracked-topic` is set.
+    #[serde(default)]
+    pub tracker: TrackerConfig,
+    /// Project Autoware objects into a camera if `--projected-topic`
+    /// is set.
+    #[serde(default)]
+    pub projection: Option<ProjectionConfig>,
+}
+
+/// The calibration of a camera with respect to the LiDAR.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CameraConfig {
+    /// The intrinsic parameters file of the camera.
+    pub intrinsics_file: YamlPath<MrptCalibration>,
+    /// The extrinsic parameters file from the LiDAR to the camera.
+    extrinsics_file: Json5Path<na::Isometry3<f64>>,
+    pub pcd_rotate_90: bool,
+    pub image_hw: [NonZeroUsize; 2],
+    /// The size of the detection coordinates, which are scaled to
+    /// `image_hw`.
+    pub det_hw: [NonZeroUsize; 2],
+}
+
+impl CameraConfig {
+    pub fn pose(&self) -> na::Isometry3<f64> {
+        if self.pcd_rotate_90 {
+            *self.extrinsics_file
+                * na::UnitQuaternion::from_euler_angles(0.0, 0.0, 90f64.to_radians())
+        } else {
+            *self.extrinsics_file
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LidarConfig {
+    /// Input topic for point cloud.
+    pub pcd_topic: String,
+    /// The camera that detections come from.
+    #[serde(flatten)]
+    pub camera: CameraConfig,
+    /// The minimum number of LiDAR points in a box to locate the
+    /// object. It must be at least 1.
+    #[serde(default = "default_min_points")]
+    pub min_points: NonZeroUsize,
+    /// Points in a box farther than it in meters from the median
+    /// distance are regarded as background.
+    #[serde(default = "default_cluster_tolerance")]
+    pub cluster_tolerance: f32,
+    /// Detections are dropped if the latest point cloud is older or
+    /// newer than it in seconds.
+    #[serde(default = "default_max_time_diff_secs")]
+    pub max_time_diff_secs: f64,
+}
+
+/// Projects metric objects in the LiDAR frame into a camera.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProjectionConfig {
+    /// The frame id of projected detections.
+    pub frame_id: String,
+    #[serde(flatten)]
+    pub camera: CameraConfig,
+}
+
+fn default_min_points() -> NonZeroUsize {
+    NonZeroUsize::new(5).unwrap()
+}
+
+fn default_cluster_tolerance() -> f32 {
+    0.5
+}
+
+fn default_max_time_diff_secs() -> f64 {
+    0.5
+}
+
+/// The parameters of the multi-object tracker. Distances are in the
+/// units of the objects, that is pixels in the 2D mode and meters in
+/// the 3D mode.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TrackerConfig {
+    /// The minimum bird's-eye IoU to match a detection to a track.
+    pub iou_threshold: f64,
+    /// A track is published after it is matched in this many
+    /// consecutive frames. It must be at least 1.
+    pub min_hits: NonZeroUsize,
+    /// A track is removed after it is not matched in this many
+    /// consecutive frames. It must be at least 1.
+    pub max_misses: NonZeroUsize,
+    /// The standard deviation of the acceleration per second.
+    pub process_noise: f64,
+    /// The standard deviation of the detected positions.
+    pub measurement_noise: f64,
+    /// The standard deviation of the velocities of new tracks.
+    pub initial_velocity_noise: f64,
+}
+
+impl Default for TrackerConfig {
+    fn default() -> Self {
+        Self {
+            iou_threshold: 0.3,
+            min_hits: NonZeroUsize::new(3).unwrap(),
+            max_misses: NonZeroUsize::new(3).unwrap(),
+            process_noise: 1.0,
+            measurement_noise: 0.1,
+            initial_velocity_noise: 10.0,
+        }
+    }
+}
@@ -0,0 +1,311 @@
+//! Maps detector classes to Autoware object labels.
+
+use r2r::{
+    autoware_auto_perception_msgs::msg::ObjectClassification,
+    log_warn,
+    vision_msgs::msg::{ObjectHypothesis, ObjectHypothesisWithPose},
+};
+use serde::{de::Error as _, Deserialize, Deserializer};
+use std::collections::{HashMap, HashSet};
+
+/// The labels of `autoware_auto_perception_msgs/ObjectClassification`
+/// in the same values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+#[repr(u8)]
+pub enum Label {
+    Unknown = 0,
+    Car = 1,
+    Truck = 2,
+    Bus = 3,
+    Trailer = 4,
+    Motorcycle = 5,
+    Bicycle = 6,
+    Pedestrian = 7,
+}
+
+impl Label {
+    pub fn from_u8(label: u8) -> Option<Self> {
+        Some(match label {
+            0 => Self::Unknown,
+            1 => Self::Car,
+            2 => Self::Truck,
+            3 => Self::Bus,
+            4 => Self::Trailer,
+            5 => Self::Motorcycle,
+            6 => Self::Bicycle,
+            7 => Self::Pedestrian,
+            _ => return None,
+        })
+    }
+
+    /// The name in the label map file.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Unknown => "UNKNOWN",
+            Self::Car => "CAR",
+            Self::Truck => "TRUCK",
+            Self::Bus => "BUS",
+            Self::Trailer => "TRAILER",
+            Self::Motorcycle => "MOTORCYCLE",
+            Self::Bicycle => "BICYCLE",
+            Self::Pedestrian => "PEDESTRIAN",
+        }
+    }
+}
+
+/// What to do with classes that are not in the label map.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UnmappedPolicy {
+    /// Keep the class as `UNKNOWN` with its original score.
+    #[default]
+    Keep,
+    /// Drop the class. A detection is dropped if none of its classes
+    /// is kept.
+    Drop,
+}
+
+/// The label map file format.
+#[derive(Debug, Clone)]
+pub struct LabelMap {
+    classes: HashMap<String, Mapping>,
+    unmapped: UnmappedPolicy,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Mapping {
+    label: Label,
+    score_scale: f64,
+}
+
+impl LabelMap {
+    /// Looks up a class id. Numeric ids match the class numbers
+    /// written either as numbers or as text in the file.
+    fn get(&self, class_id: &str) -> Option<&Mapping> {
+        self.classes.get(class_id).or_else(|| {
+            let number: u64 = class_id.trim().parse().ok()?;
+            self.classes.get(&number.to_string())
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for LabelMap {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let UncheckedLabelMap { mappings, unmapped } =
+            UncheckedLabelMap::deserialize(deserializer)?;
+        let mut classes = HashMap::new();
+
+        for entry in mappings {
+            let UncheckedMapping {
+                classes: names,
+                label,
+                score_scale,
+            } = entry;
+
+            if !(score_scale.is_finite() && score_scale >= 0.0) {
+                return Err(D::Error::custom(format!(
+                    "score_scale of {:?} must be a non-negative number, but get {}",
+                    label, score_scale
+                )));
+            }
+
+            for name in names {
+                let name = name.into_key();
+                let mapping = Mapping { label, score_scale };
+                if classes.insert(name.clone(), mapping).is_some() {
+                    return Err(D::Error::custom(format!(
+                        "class '{}' is mapped more than once",
+                        name
+                    )));
+                }
+            }
+        }
+
+        Ok(Self { classes, unmapped })
+    }
+}
+
+#[derive(Deserialize)]
+struct UncheckedLabelMap {
+    mappings: Vec<UncheckedMapping>,
+    #[serde(default)]
+    unmapped: UnmappedPolicy,
+}
+
+#[derive(Deserialize)]
+struct UncheckedMapping {
+    classes: Vec<ClassKey>,
+    label: Label,
+    /// Scores are multiplied by it and clamped to 1.
+    #[serde(default = "default_score_scale")]
+    score_scale: f64,
+}
+
+/// A class name, or a class number either as a number or in text.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ClassKey {
+    Number(u64),
+    Name(String),
+}
+
+impl ClassKey {
+    /// Class numbers are kept in decimal text so that `3` and `"3"`
+    /// are the same key.
+    fn into_key(self) -> String {
+        match self {
+            Self::Number(number) => number.to_string(),
+            Self::Name(name) => match name.trim().parse::<u64>() {
+                Ok(number) => number.to_string(),
+                Err(_) => name,
+            },
+        }
+    }
+}
+
+fn default_score_scale() -> f64 {
+    1.0
+}
+
+/// Converts object hypotheses to Autoware classifications.
+#[derive(Debug)]
+pub struct Classifier {
+    label_map: Option<LabelMap>,
+    /// Unmapped classes that are already warned about.
+    warned: HashSet<String>,
+}
+
+impl Classifier {
+    /// Creates a classifier. Without a label map, class ids are parsed
+    /// as Autoware label numbers.
+    pub fn new(label_map: Option<LabelMap>) -> Self {
+        Self {
+            label_map,
+            warned: HashSet::new(),
+        }
+    }
+
+    /// Returns the classifications sorted by probabilities, or `None`
+    /// if the detection should be dropped.
+    pub fn classify(
+        &mut self,
+        results: Vec<ObjectHypothesisWithPose>,
+    ) -> Option<Vec<ObjectClassification>> {
+        if results.is_empty() {
+            return Some(vec![]);
+        }
+
+        let mut classes: Vec<ObjectClassification> = vec![];
+
+        for result in results {
+            let ObjectHypothesisWithPose {
+                hypothesis: ObjectHypothesis { class_id, score },
+                ..
+            } = result;
+
+            let (label, probability) = match self.map_class(&class_id, score) {
+                Some(output) => output,
+                None => continue,
+            };
+
+            // Classes mapped to the same label are merged.
+            match classes.iter_mut().find(|class| class.label == label) {
+                Some(class) => class.probability = class.probability.max(probability),
+                None => classes.push(ObjectClassification { label, probability }),
+            }
+        }
+
+        if classes.is_empty() {
+            return None;
+        }
+        classes.sort_by(|lhs, rhs| rhs.probability.total_cmp(&lhs.probability));
+        Some(classes)
+    }
+
+    fn map_class(&mut self, class_id: &str, score: f64) -> Option<(u8, f32)> {
+        let label_map = match &self.label_map {
+            Some(label_map) => label_map,
+            None => {
+                let label: u8 = match class_id.parse() {
+                    Ok(val) => val,
+                    Err(_) => {
+                        self.warn_once(class_id, "is not a number. Assume label=0.");
+                        Label::Unknown as u8
+                    }
+                };
+                return Some((label, score as f32));
+            }
+        };
+
+        match (label_map.get(class_id), label_map.unmapped) {
+            (Some(mapping), _) => {
+                let probability = (score * mapping.score_scale).clamp(0.0, 1.0);
+                Some((mapping.label as u8, probability as f32))
+            }
+            (None, UnmappedPolicy::Keep) => {
+                self.warn_once(class_id, "is not mapped. Assume label=UNKNOWN.");
+                Some((Label::Unknown as u8, score as f32))
+            }
+            (None, UnmappedPolicy::Drop) => {
+                self.warn_once(class_id, "is not mapped. Drop it.");
+                None
+            }
+        }
+    }
+
+    fn warn_once(&mut self, class_id: &str, message: &str) {
+        if self.warned.insert(class_id.to_string()) {
+            log_warn!(
+                env!("CARGO_PKG_NAME"),
+                "class_id '{}' {}",
+                class_id,
+                message
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn label(label_map: &LabelMap, class_id: &str) -> Option<Label> {
+        label_map.get(class_id).map(|mapping| mapping.label)
+    }
+
+    #[test]
+    fn match_class_numbers_and_text() {
+        let label_map: LabelMap = json5::from_str(
+            r#"{
+                mappings: [
+                    { classes: [0, "person"], label: "PEDESTRIAN" },
+                    { classes: ["2"], label: "CAR" },
+                ],
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(label(&label_map, "0"), Some(Label::Pedestrian));
+        assert_eq!(label(&label_map, "person"), Some(Label::Pedestrian));
+        assert_eq!(label(&label_map, "2"), Some(Label::Car));
+        assert_eq!(label(&label_map, "02"), Some(Label::Car));
+        assert_eq!(label(&label_map, "1"), None);
+    }
+
+    #[test]
+    fn reject_classes_mapped_twice() {
+        let result: Result<LabelMap, _> = json5::from_str(
+            r#"{
+                mappings: [
+                    { classes: [2], label: "CAR" },
+                    { classes: ["2"], label: "TRUCK" },
+                ],
+            }"#,
+        );
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,111 @@
+//! Locates 2D detections in 3D by the LiDAR points projected into
+//! their boxes.
+
+use crate::config::LidarConfig;
+use anyhow::Result;
+use nalgebra as na;
+use newslab_fuse_demo::{
+    message as msg,
+    point_projection::{CameraParams, PointProjector},
+};
+use opencv::core::Point2f;
+use r2r::{geometry_msgs::msg::Pose2D, vision_msgs::msg::BoundingBox2D};
+
+/// An axis-aligned box in the LiDAR frame.
+#[derive(Debug, Clone, Copy)]
+pub struct Cuboid {
+    pub center: na::Point3<f32>,
+    pub size: na::Vector3<f32>,
+}
+
+pub struct BoxLocator {
+    projector: PointProjector,
+    scale_hw: [f64; 2],
+    min_points: usize,
+    cluster_tolerance: f32,
+}
+
+impl BoxLocator {
+    pub fn new(config: &LidarConfig) -> Result<Self> {
+        let camera = &config.camera;
+        let [image_h, image_w] = camera.image_hw;
+        let [det_h, det_w] = camera.det_hw;
+        let camera_params = CameraParams::new(&camera.intrinsics_file, &camera.pose())?;
+
+        Ok(Self {
+            projector: PointProjector {
+                height: image_h.get(),
+                width: image_w.get(),
+                camera_params,
+            },
+            scale_hw: [
+                image_h.get() as f64 / det_h.get() as f64,
+                image_w.get() as f64 / det_w.get() as f64,
+            ],
+            min_points: config.min_points.get(),
+            cluster_tolerance: config.cluster_tolerance,
+        })
+    }
+
+    /// Projects LiDAR points onto the image.
+    pub fn project(&self, points: &msg::ArcPointVec) -> Vec<(msg::ArcPoint, Point2f)> {
+        self.projector.project(points)
+    }
+
+    /// Estimates the box of an object from the projected points in its
+    /// 2D box. It returns `None` if there are too few points.
+    ///
+    /// The points at around the median distance are taken as the
+    /// object, and the rest are regarded as background.
+    pub fn locate(
+        &self,
+        projected: &[(msg::ArcPoint, Point2f)],
+        bbox: &BoundingBox2D,
+    ) -> Option<Cuboid> {
+        let [scale_h, scale_w] = self.scale_hw;
+        let BoundingBox2D {
+            size_x,
+            size_y,
+            center: Pose2D { x: cx, y: cy, .. },
+        } = *bbox;
+        let x_range =
+            ((cx - size_x / 2.0) * scale_w) as f32..=((cx + size_x / 2.0) * scale_w) as f32;
+        let y_range =
+            ((cy - size_y / 2.0) * scale_h) as f32..=((cy + size_y / 2.0) * scale_h) as f32;
+
+        let mut points: Vec<_> = projected
+            .iter()
+            .filter(|(_, img_point)| {
+                x_range.contains(&img_point.x) && y_range.contains(&img_point.y)
+            })
+            .map(|(pcd_point, _)| {
+                let position = pcd_point.position;
+                (na::distance(&na::Point3::origin(), &position), position)
+            })
+            .collect();
+        if points.len() < self.min_points {
+            return None;
+        }
+
+        // Keep the points around the median distance.
+        points.sort_unstable_by(|(lhs, _), (rhs, _)| lhs.total_cmp(rhs));
+        let median = points[points.len() / 2].0;
+        let positions: Vec<_> = points
+            .into_iter()
+            .filter(|(dist, _)| (dist - median).abs() <= self.cluster_tolerance)
+            .map(|(_, position)| position)
+            .collect();
+        if positions.len() < self.min_points {
+            return None;
+        }
+
+        let (min, max) = positions.iter().skip(1).fold(
+            (positions[0].coords, positions[0].coords),
+            |(min, max), point| (min.inf(&point.coords), max.sup(&point.coords)),
+        );
+        Some(Cuboid {
+            center: na::center(&min.into(), &max.into()),
+            size: max - min,
+        })
+    }
+}
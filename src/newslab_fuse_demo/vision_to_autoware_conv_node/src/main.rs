@@ -1,22 +1,39 @@
-use anyhow::Result;
+mod config;
+mod labels;
+mod locator;
+mod projection;
+mod tracker;
+
+use crate::{
+    config::Config, labels::Classifier, locator::BoxLocator, projection::BoxProjector,
+    tracker::Tracker,
+};
+use anyhow::{ensure, Context as _, Result};
 use async_std::task::spawn_blocking;
 use clap::Parser;
 use futures::{
-    future::{FutureExt as _, TryFutureExt as _},
-    stream::{StreamExt as _, TryStreamExt as _},
+    future::{self, FutureExt as _, TryFutureExt as _},
+    stream::{self, StreamExt as _, TryStreamExt as _},
 };
+use nalgebra as na;
+use newslab_fuse_demo::fuse::pcd_to_points;
+use ownref::ArcRefA as ARef;
 use r2r::{
     autoware_auto_perception_msgs::msg::{
-        DetectedObject, DetectedObjectKinematics, DetectedObjects, ObjectClassification, Shape,
+        DetectedObject, DetectedObjectKinematics, DetectedObjects, Shape, TrackedObjects,
     },
-    geometry_msgs::msg::{Polygon, TwistWithCovariance, Vector3},
-    log_warn,
-    vision_msgs::msg::{
-        BoundingBox2D, Detection2D, Detection2DArray, ObjectHypothesis, ObjectHypothesisWithPose,
+    builtin_interfaces::msg::Time,
+    geometry_msgs::msg::{
+        Point, Point32, Polygon, Pose, PoseWithCovariance, Quaternion, TwistWithCovariance, Vector3,
     },
+    log_info, log_warn,
+    sensor_msgs::msg::PointCloud2,
+    std_msgs::msg::Header,
+    vision_msgs::msg::{BoundingBox2D, Detection2D, Detection2DArray},
     Context, Node, QosProfile,
 };
-use std::time::Duration;
+use serde_loader::Json5Path;
+use std::{path::PathBuf, time::Duration};
 
 #[derive(Parser)]
 struct Opts {
@@ -24,13 +41,54 @@ struct Opts {
     pub input_topic: String,
     #[clap(long)]
     pub output_topic: Option<String>,
+    /// Track objects and publish them on the topic.
+    #[clap(long)]
+    pub tracked_topic: Option<String>,
+    /// Project the objects on `--objects-input-topic` and
+    /// `--tracked-input-topic` into the camera and publish them on the
+    /// topic.
+    #[clap(long)]
+    pub projected_topic: Option<String>,
+    /// The input topic of `DetectedObjects` to project.
+    #[clap(long)]
+    pub objects_input_topic: Option<String>,
+    /// The input topic of `TrackedObjects` to project.
+    #[clap(long)]
+    pub tracked_input_topic: Option<String>,
     #[clap(long, default_value = "/")]
     pub namespace: String,
+    /// The JSON5 config file.
+    #[clap(long)]
+    pub config: Option<PathBuf>,
+}
+
+/// An input message in the 3D mode.
+enum Input {
+    Detections(Detection2DArray),
+    PointCloud(PointCloud2),
+}
+
+/// An input message to project into the camera.
+enum Objects {
+    Detected(DetectedObjects),
+    Tracked(TrackedObjects),
 }
 
 #[async_std::main]
 async fn main() -> Result<()> {
     let opts = Opts::parse();
+    let config: Option<Config> = opts
+        .config
+        .as_ref()
+        .map(Json5Path::open_and_take)
+        .transpose()?;
+    let Config {
+        lidar: lidar_config,
+        label_map_file,
+        tracker: tracker_config,
+        projection: projection_config,
+    } = config.unwrap_or_default();
+    let mut classifier = Classifier::new(label_map_file.map(|label_map| label_map.take()));
 
     let ctx = Context::create()?;
     let mut node = Node::create(ctx, env!("CARGO_PKG_NAME"), &opts.namespace)?;
@@ -42,106 +100,303 @@ async fn main() -> Result<()> {
         .as_ref()
         .map(|topic| node.create_publisher::<DetectedObjects>(topic, QosProfile::default()))
         .transpose()?;
+    let tracked_publisher = opts
+        .tracked_topic
+        .as_ref()
+        .map(|topic| node.create_publisher::<TrackedObjects>(topic, QosProfile::default()))
+        .transpose()?;
 
-    let stream = subscriber
-        .map(|det| {
-            let Detection2DArray { header, detections } = det;
-
-            let objects = detections
-                .into_iter()
-                .map(|det| {
-                    let Detection2D { results, bbox, .. } = det;
-
-                    let kinematics = {
-                        let pose = results.get(0).map(|result| &result.pose);
-                        let has_position_covariance = pose.is_some();
-                        let pose_with_covariance = pose.cloned().unwrap_or_default();
-
-                        DetectedObjectKinematics {
-                            pose_with_covariance,
-                            has_position_covariance,
-                            orientation_availability: 0,
-                            twist_with_covariance: TwistWithCovariance::default(),
-                            has_twist: false,
-                            has_twist_covariance: false,
+    let stream = match lidar_config {
+        Some(lidar_config) => {
+            // Locate detections by the latest point cloud.
+            log_info!(
+                env!("CARGO_PKG_NAME"),
+                "Subscribe point cloud from {}",
+                lidar_config.pcd_topic
+            );
+            let pcd_sub =
+                node.subscribe::<PointCloud2>(&lidar_config.pcd_topic, QosProfile::default())?;
+            let locator = BoxLocator::new(&lidar_config)?;
+            let max_time_diff = lidar_config.max_time_diff_secs;
+            // The header of the latest point cloud and its points
+            // projected onto the image.
+            let mut latest_pcd = None;
+
+            stream::select(
+                subscriber.map(Input::Detections),
+                pcd_sub.map(Input::PointCloud),
+            )
+            .filter_map(move |input| {
+                let output = match input {
+                    Input::PointCloud(pcd) => {
+                        match pcd_to_points(&pcd) {
+                            Ok(points) => {
+                                let projected = locator.project(&ARef::new(points));
+                                latest_pcd = Some((pcd.header, projected));
+                            }
+                            Err(err) => log_warn!(env!("CARGO_PKG_NAME"), "{:#}", err),
                         }
-                    };
-
-                    let shape = {
-                        let BoundingBox2D { size_x, size_y, .. } = bbox;
-                        Shape {
-                            type_: 0, // BOUNDING_BOX
-                            footprint: Polygon::default(),
-                            dimensions: Vector3 {
-                                x: size_x,
-                                y: size_y,
-                                z: 0.0,
-                            },
+                        None
+                    }
+                    Input::Detections(det) => match &latest_pcd {
+                        Some((pcd_header, projected))
+                            if time_diff_secs(&det.header.stamp, &pcd_header.stamp).abs()
+                                <= max_time_diff =>
+                        {
+                            Some(convert_3d(det, pcd_header, &mut classifier, |bbox| {
+                                locator.locate(projected, bbox)
+                            }))
                         }
-                    };
-
-                    let classification: Vec<_> = results
-                        .into_iter()
-                        .map(|result| {
-                            let ObjectHypothesisWithPose {
-                                hypothesis: ObjectHypothesis { class_id, score },
-                                ..
-                            } = result;
-
-                            let label: u8 = match class_id.parse() {
-                                Ok(val) => val,
-                                Err(_) => {
-                                    log_warn!(
-                                        env!("CARGO_PKG_NAME"),
-                                        "class_id '{}' is not a number. Assume label=0.",
-                                        class_id
-                                    );
-                                    0
-                                }
-                            };
-
-                            ObjectClassification {
-                                label,
-                                probability: score as f32,
-                            }
-                        })
-                        .collect();
-
-                    DetectedObject {
-                        existence_probability: 1.0,
-                        classification,
-                        kinematics,
-                        shape,
+                        _ => {
+                            log_warn!(
+                                env!("CARGO_PKG_NAME"),
+                                "Drop detections without a point cloud within {}s",
+                                max_time_diff
+                            );
+                            None
+                        }
+                    },
+                };
+                future::ready(output)
+            })
+            .boxed()
+        }
+        None => subscriber
+            .map(move |det| convert_2d(det, &mut classifier))
+            .boxed(),
+    };
+    let stream = stream.inspect(|objects| {
+        println!("{:#?}", objects);
+    });
+
+    // Track objects across frames.
+    let stream = match tracked_publisher {
+        Some(tracked_publisher) => {
+            let mut tracker = Tracker::new(tracker_config);
+            stream
+                .map(move |objects| {
+                    let tracked = tracker.update(&objects);
+
+                    // Skip the message on failure rather than stopping
+                    // the node.
+                    if let Err(err) = tracked_publisher.publish(&tracked) {
+                        log_warn!(
+                            env!("CARGO_PKG_NAME"),
+                            "Unable to publish tracked objects: {:#}",
+                            err
+                        );
                     }
+                    anyhow::Ok(objects)
                 })
-                .collect();
-
-            DetectedObjects { header, objects }
-        })
-        .inspect(|objects| {
-            println!("{:#?}", objects);
-        });
+                .boxed()
+        }
+        None => stream.map(anyhow::Ok).boxed(),
+    };
 
     let conv_future = match publisher {
         Some(publisher) => stream
-            .map(anyhow::Ok)
             .try_fold(publisher, |publisher, msg| async move {
                 publisher.publish(&msg)?;
                 Ok(publisher)
             })
             .map_ok(|_publisher| ())
             .boxed(),
-        None => stream
-            .map(anyhow::Ok)
-            .try_for_each(|_| async move { Ok(()) })
-            .boxed(),
+        None => stream.try_for_each(|_| async move { Ok(()) }).boxed(),
+    };
+
+    // Project Autoware objects into the camera.
+    let projection_future = match &opts.projected_topic {
+        Some(topic) => {
+            let projection_config = projection_config
+                .context("--projected-topic requires the \"projection\" section in the config")?;
+            let projector = BoxProjector::new(&projection_config)?;
+            let publisher =
+                node.create_publisher::<Detection2DArray>(topic, QosProfile::default())?;
+
+            let mut inputs = vec![];
+            if let Some(topic) = &opts.objects_input_topic {
+                let sub = node.subscribe::<DetectedObjects>(topic, QosProfile::default())?;
+                inputs.push(sub.map(Objects::Detected).boxed());
+            }
+            if let Some(topic) = &opts.tracked_input_topic {
+                let sub = node.subscribe::<TrackedObjects>(topic, QosProfile::default())?;
+                inputs.push(sub.map(Objects::Tracked).boxed());
+            }
+            ensure!(
+                !inputs.is_empty(),
+                "--projected-topic requires --objects-input-topic or --tracked-input-topic"
+            );
+
+            stream::select_all(inputs)
+                .map(anyhow::Ok)
+                .try_for_each(move |objects| {
+                    let result = match &objects {
+                        Objects::Detected(objects) => projector.project_detected(objects),
+                        Objects::Tracked(objects) => projector.project_tracked(objects),
+                    }
+                    .and_then(|det| {
+                        publisher.publish(&det)?;
+                        Ok(())
+                    });
+                    future::ready(result)
+                })
+                .boxed()
+        }
+        None => future::ok(()).boxed(),
     };
 
     let spin_future = spawn_blocking(move || loop {
         node.spin_once(Duration::from_millis(100));
     });
 
-    futures::try_join!(conv_future, spin_future.map(anyhow::Ok))?;
+    futures::try_join!(conv_future, projection_future, spin_future.map(anyhow::Ok))?;
 
     Ok(())
 }
+
+/// Converts detections to objects in pixel units.
+fn convert_2d(det: Detection2DArray, classifier: &mut Classifier) -> DetectedObjects {
+    let Detection2DArray { header, detections } = det;
+
+    let objects = detections
+        .into_iter()
+        .filter_map(|det| {
+            let Detection2D { results, bbox, .. } = det;
+
+            let kinematics = {
+                let pose = results.get(0).map(|result| &result.pose);
+                let has_position_covariance = pose.is_some();
+                let pose_with_covariance = pose.cloned().unwrap_or_default();
+
+                DetectedObjectKinematics {
+                    pose_with_covariance,
+                    has_position_covariance,
+                    orientation_availability: 0,
+                    twist_with_covariance: TwistWithCovariance::default(),
+                    has_twist: false,
+                    has_twist_covariance: false,
+                }
+            };
+
+            let shape = {
+                let BoundingBox2D { size_x, size_y, .. } = bbox;
+                Shape {
+                    type_: 0, // BOUNDING_BOX
+                    footprint: Polygon::default(),
+                    dimensions: Vector3 {
+                        x: size_x,
+                        y: size_y,
+                        z: 0.0,
+                    },
+                }
+            };
+
+            Some(DetectedObject {
+                existence_probability: 1.0,
+                classification: classifier.classify(results)?,
+                kinematics,
+                shape,
+            })
+        })
+        .collect();
+
+    DetectedObjects { header, objects }
+}
+
+/// Converts detections to metric objects in the LiDAR frame.
+/// Detections that cannot be located are dropped.
+fn convert_3d<F>(
+    det: Detection2DArray,
+    pcd_header: &Header,
+    classifier: &mut Classifier,
+    mut locate: F,
+) -> DetectedObjects
+where
+    F: FnMut(&BoundingBox2D) -> Option<locator::Cuboid>,
+{
+    let Detection2DArray { header, detections } = det;
+
+    let objects = detections
+        .into_iter()
+        .filter_map(|det| {
+            let Detection2D { results, bbox, .. } = det;
+            let classification = classifier.classify(results)?;
+            let locator::Cuboid { center, size } = locate(&bbox)?;
+            let center: na::Point3<f64> = na::convert(center);
+            let size: na::Vector3<f64> = na::convert(size);
+
+            let kinematics = DetectedObjectKinematics {
+                pose_with_covariance: PoseWithCovariance {
+                    pose: Pose {
+                        position: Point {
+                            x: center.x,
+                            y: center.y,
+                            z: center.z,
+                        },
+                        orientation: Quaternion {
+                            x: 0.0,
+                            y: 0.0,
+                            z: 0.0,
+                            w: 1.0,
+                        },
+                    },
+                    ..Default::default()
+                },
+                has_position_covariance: false,
+                orientation_availability: 0, // UNAVAILABLE
+                twist_with_covariance: TwistWithCovariance::default(),
+                has_twist: false,
+                has_twist_covariance: false,
+            };
+
+            let shape = Shape {
+                type_: 0, // BOUNDING_BOX
+                footprint: box_footprint(&size),
+                dimensions: Vector3 {
+                    x: size.x,
+                    y: size.y,
+                    z: size.z,
+                },
+            };
+
+            Some(DetectedObject {
+                existence_probability: 1.0,
+                classification,
+                kinematics,
+                shape,
+            })
+        })
+        .collect();
+
+    DetectedObjects {
+        header: Header {
+            stamp: header.stamp,
+            frame_id: pcd_header.frame_id.clone(),
+        },
+        objects,
+    }
+}
+
+/// The counterclockwise corners of a box on the x-y plane relative to
+/// its center.
+fn box_footprint(size: &na::Vector3<f64>) -> Polygon {
+    let half_x = size.x as f32 / 2.0;
+    let half_y = size.y as f32 / 2.0;
+    let points = [
+        (half_x, half_y),
+        (-half_x, half_y),
+        (-half_x, -half_y),
+        (half_x, -half_y),
+    ]
+    .into_iter()
+    .map(|(x, y)| Point32 { x, y, z: 0.0 })
+    .collect();
+
+    Polygon { points }
+}
+
+fn time_diff_secs(lhs: &Time, rhs: &Time) -> f64 {
+    let secs = lhs.sec as f64 - rhs.sec as f64;
+    let nanos = lhs.nanosec as f64 - rhs.nanosec as f64;
+    secs + nanos / 1e9
+}
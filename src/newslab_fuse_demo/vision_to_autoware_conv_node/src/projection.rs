@@ -0,0 +1,370 @@
+//! Projects metric Autoware objects into a camera as 2D detections.
+
+use crate::{config::ProjectionConfig, labels::Label};
+use anyhow::Result;
+use nalgebra as na;
+use newslab_fuse_demo::point_projection::CameraParams;
+use opencv::{
+    calib3d,
+    core::{no_array, Point2f, Point3f, Vector},
+};
+use r2r::{
+    autoware_auto_perception_msgs::msg::{
+        DetectedObjects, ObjectClassification, Shape, TrackedObjects,
+    },
+    geometry_msgs::msg::{Point, Pose, Pose2D, PoseWithCovariance, Quaternion, Vector3},
+    std_msgs::msg::Header,
+    vision_msgs::msg::{
+        BoundingBox2D, Detection2D, Detection2DArray, ObjectHypothesis, ObjectHypothesisWithPose,
+    },
+};
+use uuid::Uuid;
+
+/// The near plane in meters. Shapes are clipped to the part farther
+/// than it from the camera plane.
+const MIN_DEPTH: f32 = 0.1;
+
+pub struct BoxProjector {
+    frame_id: String,
+    camera_params: CameraParams,
+    image_hw: [f32; 2],
+    /// The scale from the image to the detection coordinates.
+    scale_hw: [f32; 2],
+}
+
+impl BoxProjector {
+    pub fn new(config: &ProjectionConfig) -> Result<Self> {
+        let camera = &config.camera;
+        let [image_h, image_w] = camera.image_hw;
+        let [det_h, det_w] = camera.det_hw;
+        let camera_params = CameraParams::new(&camera.intrinsics_file, &camera.pose())?;
+
+        Ok(Self {
+            frame_id: config.frame_id.clone(),
+            camera_params,
+            image_hw: [image_h.get() as f32, image_w.get() as f32],
+            scale_hw: [
+                det_h.get() as f32 / image_h.get() as f32,
+                det_w.get() as f32 / image_w.get() as f32,
+            ],
+        })
+    }
+
+    /// Projects detected objects. Objects that are entirely behind the
+    /// camera or out of the image are dropped.
+    pub fn project_detected(&self, objects: &DetectedObjects) -> Result<Detection2DArray> {
+        let mut detections = vec![];
+
+        for object in &objects.objects {
+            let pose = &object.kinematics.pose_with_covariance.pose;
+            if let Some(bbox) = self.project_shape(pose, &object.shape)? {
+                detections.push(self.detection(
+                    &objects.header,
+                    bbox,
+                    &object.classification,
+                    String::new(),
+                ));
+            }
+        }
+
+        Ok(self.detection_array(&objects.header, detections))
+    }
+
+    /// Projects tracked objects. The detection ids are the object
+    /// UUIDs.
+    pub fn project_tracked(&self, objects: &TrackedObjects) -> Result<Detection2DArray> {
+        let mut detections = vec![];
+
+        for object in &objects.objects {
+            let pose = &object.kinematics.pose_with_covariance.pose;
+            if let Some(bbox) = self.project_shape(pose, &object.shape)? {
+                let id = match Uuid::from_slice(&object.object_id.uuid) {
+                    Ok(uuid) => uuid.to_string(),
+                    Err(_) => String::new(),
+                };
+                detections.push(self.detection(&objects.header, bbox, &object.classification, id));
+            }
+        }
+
+        Ok(self.detection_array(&objects.header, detections))
+    }
+
+    /// Projects the corners of a shape and returns the bounding box in
+    /// detection coordinates. The shape is clipped at [MIN_DEPTH] so
+    /// that objects partially behind the camera are kept.
+    fn project_shape(&self, pose: &Pose, shape: &Shape) -> Result<Option<BoundingBox2D>> {
+        let Vector3 {
+            x: size_x,
+            y: size_y,
+            z: size_z,
+        } = shape.dimensions;
+
+        // The base is the footprint if it exists, or the bounding box.
+        let base: Vec<(f64, f64)> = if shape.footprint.points.is_empty() {
+            vec![
+                (size_x / 2.0, size_y / 2.0),
+                (-size_x / 2.0, size_y / 2.0),
+                (-size_x / 2.0, -size_y / 2.0),
+                (size_x / 2.0, -size_y / 2.0),
+            ]
+        } else {
+            shape
+                .footprint
+                .points
+                .iter()
+                .map(|point| (point.x as f64, point.y as f64))
+                .collect()
+        };
+
+        let object_pose = to_isometry(pose);
+        let corners: Vec<na::Point3<f32>> = base
+            .into_iter()
+            .flat_map(|(x, y)| {
+                [-size_z / 2.0, size_z / 2.0]
+                    .map(|z| na::convert(object_pose * na::Point3::new(x, y, z)))
+            })
+            .collect();
+
+        let corners = clip_to_depth(&corners, &self.camera_params.pose, MIN_DEPTH);
+        if corners.is_empty() {
+            return Ok(None);
+        }
+
+        let CameraParams {
+            rvec,
+            tvec,
+            camera_matrix,
+            distortion_coefficients,
+            ..
+        } = &self.camera_params;
+        let object_points: Vector<Point3f> = corners
+            .iter()
+            .map(|corner| Point3f::new(corner.x, corner.y, corner.z))
+            .collect();
+        let mut image_points: Vector<Point2f> = Vector::new();
+        calib3d::project_points(
+            &object_points,
+            rvec,
+            tvec,
+            camera_matrix,
+            distortion_coefficients,
+            &mut image_points,
+            &mut no_array(), // jacobian
+            0.0,             // aspect_ratio
+        )?;
+
+        // Clip the box to the image.
+        let [image_h, image_w] = self.image_hw;
+        let (min, max) = image_points.into_iter().fold(
+            ([f32::INFINITY; 2], [f32::NEG_INFINITY; 2]),
+            |([min_x, min_y], [max_x, max_y]), point| {
+                (
+                    [min_x.min(point.x), min_y.min(point.y)],
+                    [max_x.max(point.x), max_y.max(point.y)],
+                )
+            },
+        );
+        let [lx, ty] = [min[0].max(0.0), min[1].max(0.0)];
+        let [rx, by] = [max[0].min(image_w), max[1].min(image_h)];
+        if lx >= rx || ty >= by {
+            return Ok(None);
+        }
+
+        let [scale_h, scale_w] = self.scale_hw;
+        Ok(Some(BoundingBox2D {
+            center: Pose2D {
+                x: ((lx + rx) / 2.0 * scale_w) as f64,
+                y: ((ty + by) / 2.0 * scale_h) as f64,
+                theta: 0.0,
+            },
+            size_x: ((rx - lx) * scale_w) as f64,
+            size_y: ((by - ty) * scale_h) as f64,
+        }))
+    }
+
+    fn detection(
+        &self,
+        header: &Header,
+        bbox: BoundingBox2D,
+        classification: &[ObjectClassification],
+        id: String,
+    ) -> Detection2D {
+        let pose = PoseWithCovariance {
+            pose: Pose {
+                position: Point {
+                    x: bbox.center.x,
+                    y: bbox.center.y,
+                    z: 0.0,
+                },
+                orientation: Quaternion {
+                    x: 0.0,
+                    y: 0.0,
+                    z: 0.0,
+                    w: 1.0,
+                },
+            },
+            ..Default::default()
+        };
+        let results = classification
+            .iter()
+            .map(|class| {
+                let class_id = match Label::from_u8(class.label) {
+                    Some(label) => label.as_str().to_string(),
+                    None => class.label.to_string(),
+                };
+
+                ObjectHypothesisWithPose {
+                    hypothesis: ObjectHypothesis {
+                        class_id,
+                        score: class.probability as f64,
+                    },
+                    pose: pose.clone(),
+                }
+            })
+            .collect();
+
+        Detection2D {
+            header: self.header(header),
+            results,
+            bbox,
+            id,
+        }
+    }
+
+    fn detection_array(&self, header: &Header, detections: Vec<Detection2D>) -> Detection2DArray {
+        Detection2DArray {
+            header: self.header(header),
+            detections,
+        }
+    }
+
+    fn header(&self, header: &Header) -> Header {
+        Header {
+            stamp: header.stamp.clone(),
+            frame_id: self.frame_id.clone(),
+        }
+    }
+}
+
+/// Clips a convex shape, given by its corners, to the part at least
+/// `min_depth` in front of the camera. It returns the corners in front
+/// and the points where the segments between corners cross the plane,
+/// whose bounding box in the image covers the visible part.
+fn clip_to_depth(
+    corners: &[na::Point3<f32>],
+    camera_pose: &na::Isometry3<f32>,
+    min_depth: f32,
+) -> Vec<na::Point3<f32>> {
+    let depths: Vec<f32> = corners
+        .iter()
+        .map(|corner| (camera_pose * corner).z)
+        .collect();
+
+    let front = corners
+        .iter()
+        .zip(&depths)
+        .filter(|(_, &depth)| depth >= min_depth)
+        .map(|(corner, _)| *corner);
+    let crossings = corners
+        .iter()
+        .zip(&depths)
+        .enumerate()
+        .flat_map(|(idx, lhs)| {
+            corners[idx + 1..]
+                .iter()
+                .zip(&depths[idx + 1..])
+                .map(move |rhs| (lhs, rhs))
+        })
+        .filter(|((_, &lhs_depth), (_, &rhs_depth))| {
+            (lhs_depth < min_depth) != (rhs_depth < min_depth)
+        })
+        .map(|((lhs, &lhs_depth), (rhs, &rhs_depth))| {
+            let ratio = (min_depth - lhs_depth) / (rhs_depth - lhs_depth);
+            lhs + (rhs - lhs) * ratio
+        });
+
+    front.chain(crossings).collect()
+}
+
+fn to_isometry(pose: &Pose) -> na::Isometry3<f64> {
+    let Pose {
+        position: Point { x, y, z },
+        orientation:
+            Quaternion {
+                x: qx,
+                y: qy,
+                z: qz,
+                w: qw,
+            },
+    } = *pose;
+    let quaternion = na::Quaternion::new(qw, qx, qy, qz);
+
+    // A zero quaternion is taken as no rotation.
+    let rotation = if quaternion.norm() > 0.0 {
+        na::UnitQuaternion::from_quaternion(quaternion)
+    } else {
+        na::UnitQuaternion::identity()
+    };
+    na::Isometry3::from_parts(na::Translation3::new(x, y, z), rotation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The corners of a cube with the center and the half size.
+    fn cube(center: [f32; 3], half: f32) -> Vec<na::Point3<f32>> {
+        let [x, y, z] = center;
+        let mut corners = vec![];
+        for dx in [-half, half] {
+            for dy in [-half, half] {
+                for dz in [-half, half] {
+                    corners.push(na::Point3::new(x + dx, y + dy, z + dz));
+                }
+            }
+        }
+        corners
+    }
+
+    #[test]
+    fn keep_shapes_in_front() {
+        let corners = cube([0.0, 0.0, 5.0], 1.0);
+        let clipped = clip_to_depth(&corners, &na::Isometry3::identity(), MIN_DEPTH);
+        assert_eq!(clipped, corners);
+    }
+
+    #[test]
+    fn drop_shapes_behind() {
+        let corners = cube([0.0, 0.0, -5.0], 1.0);
+        let clipped = clip_to_depth(&corners, &na::Isometry3::identity(), MIN_DEPTH);
+        assert!(clipped.is_empty());
+    }
+
+    #[test]
+    fn clip_shapes_across_the_near_plane() {
+        let corners = cube([0.0, 0.0, 0.0], 1.0);
+        let clipped = clip_to_depth(&corners, &na::Isometry3::identity(), 0.5);
+
+        assert!(clipped.iter().all(|point| point.z >= 0.5 - 1e-6));
+        let (min, max) = clipped.iter().skip(1).fold(
+            (clipped[0].coords, clipped[0].coords),
+            |(min, max), point| (min.inf(&point.coords), max.sup(&point.coords)),
+        );
+        assert!((min - na::Vector3::new(-1.0, -1.0, 0.5)).norm() < 1e-6);
+        assert!((max - na::Vector3::new(1.0, 1.0, 1.0)).norm() < 1e-6);
+    }
+
+    #[test]
+    fn clip_in_the_camera_frame() {
+        // The camera looks along the LiDAR x axis.
+        let camera_pose = na::Isometry3::from_parts(
+            na::Translation3::identity(),
+            na::UnitQuaternion::rotation_between(&na::Vector3::x(), &na::Vector3::z()).unwrap(),
+        );
+        let corners = cube([0.0, 0.0, 0.0], 1.0);
+        let clipped = clip_to_depth(&corners, &camera_pose, 0.5);
+
+        assert!(!clipped.is_empty());
+        assert!(clipped.iter().all(|point| point.x >= 0.5 - 1e-6));
+    }
+}
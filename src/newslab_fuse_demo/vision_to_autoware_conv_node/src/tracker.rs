@@ -0,0 +1,462 @@
+//! Tracks objects across frames in the SORT style. Objects are matched
+//! to tracks by bird's-eye IoU, and each track runs a constant velocity
+//! Kalman filter on the object center.
+
+use crate::{config::TrackerConfig, time_diff_secs};
+use nalgebra as na;
+use r2r::{
+    autoware_auto_perception_msgs::msg::{
+        DetectedObject, DetectedObjects, TrackedObject, TrackedObjectKinematics, TrackedObjects,
+    },
+    builtin_interfaces::msg::Time,
+    geometry_msgs::msg::{Point, Pose, PoseWithCovariance, Twist, TwistWithCovariance, Vector3},
+    unique_identifier_msgs::msg::UUID,
+};
+use uuid::Uuid;
+
+/// The position and the velocity.
+type State = na::SVector<f64, 6>;
+type Covariance = na::SMatrix<f64, 6, 6>;
+
+pub struct Tracker {
+    config: TrackerConfig,
+    tracks: Vec<Track>,
+    last_stamp: Option<Time>,
+}
+
+struct Track {
+    id: Uuid,
+    state: State,
+    covariance: Covariance,
+    /// The last matched detection.
+    object: DetectedObject,
+    /// The number of consecutive matched frames.
+    hits: usize,
+    /// The number of consecutive unmatched frames.
+    misses: usize,
+    confirmed: bool,
+}
+
+impl Tracker {
+    pub fn new(config: TrackerConfig) -> Self {
+        Self {
+            config,
+            tracks: vec![],
+            last_stamp: None,
+        }
+    }
+
+    /// Matches detections to tracks and returns the confirmed tracks
+    /// that are matched in this frame.
+    pub fn update(&mut self, objects: &DetectedObjects) -> TrackedObjects {
+        let DetectedObjects { header, objects } = objects;
+
+        let dt = match &self.last_stamp {
+            Some(last_stamp) => time_diff_secs(&header.stamp, last_stamp).max(0.0),
+            None => 0.0,
+        };
+        self.last_stamp = Some(header.stamp.clone());
+
+        self.tracks
+            .iter_mut()
+            .for_each(|track| track.predict(dt, &self.config));
+
+        // Match the pairs with the highest IoU first.
+        let mut pairs: Vec<_> = self
+            .tracks
+            .iter()
+            .enumerate()
+            .flat_map(|(track_idx, track)| {
+                let track_box = bev_box(&track.position(), &track.object);
+                objects.iter().enumerate().map(move |(obj_idx, object)| {
+                    let obj_box = bev_box(&object_position(object), object);
+                    (track_idx, obj_idx, iou(&track_box, &obj_box))
+                })
+            })
+            .filter(|(_, _, iou)| *iou >= self.config.iou_threshold)
+            .collect();
+        pairs.sort_unstable_by(|(_, _, lhs), (_, _, rhs)| rhs.total_cmp(lhs));
+
+        let mut track_matched = vec![false; self.tracks.len()];
+        let mut obj_matched = vec![false; objects.len()];
+
+        for (track_idx, obj_idx, _) in pairs {
+            if track_matched[track_idx] || obj_matched[obj_idx] {
+                continue;
+            }
+            track_matched[track_idx] = true;
+            obj_matched[obj_idx] = true;
+            self.tracks[track_idx].correct(&objects[obj_idx], &self.config);
+        }
+
+        // Remove tracks that are lost for too long.
+        let max_misses = self.config.max_misses.get();
+        let mut matched = track_matched.into_iter();
+        self.tracks.retain_mut(|track| {
+            if !matched.next().unwrap() {
+                track.hits = 0;
+                track.misses += 1;
+            }
+            track.misses < max_misses
+        });
+
+        // Start tracks from unmatched detections.
+        let new_tracks: Vec<_> = objects
+            .iter()
+            .zip(obj_matched)
+            .filter(|(_, matched)| !matched)
+            .map(|(object, _)| Track::new(object, &self.config))
+            .collect();
+        self.tracks.extend(new_tracks);
+
+        let tracked = self
+            .tracks
+            .iter()
+            .filter(|track| track.confirmed && track.misses == 0)
+            .map(Track::to_msg)
+            .collect();
+
+        TrackedObjects {
+            header: header.clone(),
+            objects: tracked,
+        }
+    }
+}
+
+impl Track {
+    fn new(object: &DetectedObject, config: &TrackerConfig) -> Self {
+        let position = object_position(object);
+        let state = State::new(position.x, position.y, position.z, 0.0, 0.0, 0.0);
+
+        let pos_var = config.measurement_noise.powi(2);
+        let vel_var = config.initial_velocity_noise.powi(2);
+        let covariance = Covariance::from_diagonal(&State::new(
+            pos_var, pos_var, pos_var, vel_var, vel_var, vel_var,
+        ));
+
+        let mut track = Self {
+            id: Uuid::new_v4(),
+            state,
+            covariance,
+            object: object.clone(),
+            hits: 1,
+            misses: 0,
+            confirmed: false,
+        };
+        track.confirmed = track.hits >= config.min_hits.get();
+        track
+    }
+
+    fn position(&self) -> na::Point3<f64> {
+        self.state.fixed_rows::<3>(0).into_owned().into()
+    }
+
+    fn velocity(&self) -> na::Vector3<f64> {
+        self.state.fixed_rows::<3>(3).into_owned()
+    }
+
+    fn predict(&mut self, dt: f64, config: &TrackerConfig) {
+        let mut transition = Covariance::identity();
+        let mut noise = Covariance::zeros();
+        let var = config.process_noise.powi(2);
+
+        for axis in 0..3 {
+            transition[(axis, axis + 3)] = dt;
+            noise[(axis, axis)] = dt.powi(4) / 4.0 * var;
+            noise[(axis, axis + 3)] = dt.powi(3) / 2.0 * var;
+            noise[(axis + 3, axis)] = dt.powi(3) / 2.0 * var;
+            noise[(axis + 3, axis + 3)] = dt.powi(2) * var;
+        }
+
+        self.state = transition * self.state;
+        self.covariance = transition * self.covariance * transition.transpose() + noise;
+    }
+
+    fn correct(&mut self, object: &DetectedObject, config: &TrackerConfig) {
+        let observation = na::SMatrix::<f64, 3, 6>::identity();
+        let noise = na::Matrix3::from_diagonal_element(config.measurement_noise.powi(2));
+
+        let residual = object_position(object).coords - observation * self.state;
+        let innovation = observation * self.covariance * observation.transpose() + noise;
+
+        if let Some(inv_innovation) = innovation.try_inverse() {
+            let gain = self.covariance * observation.transpose() * inv_innovation;
+            self.state += gain * residual;
+            self.covariance = (Covariance::identity() - gain * observation) * self.covariance;
+        }
+
+        self.object = object.clone();
+        self.hits += 1;
+        self.misses = 0;
+        if self.hits >= config.min_hits.get() {
+            self.confirmed = true;
+        }
+    }
+
+    /// The twist is expressed in the parent frame, which is the object
+    /// frame as well since detected objects are not rotated.
+    fn to_msg(&self) -> TrackedObject {
+        let DetectedObject {
+            existence_probability,
+            ref classification,
+            ref kinematics,
+            ref shape,
+        } = self.object;
+        let position = self.position();
+        let velocity = self.velocity();
+
+        TrackedObject {
+            object_id: UUID {
+                uuid: self.id.as_bytes().to_vec(),
+            },
+            existence_probability,
+            classification: classification.clone(),
+            kinematics: TrackedObjectKinematics {
+                pose_with_covariance: PoseWithCovariance {
+                    pose: Pose {
+                        position: Point {
+                            x: position.x,
+                            y: position.y,
+                            z: position.z,
+                        },
+                        orientation: kinematics.pose_with_covariance.pose.orientation.clone(),
+                    },
+                    covariance: covariance_6x6(&self.covariance.fixed_slice::<3, 3>(0, 0).into()),
+                },
+                twist_with_covariance: TwistWithCovariance {
+                    twist: Twist {
+                        linear: Vector3 {
+                            x: velocity.x,
+                            y: velocity.y,
+                            z: velocity.z,
+                        },
+                        angular: Vector3::default(),
+                    },
+                    covariance: covariance_6x6(&self.covariance.fixed_slice::<3, 3>(3, 3).into()),
+                },
+                acceleration_with_covariance: Default::default(),
+                orientation_availability: kinematics.orientation_availability,
+                is_stationary: false,
+            },
+            shape: shape.clone(),
+        }
+    }
+}
+
+/// An axis-aligned box on the x-y plane in `[min_x, min_y, max_x,
+/// max_y]`.
+fn bev_box(center: &na::Point3<f64>, object: &DetectedObject) -> [f64; 4] {
+    let Vector3 { x: sx, y: sy, .. } = object.shape.dimensions;
+    [
+        center.x - sx / 2.0,
+        center.y - sy / 2.0,
+        center.x + sx / 2.0,
+        center.y + sy / 2.0,
+    ]
+}
+
+fn iou(lhs: &[f64; 4], rhs: &[f64; 4]) -> f64 {
+    let inter_w = (lhs[2].min(rhs[2]) - lhs[0].max(rhs[0])).max(0.0);
+    let inter_h = (lhs[3].min(rhs[3]) - lhs[1].max(rhs[1])).max(0.0);
+    let inter = inter_w * inter_h;
+    let area = |b: &[f64; 4]| (b[2] - b[0]) * (b[3] - b[1]);
+    let union = area(lhs) + area(rhs) - inter;
+
+    if union > 0.0 {
+        inter / union
+    } else {
+        0.0
+    }
+}
+
+fn object_position(object: &DetectedObject) -> na::Point3<f64> {
+    let Point { x, y, z } = object.kinematics.pose_with_covariance.pose.position;
+    na::Point3::new(x, y, z)
+}
+
+/// Places a 3x3 covariance in the top-left block of a row-major 6x6
+/// covariance.
+fn covariance_6x6(block: &na::Matrix3<f64>) -> Vec<f64> {
+    let mut output = na::Matrix6::zeros();
+    output.fixed_slice_mut::<3, 3>(0, 0).copy_from(block);
+    output.transpose().iter().copied().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use r2r::{
+        autoware_auto_perception_msgs::msg::{DetectedObjectKinematics, Shape},
+        std_msgs::msg::Header,
+    };
+
+    /// The frame interval in milliseconds.
+    const INTERVAL_MILLIS: u32 = 100;
+
+    /// Detects 2x2x1 boxes at the centers on the x-y plane.
+    fn objects(frame: u32, centers: &[[f64; 2]]) -> DetectedObjects {
+        let millis = frame * INTERVAL_MILLIS;
+        let objects = centers
+            .iter()
+            .map(|&[x, y]| DetectedObject {
+                existence_probability: 1.0,
+                kinematics: DetectedObjectKinematics {
+                    pose_with_covariance: PoseWithCovariance {
+                        pose: Pose {
+                            position: Point { x, y, z: 0.0 },
+                            ..Default::default()
+                        },
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+                shape: Shape {
+                    dimensions: Vector3 {
+                        x: 2.0,
+                        y: 2.0,
+                        z: 1.0,
+                    },
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .collect();
+
+        DetectedObjects {
+            header: Header {
+                stamp: Time {
+                    sec: (millis / 1000) as i32,
+                    nanosec: millis % 1000 * 1_000_000,
+                },
+                frame_id: "lidar".into(),
+            },
+            objects,
+        }
+    }
+
+    /// The position of an object after the frames at the velocity.
+    fn moved(start: [f64; 2], velocity: [f64; 2], frame: u32) -> [f64; 2] {
+        let secs = (frame * INTERVAL_MILLIS) as f64 / 1000.0;
+        [start[0] + velocity[0] * secs, start[1] + velocity[1] * secs]
+    }
+
+    fn ids(tracked: &TrackedObjects) -> Vec<Vec<u8>> {
+        tracked
+            .objects
+            .iter()
+            .map(|object| object.object_id.uuid.clone())
+            .collect()
+    }
+
+    #[test]
+    fn publish_tracks_after_min_hits() {
+        let config = TrackerConfig::default();
+        let min_hits = config.min_hits.get() as u32;
+        let mut tracker = Tracker::new(config);
+
+        for frame in 0..min_hits {
+            let tracked = tracker.update(&objects(frame, &[[10.0, 0.0]]));
+            let expect = if frame + 1 < min_hits { 0 } else { 1 };
+            assert_eq!(tracked.objects.len(), expect, "frame {}", frame);
+        }
+    }
+
+    #[test]
+    fn track_moving_objects() {
+        let mut tracker = Tracker::new(TrackerConfig::default());
+        let starts = [[10.0, 5.0], [10.0, -5.0]];
+        let velocities = [[2.0, 0.0], [-1.0, 0.5]];
+
+        let mut first_ids = None;
+        let mut last = None;
+        for frame in 0..30 {
+            let centers: Vec<_> = starts
+                .iter()
+                .zip(&velocities)
+                .map(|(&start, &velocity)| moved(start, velocity, frame))
+                .collect();
+            let tracked = tracker.update(&objects(frame, &centers));
+
+            if !tracked.objects.is_empty() {
+                // The tracks are kept in the order they are started.
+                let ids = ids(&tracked);
+                assert_eq!(ids.len(), 2);
+                assert_ne!(ids[0], ids[1]);
+                assert_eq!(*first_ids.get_or_insert_with(|| ids.clone()), ids);
+                last = Some((centers, tracked));
+            }
+        }
+
+        let (centers, tracked) = last.unwrap();
+        for ((object, center), velocity) in tracked.objects.iter().zip(&centers).zip(&velocities) {
+            let kinematics = &object.kinematics;
+            let Point { x, y, .. } = kinematics.pose_with_covariance.pose.position;
+            assert!((x - center[0]).abs() < 0.1 && (y - center[1]).abs() < 0.1);
+
+            let Vector3 {
+                x: vx,
+                y: vy,
+                z: vz,
+            } = kinematics.twist_with_covariance.twist.linear;
+            assert!((vx - velocity[0]).abs() < 0.2, "{} != {}", vx, velocity[0]);
+            assert!((vy - velocity[1]).abs() < 0.2, "{} != {}", vy, velocity[1]);
+            assert!(vz.abs() < 0.2);
+
+            let Vector3 { x, y, z } = object.shape.dimensions;
+            assert_eq!([x, y, z], [2.0, 2.0, 1.0]);
+        }
+    }
+
+    #[test]
+    fn keep_tracks_within_max_misses() {
+        let config = TrackerConfig::default();
+        let max_misses = config.max_misses.get() as u32;
+        let mut tracker = Tracker::new(config);
+
+        let mut frame = 0;
+        let mut expect = None;
+        for _ in 0..5 {
+            let tracked = tracker.update(&objects(frame, &[[10.0, 0.0]]));
+            expect = ids(&tracked).pop().or(expect);
+            frame += 1;
+        }
+        for _ in 0..max_misses - 1 {
+            assert!(tracker.update(&objects(frame, &[])).objects.is_empty());
+            frame += 1;
+        }
+        let tracked = tracker.update(&objects(frame, &[[10.0, 0.0]]));
+
+        assert_eq!(ids(&tracked), vec![expect.unwrap()]);
+    }
+
+    #[test]
+    fn remove_tracks_after_max_misses() {
+        let config = TrackerConfig::default();
+        let max_misses = config.max_misses.get() as u32;
+        let min_hits = config.min_hits.get() as u32;
+        let mut tracker = Tracker::new(config);
+
+        let mut frame = 0;
+        let mut old_id = None;
+        for _ in 0..5 {
+            let tracked = tracker.update(&objects(frame, &[[10.0, 0.0]]));
+            old_id = ids(&tracked).pop().or(old_id);
+            frame += 1;
+        }
+        for _ in 0..max_misses {
+            tracker.update(&objects(frame, &[]));
+            frame += 1;
+        }
+
+        // The object starts a new track that must be confirmed again.
+        let mut new_id = None;
+        for hit in 0..min_hits {
+            let tracked = tracker.update(&objects(frame, &[[10.0, 0.0]]));
+            assert_eq!(tracked.objects.is_empty(), hit + 1 < min_hits);
+            new_id = ids(&tracked).pop();
+            frame += 1;
+        }
+        assert!(old_id.is_some() && new_id.is_some());
+        assert_ne!(old_id, new_id);
+    }
+}